@@ -4,6 +4,7 @@ use ibdl_common::post::{
     extension::Extension,
     rating::Rating,
     tags::{Tag, TagType},
+    variant::FileVariant,
     Post,
 };
 use rand::{
@@ -195,6 +196,11 @@ fn seed_data(num: u64) -> (Vec<Post>, AHashSet<String>) {
             extension: Extension::guess_format(&ext),
             rating,
             tags,
+            frame_delays: None,
+            variant: FileVariant::Original,
+            original_extension: None,
+            pool_post_id: None,
+            server: None,
         };
 
         v2.push(pst)
@@ -1,6 +1,7 @@
 #![deny(clippy::nursery)]
 use std::{
     env,
+    fmt::Display,
     fs::create_dir_all,
     io,
     path::{Path, PathBuf},
@@ -38,14 +39,14 @@ pub enum ImageBoards {
     Gelbooru,
 }
 
-impl ToString for ImageBoards {
-    fn to_string(&self) -> String {
+impl Display for ImageBoards {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Danbooru => String::from("Danbooru"),
-            Self::E621 => String::from("e621"),
-            Self::GelbooruV0_2 => String::from("Gelbooru Beta V0.2.0"),
-            Self::Moebooru => String::from("Moebooru"),
-            Self::Gelbooru => String::from("Gelbooru"),
+            Self::Danbooru => write!(f, "Danbooru"),
+            Self::E621 => write!(f, "e621"),
+            Self::GelbooruV0_2 => write!(f, "Gelbooru Beta V0.2.0"),
+            Self::Moebooru => write!(f, "Moebooru"),
+            Self::Gelbooru => write!(f, "Gelbooru"),
         }
     }
 }
@@ -75,7 +76,7 @@ impl ImageBoards {
     /// Or you can set the env var `IBDL_CACHE_DIR` to point it to a custom location.
     #[inline]
     pub fn auth_cache_dir() -> Result<PathBuf, io::Error> {
-        let cfg_path = env::var("IBDL_CACHE_DIR").unwrap_or({
+        let cfg_path = env::var("IBDL_CACHE_DIR").unwrap_or_else(|_| {
             let cdir = ProjectDirs::from("com", "FerrahWolfeh", "imageboard-downloader").unwrap();
             cdir.config_dir().to_string_lossy().to_string()
         });
@@ -29,7 +29,10 @@ macro_rules! join_tags {
 #[macro_export]
 macro_rules! extract_ext_from_url {
     ($x:expr) => {{
-        let ext = $x.split('.').next_back().unwrap();
+        // Ignore query strings and fragments appended by CDNs
+        let path = $x.split(['?', '#']).next().unwrap();
+        let file = path.rsplit('/').next().unwrap();
+        let ext = file.rsplit_once('.').map_or("", |(_, ext)| ext);
         ext.to_string()
     }};
 }
@@ -43,4 +43,7 @@ pub enum PostError {
 
     #[error("Post has an unknown extension: {message}")]
     UnknownExtension { message: String },
+
+    #[error("Failed to convert Ugoira frames: {message}")]
+    UgoiraConversionFail { message: String },
 }
@@ -1,10 +1,10 @@
-use std::str::FromStr;
+use std::{fmt::Display, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
 use super::error::PostError;
 
-/// Enum representing the 8 possible extensions a downloaded post can have.
+/// Enum representing the possible extensions a downloaded post can have.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Extension {
@@ -22,9 +22,25 @@ pub enum Extension {
     Ugoira,
     /// Used for any file whose extension is unknown or not currently supported by this library.
     Unknown,
+    /// Legacy Flash animations. Declared after `Unknown` to keep the variant indexes of older caches.
+    SWF,
 }
 
 impl Extension {
+    /// Every format [`sniff`](Self::sniff) can recognize.
+    pub const SNIFFABLE: [Self; 10] = [
+        Self::JPG,
+        Self::PNG,
+        Self::GIF,
+        Self::WEBP,
+        Self::JXL,
+        Self::WEBM,
+        Self::Ugoira,
+        Self::SWF,
+        Self::AVIF,
+        Self::MP4,
+    ];
+
     /// Naive and simple way of recognizing the extension of a post to use in [`Post`](crate::post::Post). This function never fails.
     pub fn guess_format(s: &str) -> Self {
         let uu = Self::from_str(s);
@@ -35,7 +51,51 @@ impl Extension {
     }
 
     pub const fn is_video(&self) -> bool {
-        matches!(self, Self::GIF | Self::WEBM | Self::MP4 | Self::Ugoira)
+        matches!(
+            self,
+            Self::GIF | Self::WEBM | Self::MP4 | Self::Ugoira | Self::SWF
+        )
+    }
+
+    /// Recognizes the real format of a file from it's first bytes (magic numbers).
+    ///
+    /// Matroska files are reported as [`Extension::WEBM`]. Returns `None` if the format is not recognized.
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        match header {
+            [0xFF, 0xD8, 0xFF, ..] => Some(Self::JPG),
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(Self::PNG),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(Self::GIF),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(Self::WEBP),
+            [0xFF, 0x0A, ..]
+            | [0, 0, 0, 0x0C, b'J', b'X', b'L', b' ', 0x0D, 0x0A, 0x87, 0x0A, ..] => {
+                Some(Self::JXL)
+            }
+            [0x1A, 0x45, 0xDF, 0xA3, ..] => Some(Self::WEBM),
+            [b'P', b'K', 0x03, 0x04, ..] => Some(Self::Ugoira),
+            [b'F' | b'C' | b'Z', b'W', b'S', ..] => Some(Self::SWF),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some(Self::sniff_ftyp(header)),
+            _ => None,
+        }
+    }
+
+    /// Tells AVIF images apart from MP4 videos, since both use the ISO base media `ftyp` box.
+    fn sniff_ftyp(header: &[u8]) -> Self {
+        let box_size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let ftyp = &header[..box_size.clamp(8, header.len())];
+
+        // Major brand followed by the minor version and the compatible brands
+        let brands = ftyp
+            .get(8..12)
+            .into_iter()
+            .chain(ftyp.get(16..).unwrap_or_default().chunks_exact(4));
+
+        for brand in brands {
+            if brand == b"avif" || brand == b"avis" {
+                return Self::AVIF;
+            }
+        }
+
+        Self::MP4
     }
 }
 
@@ -53,6 +113,7 @@ impl FromStr for Extension {
             "zip" => Ok(Self::Ugoira),
             "jxl" => Ok(Self::JXL),
             "avif" => Ok(Self::AVIF),
+            "swf" => Ok(Self::SWF),
             _ => Err(PostError::UnknownExtension {
                 message: s.to_string(),
             }),
@@ -60,19 +121,20 @@ impl FromStr for Extension {
     }
 }
 
-impl ToString for Extension {
-    fn to_string(&self) -> String {
+impl Display for Extension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::JPG => String::from("jpg"),
-            Self::PNG => String::from("png"),
-            Self::WEBP => String::from("webp"),
-            Self::GIF => String::from("gif"),
-            Self::WEBM => String::from("webm"),
-            Self::MP4 => String::from("mp4"),
-            Self::Ugoira => String::from("zip"),
-            Self::Unknown => String::from("bin"),
-            Self::AVIF => String::from("avif"),
-            Self::JXL => String::from("jxl"),
+            Self::JPG => write!(f, "jpg"),
+            Self::PNG => write!(f, "png"),
+            Self::WEBP => write!(f, "webp"),
+            Self::GIF => write!(f, "gif"),
+            Self::WEBM => write!(f, "webm"),
+            Self::MP4 => write!(f, "mp4"),
+            Self::Ugoira => write!(f, "zip"),
+            Self::Unknown => write!(f, "bin"),
+            Self::AVIF => write!(f, "avif"),
+            Self::JXL => write!(f, "jxl"),
+            Self::SWF => write!(f, "swf"),
         }
     }
 }
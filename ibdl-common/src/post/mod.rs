@@ -7,16 +7,17 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use std::{cmp::Ordering, fmt::Debug, ops::Not};
+use std::{cmp::Ordering, fmt::Debug, num::NonZeroU64, ops::Not};
 
 use crate::ImageBoards;
 
-use self::{extension::Extension, rating::Rating, tags::Tag};
+use self::{extension::Extension, rating::Rating, tags::Tag, variant::FileVariant};
 
 pub mod error;
 pub mod extension;
 pub mod rating;
 pub mod tags;
+pub mod variant;
 
 /// Special enum to simplify the selection of the output file name when downloading a [`Post`]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -76,7 +77,8 @@ pub struct Post {
     pub extension: Extension,
     /// Rating of the post. Can be:
     ///
-    /// * `Rating::Safe` for SFW posts
+    /// * `Rating::Safe` or `Rating::General` for SFW posts
+    /// * `Rating::Sensitive` for mildly suggestive posts
     /// * `Rating::Questionable` for a not necessarily SFW post
     /// * `Rating::Explicit` for NSFW posts
     /// * `Rating::Unknown` in case none of the above are correctly parsed
@@ -85,6 +87,30 @@ pub struct Post {
     ///
     /// Used to exclude posts according to a blacklist
     pub tags: Vec<Tag>,
+    /// Delay of each frame in milliseconds for Pixiv Ugoira posts, when provided by the imageboard.
+    ///
+    /// Used to convert the downloaded zip of frames into an animated image.
+    #[serde(default)]
+    pub frame_delays: Option<Vec<u32>>,
+    /// Which version of the file `url` points to.
+    #[serde(default)]
+    pub variant: FileVariant,
+    /// Extension of the original file, when `url` points to a sample or preview instead.
+    ///
+    /// Used to filter posts by the format of the original file, whatever variant is downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_extension: Option<Extension>,
+    /// ID of the post in the imageboard when it was downloaded from a pool, since `id` is replaced
+    /// by it's page number.
+    ///
+    /// Used to keep the page numbers stable when new posts are added to the pool.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_post_id: Option<NonZeroU64>,
+    /// Name of the server the post was found in, when a search goes through more than one server.
+    ///
+    /// Ids of different servers can be the same, so this keeps track of where each post came from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
 }
 
 impl Debug for Post {
@@ -97,6 +123,11 @@ impl Debug for Post {
             .field("File Extension", &self.extension)
             .field("Rating", &self.rating)
             .field("Tag List", &self.tags)
+            .field("Frame Delays", &self.frame_delays)
+            .field("Variant", &self.variant)
+            .field("Original Extension", &self.original_extension)
+            .field("Pool Post ID", &self.pool_post_id)
+            .field("Server", &self.server)
             .finish()
     }
 }
@@ -123,25 +154,64 @@ impl Post {
     /// Get the final file name of the post for saving.
     #[inline]
     pub fn file_name(&self, name_type: NameType) -> String {
+        format!("{}.{}", self.name(name_type), self.extension)
+    }
+
+    /// Get the generic name of the post. Can be it's MD5 hash or ID
+    ///
+    /// Samples and previews get the variant as a suffix, like `1234_sample`, so they are never
+    /// mistaken for the original file.
+    #[inline]
+    pub fn name(&self, name_type: NameType) -> String {
         let name = match name_type {
             NameType::ID => self.id.to_string(),
             NameType::MD5 => self.md5.to_string(),
         };
 
-        format!("{}.{}", name, self.extension.to_string())
+        format!("{}{}", name, self.variant_suffix())
     }
 
-    /// Get the generic name of the post. Can be it's MD5 hash or ID
+    /// Extension of the original file of the post, even if a sample or preview is downloaded.
     #[inline]
-    pub fn name(&self, name_type: NameType) -> String {
-        match name_type {
-            NameType::ID => self.id.to_string(),
-            NameType::MD5 => self.md5.to_string(),
+    pub fn source_extension(&self) -> Extension {
+        self.original_extension.unwrap_or(self.extension)
+    }
+
+    fn variant_suffix(&self) -> String {
+        if self.variant.is_original() {
+            String::new()
+        } else {
+            format!("_{}", self.variant)
+        }
+    }
+
+    /// Points the post to another version of it's file, guessing the new extension from the url.
+    ///
+    /// Does nothing if `url` is `None`, so the original file is downloaded instead.
+    pub fn set_variant(&mut self, variant: FileVariant, url: Option<String>) {
+        let Some(url) = url else {
+            return;
+        };
+
+        if url == self.url {
+            return;
         }
+
+        let ext = crate::extract_ext_from_url!(url);
+
+        self.original_extension = Some(self.extension);
+        self.extension = Extension::guess_format(&ext);
+        self.url = url;
+        self.variant = variant;
     }
 
     #[inline]
     pub fn seq_file_name(&self, num_digits: usize) -> String {
-        format!("{:0num_digits$}.{}", self.id, self.extension.to_string())
+        format!(
+            "{:0num_digits$}{}.{}",
+            self.id,
+            self.variant_suffix(),
+            self.extension
+        )
     }
 }
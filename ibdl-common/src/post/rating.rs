@@ -7,8 +7,15 @@
 //! * `Questionable` or `Sensitive`: Posts that involve nude/semi-nude characters or other suggestive art that *might* not be safe for viewing close to other people or at work.
 //! * `Explicit`: Posts that are explicity pornographic or have other sensitive content such as gore, etc.
 //!
+//! Since the same rating tag can mean different things depending on the imageboard (on Danbooru, `s` means *sensitive*,
+//! while on e621 it means *safe*), the tags are parsed through a per-board mapping table with [`Rating::from_board_str`].
 use serde::{Deserialize, Serialize};
+use std::fmt::Display;
 
+use crate::ImageBoards;
+
+/// The `General` and `Sensitive` variants are declared last so the variant indexes of
+/// older `bincode` caches keep decoding to the same ratings.
 #[derive(
     Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
 )]
@@ -22,27 +29,107 @@ pub enum Rating {
     /// Represents a failure to parse the `rating` tag into one of the above.
     #[default]
     Unknown,
+    /// Danbooru and Gelbooru's `general` rating. Equivalent to `Safe` on other imageboards.
+    General,
+    /// Danbooru and Gelbooru's `sensitive` rating. Posts that are mildly suggestive, close to `Questionable`.
+    Sensitive,
 }
 
-impl ToString for Rating {
-    fn to_string(&self) -> String {
+/// Mapping table used by imageboards where `s` means *safe*.
+const DEFAULT_RATING_TABLE: &[(&str, Rating)] = &[
+    ("s", Rating::Safe),
+    ("safe", Rating::Safe),
+    ("g", Rating::General),
+    ("general", Rating::General),
+    ("sensitive", Rating::Sensitive),
+    ("q", Rating::Questionable),
+    ("questionable", Rating::Questionable),
+    ("e", Rating::Explicit),
+    ("explicit", Rating::Explicit),
+];
+
+/// Mapping table for Danbooru, where `s` means *sensitive*.
+const DANBOORU_RATING_TABLE: &[(&str, Rating)] = &[
+    ("g", Rating::General),
+    ("general", Rating::General),
+    ("s", Rating::Sensitive),
+    ("sensitive", Rating::Sensitive),
+    ("q", Rating::Questionable),
+    ("questionable", Rating::Questionable),
+    ("e", Rating::Explicit),
+    ("explicit", Rating::Explicit),
+];
+
+impl Display for Rating {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Safe => String::from("Safe"),
-            Self::Questionable => String::from("Questionable"),
-            Self::Explicit => String::from("Explicit"),
-            Self::Unknown => String::from("Unknown"),
+            Self::Safe => write!(f, "Safe"),
+            Self::General => write!(f, "General"),
+            Self::Sensitive => write!(f, "Sensitive"),
+            Self::Questionable => write!(f, "Questionable"),
+            Self::Explicit => write!(f, "Explicit"),
+            Self::Unknown => write!(f, "Unknown"),
         }
     }
 }
 
 impl Rating {
     /// Guess the variant according to the rating tag present in the post
+    ///
+    /// Uses the default mapping table, where `s` means `Safe`. Prefer [`Rating::from_board_str`] when the imageboard is known.
     pub fn from_rating_str(s: &str) -> Self {
-        match s {
-            "s" | "g" | "safe" | "sensitive" | "general" => Self::Safe,
-            "q" | "questionable" => Self::Questionable,
-            "e" | "explicit" => Self::Explicit,
-            _ => Self::Unknown,
+        Self::lookup(DEFAULT_RATING_TABLE, s)
+    }
+
+    /// Parses the rating tag using the mapping table of the selected imageboard.
+    pub fn from_board_str(s: &str, imageboard: ImageBoards) -> Self {
+        Self::lookup(Self::rating_table(imageboard), s)
+    }
+
+    /// Returns the mapping table between rating tags and variants used by the imageboard.
+    pub const fn rating_table(imageboard: ImageBoards) -> &'static [(&'static str, Self)] {
+        match imageboard {
+            ImageBoards::Danbooru => DANBOORU_RATING_TABLE,
+            _ => DEFAULT_RATING_TABLE,
         }
     }
+
+    /// Returns all ratings the imageboard can classify it's posts with, ordered from the safest to the most explicit. Always ends with `Unknown`.
+    pub fn board_ratings(imageboard: ImageBoards) -> Vec<Self> {
+        let mut ratings = match imageboard {
+            ImageBoards::Danbooru => vec![
+                Self::General,
+                Self::Sensitive,
+                Self::Questionable,
+                Self::Explicit,
+            ],
+            // Gelbooru uses general/sensitive while Rule34 still uses safe
+            ImageBoards::Gelbooru => vec![
+                Self::Safe,
+                Self::General,
+                Self::Sensitive,
+                Self::Questionable,
+                Self::Explicit,
+            ],
+            ImageBoards::E621 | ImageBoards::Moebooru | ImageBoards::GelbooruV0_2 => {
+                vec![Self::Safe, Self::Questionable, Self::Explicit]
+            }
+        };
+        ratings.push(Self::Unknown);
+        ratings
+    }
+
+    /// Whether the rating is considered safe for work on any imageboard.
+    pub const fn is_safe(&self) -> bool {
+        matches!(self, Self::Safe | Self::General)
+    }
+
+    #[inline]
+    fn lookup(table: &[(&str, Self)], s: &str) -> Self {
+        let tag = s.to_lowercase();
+        table
+            .iter()
+            .find(|(name, _)| *name == tag)
+            .map_or(Self::Unknown, |(_, rating)| *rating)
+    }
 }
@@ -0,0 +1,51 @@
+//! Selection of which version of a post's file will be downloaded
+//! # File Variants
+//! Besides the original file, most imageboards also host smaller versions of each post:
+//! * `Sample`: A resized (and usually recompressed) version of the file, meant to be displayed in the post page.
+//! * `Preview`: A small thumbnail, used in the post list.
+//!
+//! Since these files don't match the MD5 hash of the post, downloads of anything other than
+//! [`FileVariant::Original`] can't have their integrity checked.
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FileVariant {
+    /// The original file uploaded to the imageboard.
+    #[default]
+    Original,
+    /// A resized version of the original file.
+    Sample,
+    /// A small thumbnail of the original file.
+    Preview,
+}
+
+impl FileVariant {
+    /// Whether the downloaded file should match the MD5 hash of the post.
+    #[inline]
+    pub const fn is_original(&self) -> bool {
+        matches!(self, Self::Original)
+    }
+
+    /// Picks the url of this variant, returning `None` if it's missing or the selected variant is `Original`.
+    #[inline]
+    pub fn pick(&self, sample: Option<String>, preview: Option<String>) -> Option<String> {
+        let url = match self {
+            Self::Original => None,
+            Self::Sample => sample,
+            Self::Preview => preview,
+        };
+
+        url.filter(|u| !u.is_empty())
+    }
+}
+
+impl Display for FileVariant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Original => write!(f, "original"),
+            Self::Sample => write!(f, "sample"),
+            Self::Preview => write!(f, "preview"),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Feedback sent from the queue back to the extractor, so a search stops scanning once it only
+//! finds posts that were already downloaded, or once it downloaded enough new posts.
+use std::{
+    num::NonZeroU32,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use ibdl_common::tokio::sync::watch;
+use ibdl_extractors::imageboards::AbortSignal;
+use owo_colors::OwoColorize;
+
+use super::get_counters;
+
+/// Counts how many posts in a row were already downloaded and tells the extractor to stop once
+/// the count reaches the limit.
+#[derive(Debug)]
+pub struct AbortOnExisting {
+    limit: u32,
+    streak: AtomicU32,
+    signal: watch::Sender<bool>,
+}
+
+impl AbortOnExisting {
+    /// Returns the tracker for the [`Queue`](super::Queue) and the signal for the extractor.
+    pub fn new(limit: NonZeroU32) -> (Self, AbortSignal) {
+        let (signal, receiver) = watch::channel(false);
+
+        let tracker = Self {
+            limit: limit.get(),
+            streak: AtomicU32::new(0),
+            signal,
+        };
+
+        (tracker, receiver)
+    }
+
+    /// Records whether a post was skipped because it was already downloaded. Any new post resets
+    /// the count.
+    pub(crate) fn record(&self, existing: bool) {
+        if !existing {
+            self.streak.store(0, Ordering::Relaxed);
+            return;
+        }
+
+        let streak = self.streak.fetch_add(1, Ordering::Relaxed) + 1;
+
+        // Only report it once, the extractor might still send a few posts before it stops
+        if streak >= self.limit && !self.signal.send_replace(true) {
+            let _ = get_counters().multi.println(format!(
+                "{} {} {}",
+                "Found".bold().yellow(),
+                streak.bold().blue(),
+                "posts in a row that were already downloaded. Stopping the search."
+                    .bold()
+                    .yellow()
+            ));
+        }
+    }
+}
+
+/// Limits the number of posts the queue downloads, without counting the ones that were already
+/// downloaded, and tells the extractor to stop once the limit is reached.
+#[derive(Debug)]
+pub struct NewPostLimit {
+    limit: u32,
+    taken: AtomicU32,
+    signal: watch::Sender<bool>,
+}
+
+impl NewPostLimit {
+    /// Returns the limit for the [`Queue`](super::Queue) and the signal for the extractor.
+    pub fn new(limit: u32) -> (Self, AbortSignal) {
+        let (signal, receiver) = watch::channel(false);
+
+        let tracker = Self {
+            limit,
+            taken: AtomicU32::new(0),
+            signal,
+        };
+
+        (tracker, receiver)
+    }
+
+    /// Takes a slot for a new post, returning `false` if the limit was already reached.
+    pub(crate) fn take(&self) -> bool {
+        let taken = self.taken.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if taken >= self.limit {
+            self.signal.send_replace(true);
+        }
+
+        taken <= self.limit
+    }
+
+    /// Whether the queue downloaded as many new posts as it could, so older posts may be left.
+    pub fn reached(&self) -> bool {
+        self.taken.load(Ordering::Relaxed) >= self.limit
+    }
+}
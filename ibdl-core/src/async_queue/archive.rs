@@ -0,0 +1,72 @@
+//! Archive formats downloaded posts can be saved into, instead of a plain folder.
+use std::path::Path;
+
+use clap::ValueEnum;
+use ibdl_common::{
+    post::Post,
+    serde::{self, Deserialize, Serialize},
+};
+
+use crate::error::QueueError;
+
+use super::spool::EntryData;
+
+/// Output path that makes streamable archives be written to the standard output.
+pub const STDOUT_PATH: &str = "-";
+
+/// Format of the single file all downloaded posts are saved into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(crate = "self::serde", rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    /// Zip file with no compression, read by most comic readers
+    Cbz,
+    /// Uncompressed tar file
+    Tar,
+    /// Tar file compressed with zstd
+    #[value(name = "tar.zst")]
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    /// EPUB 3 book with one page per image, for e-readers (pools only)
+    Epub,
+}
+
+impl ArchiveFormat {
+    /// File extension of the archive, without the leading dot.
+    #[inline]
+    pub const fn extension(&self) -> &'static str {
+        match self {
+            Self::Cbz => "cbz",
+            Self::Tar => "tar",
+            Self::TarZst => "tar.zst",
+            Self::Epub => "epub",
+        }
+    }
+
+    /// Whether the archive can be written without seeking, so it can be sent to the standard output.
+    #[inline]
+    pub const fn is_streamable(&self) -> bool {
+        matches!(self, Self::Tar | Self::TarZst)
+    }
+
+    /// Whether `path` means the standard output instead of a file.
+    #[inline]
+    pub fn is_stdout(path: &Path) -> bool {
+        path == Path::new(STDOUT_PATH)
+    }
+}
+
+/// Archive that downloaded posts are written into, one at a time.
+pub trait PostArchive {
+    /// Whether the post was already saved in the archive.
+    fn contains(&self, post: &Post) -> bool;
+
+    /// First pool page starting from `page` that isn't inside the archive yet.
+    fn next_missing_page(&self, page: u64) -> u64;
+
+    /// Writes the files of a downloaded post.
+    fn write_post(&mut self, post: Post, entries: &[(String, EntryData)])
+        -> Result<(), QueueError>;
+
+    /// Writes the metadata entries and closes the archive.
+    fn finish(&mut self) -> Result<(), QueueError>;
+}
@@ -1,38 +1,55 @@
-use std::{fs::File, io::Write, path::PathBuf, sync::Arc, sync::Mutex};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    sync::Mutex,
+};
 
-use futures::StreamExt;
+use futures::{future::ready, FutureExt, StreamExt};
 use ibdl_common::{
     log::debug,
-    post::{error::PostError, rating::Rating, NameType, Post},
+    post::{error::PostError, NameType, Post},
     reqwest::Client,
     tokio::{
-        io::AsyncWriteExt,
         sync::mpsc::Sender,
         task::{self, spawn_blocking},
     },
-    ImageBoards,
 };
 use owo_colors::OwoColorize;
+use tempfile::TempPath;
 use tokio_stream::wrappers::UnboundedReceiverStream;
-use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
 use crate::{async_queue::get_counters, error::QueueError};
 
-use super::Queue;
+use super::{
+    archive::{ArchiveFormat, PostArchive},
+    epub::EpubArchive,
+    spool::{spool_response, EntryData},
+    stats::PostOutcome,
+    summary::CbzSummary,
+    tarball::TarArchive,
+    volume::CbzArchive,
+    Queue, UgoiraConversion,
+};
+
+/// Settings shared by every cbz download task.
+#[derive(Debug, Clone)]
+pub struct CbzFetch {
+    client: Client,
+    name_type: NameType,
+    annotate: bool,
+    ugoira: Option<UgoiraConversion>,
+    /// Directory where downloads are spooled before being written into the cbz.
+    spool_dir: PathBuf,
+}
 
 impl Queue {
-    pub(crate) async fn fetch_cbz_pool(
-        client: Client,
-        variant: ImageBoards,
-        post: Post,
-        zip: Arc<Mutex<ZipWriter<File>>>,
-        num_digits: usize,
-    ) -> Result<(), PostError> {
+    /// Downloads a post into a temporary file, fixing it's extension if needed.
+    async fn spool_post(fetch: &CbzFetch, post: &mut Post) -> Result<TempPath, PostError> {
         let counters = get_counters();
 
-        let filename = post.seq_file_name(num_digits);
         debug!("Fetching {}", &post.url);
-        let res = client.get(&post.url).send().await?;
+        let res = fetch.client.get(&post.url).send().await?;
 
         if res.status().is_client_error() {
             counters.multi.println(format!(
@@ -47,228 +64,227 @@ impl Queue {
 
         let size = res.content_length().unwrap_or_default();
 
-        let pb = counters.add_download_bar(size, variant);
+        let pb = counters.add_download_bar(size, post.website);
 
         // Download the file chunk by chunk.
-        debug!("Retrieving chunks for {}", &filename);
-        let mut stream = res.bytes_stream();
-
-        let buf_size: usize = size.try_into()?;
+        debug!("Retrieving chunks for post {}", post.id);
+        let (data, header) = spool_response(res, &pb, &fetch.spool_dir).await?;
 
-        let mut fvec: Vec<u8> = Vec::with_capacity(buf_size);
-
-        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
-
-        while let Some(item) = stream.next().await {
-            // Retrieve chunk.
-            let chunk = match item {
-                Ok(chunk) => chunk,
-                Err(e) => {
-                    return Err(PostError::ChunkDownloadFail {
-                        message: e.to_string(),
-                    })
-                }
-            };
-            pb.inc(chunk.len().try_into()?);
-
-            // Write to file.
-            AsyncWriteExt::write_all(&mut fvec, &chunk).await?;
-        }
-
-        spawn_blocking(move || -> Result<(), PostError> {
-            let mut un_mut = zip.lock().unwrap();
-
-            debug!("Writing {} to cbz file", filename);
-            if let Err(error) = un_mut.start_file(filename, options) {
-                return Err(PostError::ZipFileWriteError {
-                    message: error.to_string(),
-                });
-            }
-
-            un_mut.write_all(&fvec)?;
-
-            drop(un_mut);
-
-            Ok(())
-        })
-        .await??;
+        Self::fix_extension(post, &header)?;
 
         pb.finish_and_clear();
 
-        Ok(())
+        Ok(data)
     }
 
-    pub(crate) async fn fetch_cbz(
-        client: Client,
-        variant: ImageBoards,
-        name_type: NameType,
-        post: Post,
-        annotate: bool,
-        zip: Arc<Mutex<ZipWriter<File>>>,
-    ) -> Result<(), PostError> {
-        let counters = get_counters();
-        let filename = post.file_name(name_type);
-        debug!("Fetching {}", &post.url);
-        let res = client.get(&post.url).send().await?;
+    /// Downloads a pool page, returning the entries to be written into the cbz.
+    ///
+    /// Pages are not written right away, so [`Queue::archive_path`] can write them in page order.
+    pub(crate) async fn fetch_cbz_pool(
+        fetch: CbzFetch,
+        mut post: Post,
+        num_digits: usize,
+    ) -> Result<(Post, Vec<(String, EntryData)>), PostError> {
+        let data = Self::spool_post(&fetch, &mut post).await?;
+        let filename = post.seq_file_name(num_digits);
 
-        if res.status().is_client_error() {
-            counters.multi.println(format!(
-                "{} {}{}",
-                "Image source returned status".bold().red(),
-                res.status().as_str().bold().red(),
-                ". Skipping download.".bold().red()
-            ))?;
-            counters.main.inc(1);
-            return Err(PostError::RemoteFileNotFound);
-        }
+        let entries = Self::ugoira_cbz_entries(fetch.ugoira, &post, filename, data).await?;
 
-        let size = res.content_length().unwrap_or_default();
+        Ok((post, entries))
+    }
 
-        let pb = counters.add_download_bar(size, variant);
+    /// Downloads a post, returning the entries to be written into the cbz along with it's caption.
+    pub(crate) async fn fetch_cbz(
+        fetch: CbzFetch,
+        mut post: Post,
+    ) -> Result<(Post, Vec<(String, EntryData)>), PostError> {
+        let name_type = fetch.name_type;
 
-        // Download the file chunk by chunk.
-        debug!("Retrieving chunks for {}", &filename);
-        let mut stream = res.bytes_stream();
-
-        let buf_size: usize = size.try_into()?;
-
-        let mut fvec: Vec<u8> = Vec::with_capacity(buf_size);
-
-        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
-        let cap_options = FileOptions::default()
-            .compression_method(CompressionMethod::Deflated)
-            .compression_level(Some(5));
-
-        while let Some(item) = stream.next().await {
-            // Retrieve chunk.
-            let chunk = match item {
-                Ok(chunk) => chunk,
-                Err(e) => {
-                    return Err(PostError::ChunkDownloadFail {
-                        message: e.to_string(),
-                    })
-                }
-            };
-            pb.inc(chunk.len().try_into()?);
+        let data = Self::spool_post(&fetch, &mut post).await?;
+        let filename = post.file_name(name_type);
 
-            // Write to file.
-            AsyncWriteExt::write_all(&mut fvec, &chunk).await?;
+        let mut entries: Vec<(String, EntryData)> =
+            Self::ugoira_cbz_entries(fetch.ugoira, &post, filename, data)
+                .await?
+                .into_iter()
+                .map(|(entry_name, data)| (format!("{}/{}", post.rating, entry_name), data))
+                .collect();
+
+        if fetch.annotate {
+            let tag_list = Vec::from_iter(
+                post.tags
+                    .iter()
+                    .filter(|t| t.is_prompt_tag())
+                    .map(|tag| tag.tag()),
+            );
+
+            let prompt = tag_list.join(", ");
+
+            let f1 = prompt.replace('_', " ");
+
+            entries.push((
+                format!("{}/{}.txt", post.rating, post.name(name_type)),
+                EntryData::Text(f1),
+            ));
         }
 
-        spawn_blocking(move || -> Result<(), PostError> {
-            let mut un_mut = zip.lock().unwrap();
-
-            debug!("Writing {} to cbz file", filename);
-            if let Err(error) =
-                un_mut.start_file(format!("{}/{}", post.rating.to_string(), filename), options)
-            {
-                drop(un_mut);
-                return Err(PostError::ZipFileWriteError {
-                    message: error.to_string(),
-                });
-            };
-
-            un_mut.write_all(&fvec)?;
-
-            if annotate {
-                debug!("Writing caption for {} to cbz file", filename);
-                if let Err(error) = un_mut.start_file(
-                    format!("{}/{}.txt", post.rating.to_string(), post.name(name_type)),
-                    cap_options,
-                ) {
-                    drop(un_mut);
-
-                    return Err(PostError::ZipFileWriteError {
-                        message: error.to_string(),
-                    });
-                };
-
-                let tag_list = Vec::from_iter(
-                    post.tags
-                        .iter()
-                        .filter(|t| t.is_prompt_tag())
-                        .map(|tag| tag.tag()),
-                );
-
-                let prompt = tag_list.join(", ");
-
-                let f1 = prompt.replace('_', " ");
-
-                un_mut.write_all(f1.as_bytes())?;
-
-                drop(un_mut);
-            }
-            Ok(())
-        })
-        .await??;
-
-        pb.finish_and_clear();
+        Ok((post, entries))
+    }
 
-        Ok(())
+    /// Writes the entries of a downloaded post into the archive.
+    async fn write_archive_post<A: PostArchive + Send + 'static>(
+        archive: Arc<Mutex<A>>,
+        post: Post,
+        entries: Vec<(String, EntryData)>,
+    ) -> Result<(), QueueError> {
+        spawn_blocking(move || archive.lock().unwrap().write_post(post, &entries))
+            .await
+            .map_err(PostError::from)?
     }
 
-    pub(crate) fn write_zip_structure(
+    /// Downloads all posts into a single archive file, or the standard output for tar archives.
+    pub(crate) async fn archive_path(
         &self,
-        zip: Arc<Mutex<ZipWriter<File>>>,
+        path: PathBuf,
+        format: ArchiveFormat,
+        progress_channel: Sender<bool>,
+        channel: UnboundedReceiverStream<Post>,
+        pool: bool,
     ) -> Result<(), QueueError> {
-        {
-            let mut z_1 = zip.lock().unwrap();
-            z_1.add_directory(Rating::Safe.to_string(), FileOptions::default())?;
-            z_1.add_directory(Rating::Questionable.to_string(), FileOptions::default())?;
-            z_1.add_directory(Rating::Explicit.to_string(), FileOptions::default())?;
-            z_1.add_directory(Rating::Unknown.to_string(), FileOptions::default())?;
+        let spool_dir = if ArchiveFormat::is_stdout(&path) {
+            std::env::temp_dir()
+        } else {
+            path.parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map_or_else(|| PathBuf::from("."), Path::to_path_buf)
+        };
+
+        let summary = CbzSummary::new(
+            &self.server_names(),
+            self.imageboard.server,
+            self.name_type,
+            pool,
+        );
+
+        match format {
+            ArchiveFormat::Cbz => {
+                let archive =
+                    CbzArchive::open(path, summary, self.cbz_split, self.pool_info.clone())?;
+                self.write_archive(archive, spool_dir, progress_channel, channel, pool)
+                    .await
+            }
+            ArchiveFormat::Tar | ArchiveFormat::TarZst => {
+                let archive = TarArchive::create(&path, format, summary, self.pool_info.clone())?;
+                self.write_archive(archive, spool_dir, progress_channel, channel, pool)
+                    .await
+            }
+            ArchiveFormat::Epub => {
+                let archive =
+                    EpubArchive::create(&path, self.pool_info.clone(), self.epub_max_size)?;
+                self.write_archive(archive, spool_dir, progress_channel, channel, pool)
+                    .await
+            }
         }
-
-        Ok(())
     }
 
-    pub(crate) async fn cbz_path(
+    async fn write_archive<A: PostArchive + Send + 'static>(
         &self,
-        path: PathBuf,
+        archive: A,
+        spool_dir: PathBuf,
         progress_channel: Sender<bool>,
         channel: UnboundedReceiverStream<Post>,
         pool: bool,
     ) -> Result<(), QueueError> {
-        debug!("Target file: {}", path.display());
+        // Pool pages are downloaded concurrently, but written in page order, since many readers
+        // use the entry order instead of the file names
+        let mut pending_pages: BTreeMap<u64, (Post, Vec<(String, EntryData)>)> = BTreeMap::new();
+        let mut next_page = archive.next_missing_page(0);
 
-        let file = File::create(&path)?;
-        let zip = Arc::new(Mutex::new(ZipWriter::new(file)));
+        let archive = Arc::new(Mutex::new(archive));
 
-        if !pool {
-            self.write_zip_structure(zip.clone())?;
-        }
         let sender = progress_channel.clone();
 
-        channel
-            .map(|d| {
-                let nt = self.name_type;
+        let fetch = CbzFetch {
+            client: self.client.clone(),
+            name_type: self.name_type,
+            annotate: self.annotate,
+            ugoira: self.ugoira,
+            spool_dir,
+        };
+
+        let mut downloads = channel
+            .filter(|d| {
+                let exists = archive.lock().unwrap().contains(d);
+
+                self.record_existing(exists);
+
+                if exists {
+                    debug!("Post {} is already in the archive. Skipping.", d.id);
+                    get_counters().main.inc(1);
+                    self.stats.record(d.id, PostOutcome::Skipped);
+                    return ready(false);
+                }
+
+                if !Self::take_new_post(self.new_post_limit.as_deref()) {
+                    debug!("Post {} is left for a later download.", d.id);
+                    get_counters().main.inc(1);
+                    return ready(false);
+                }
+
+                ready(true)
+            })
+            .map(|mut d| {
+                let (server, client) = self.server_of(&d);
+                d.url = server.route_file_url(&d.url);
 
-                let cli = self.client.clone();
-                let zip = zip.clone();
-                let variant = self.imageboard.server;
-                let annotate = self.annotate;
+                let mut fetch = fetch.clone();
+                fetch.client = client.clone();
                 let sender = sender.clone();
+                let id = d.id;
 
                 task::spawn(async move {
-                    if pool {
-                        Self::fetch_cbz_pool(cli, variant, d, zip, 6).await?;
+                    let downloaded = if pool {
+                        Self::fetch_cbz_pool(fetch, d, 6).await?
                     } else {
-                        Self::fetch_cbz(cli, variant, nt, d, annotate, zip).await?;
-                    }
+                        Self::fetch_cbz(fetch, d).await?
+                    };
 
                     let _ = sender.send(true).await;
-                    Ok::<(), QueueError>(())
+                    Ok::<_, QueueError>(downloaded)
                 })
+                .map(move |task| (id, task))
             })
-            .buffer_unordered(self.sim_downloads.into())
-            .for_each(|_| async {})
-            .await;
+            .buffer_unordered(self.sim_downloads.into());
+
+        while let Some((id, task)) = downloads.next().await {
+            let Ok(Ok((post, entries))) = task else {
+                self.stats.record(id, PostOutcome::Failed);
+                continue;
+            };
+
+            self.stats.record(id, PostOutcome::Downloaded);
+
+            if !pool {
+                Self::write_archive_post(archive.clone(), post, entries).await?;
+                continue;
+            }
 
-        {
-            let mut mtx = zip.lock().unwrap();
+            pending_pages.insert(post.id, (post, entries));
 
-            mtx.finish()?;
+            // Write every page that is next in line
+            while let Some((post, entries)) = pending_pages.remove(&next_page) {
+                Self::write_archive_post(archive.clone(), post, entries).await?;
+                next_page = archive.lock().unwrap().next_missing_page(next_page + 1);
+            }
+        }
+
+        // Pages after a missing one (filtered or failed downloads) are written in order at the end
+        for (_, (post, entries)) in pending_pages {
+            Self::write_archive_post(archive.clone(), post, entries).await?;
         }
+
+        archive.lock().unwrap().finish()?;
+
         Ok(())
     }
 }
@@ -0,0 +1,204 @@
+//! Generation of the `ComicInfo.xml` metadata entry read by comic readers like Komga and Kavita.
+use std::collections::{BTreeSet, HashSet};
+
+use ibdl_common::post::{tags::TagType, Post};
+use ibdl_extractors::imageboards::PoolInfo;
+
+/// Collects the metadata of a pool while it's pages are downloaded.
+#[derive(Debug)]
+pub struct ComicInfo {
+    pool: PoolInfo,
+    /// Number of the volume, when the pool is split into multiple archives
+    volume: Option<usize>,
+    /// Names of the files written into the archive, sorted the same way as comic readers do
+    entries: BTreeSet<String>,
+    artists: BTreeSet<String>,
+    characters: BTreeSet<String>,
+    tags: BTreeSet<String>,
+}
+
+impl ComicInfo {
+    pub const FILE_NAME: &'static str = "ComicInfo.xml";
+
+    pub const fn new(pool: PoolInfo) -> Self {
+        Self {
+            pool,
+            volume: None,
+            entries: BTreeSet::new(),
+            artists: BTreeSet::new(),
+            characters: BTreeSet::new(),
+            tags: BTreeSet::new(),
+        }
+    }
+
+    pub const fn volume(&mut self, volume: usize) -> &mut Self {
+        self.volume = Some(volume);
+        self
+    }
+
+    /// Registers the metadata of a downloaded page.
+    pub fn add_page(&mut self, post: &Post) {
+        for tag in &post.tags {
+            let name = tag.tag().replace('_', " ");
+
+            match tag.tag_type() {
+                TagType::Author => {
+                    self.artists.insert(name);
+                }
+                TagType::Character => {
+                    self.characters.insert(name.clone());
+                    self.tags.insert(name);
+                }
+                TagType::Meta => {}
+                _ => {
+                    self.tags.insert(name);
+                }
+            }
+        }
+    }
+
+    /// Registers a file written into the archive, so pages point to the right entry.
+    pub fn add_entry(&mut self, name: &str) {
+        self.entries.insert(name.to_string());
+    }
+
+    /// Positions of the entries that are pages, skipping the annotations and the Ugoira zips kept
+    /// next to their converted animation.
+    fn page_entries(&self) -> Vec<usize> {
+        let converted = self
+            .entries
+            .iter()
+            .filter(|name| !name.ends_with(".zip") && !name.ends_with(".txt"))
+            .map(|name| file_stem(name))
+            .collect::<HashSet<_>>();
+
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| {
+                let extra = name.ends_with(".txt")
+                    || (name.ends_with(".zip") && converted.contains(file_stem(name)));
+
+                !extra
+            })
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    pub fn to_xml(&self) -> String {
+        let pages = self.page_entries();
+
+        let join = |set: &BTreeSet<String>| set.iter().cloned().collect::<Vec<_>>().join(", ");
+
+        let mut xml = String::with_capacity(1024 + pages.len() * 32);
+
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<ComicInfo xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n");
+
+        let name = self.pool.pretty_name();
+        push_element(&mut xml, "Title", &name);
+        push_element(&mut xml, "Series", &name);
+        if let Some(volume) = self.volume {
+            push_element(&mut xml, "Volume", &volume.to_string());
+        }
+        push_element(&mut xml, "Summary", &self.pool.description);
+        push_element(&mut xml, "Writer", &join(&self.artists));
+        push_element(&mut xml, "Penciller", &join(&self.artists));
+        push_element(&mut xml, "Characters", &join(&self.characters));
+        push_element(&mut xml, "PageCount", &pages.len().to_string());
+
+        if let Some(url) = &self.pool.url {
+            push_element(&mut xml, "Web", url);
+        }
+
+        push_element(&mut xml, "Tags", &join(&self.tags));
+
+        // Entries are named after their page number, so their sorted order is the page order
+        xml.push_str("  <Pages>\n");
+        for (page, idx) in pages.iter().enumerate() {
+            if page == 0 {
+                xml.push_str(&format!(
+                    "    <Page Image=\"{idx}\" Type=\"FrontCover\" />\n"
+                ));
+            } else {
+                xml.push_str(&format!("    <Page Image=\"{idx}\" />\n"));
+            }
+        }
+        xml.push_str("  </Pages>\n");
+
+        xml.push_str("</ComicInfo>\n");
+        xml
+    }
+}
+
+/// Name of the entry without it's extension.
+fn file_stem(name: &str) -> &str {
+    name.rsplit_once('.').map_or(name, |(stem, _)| stem)
+}
+
+fn push_element(xml: &mut String, name: &str, value: &str) {
+    if value.is_empty() {
+        return;
+    }
+
+    xml.push_str(&format!("  <{name}>{}</{name}>\n", escape_xml(value)));
+}
+
+pub fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comic_info(entries: &[&str]) -> ComicInfo {
+        let mut info = ComicInfo::new(PoolInfo::default());
+
+        for name in entries {
+            info.add_entry(name);
+        }
+
+        info
+    }
+
+    #[test]
+    fn pages_follow_the_entries() {
+        let info = comic_info(&["00002.png", "00000.jpg", "00001.jpg"]);
+
+        assert_eq!(info.page_entries(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn kept_ugoira_zips_are_not_pages() {
+        let info = comic_info(&["00000.jpg", "00001.gif", "00001.zip", "00002.jpg"]);
+
+        assert_eq!(info.page_entries(), vec![0, 1, 3]);
+
+        let xml = info.to_xml();
+        assert!(xml.contains("<PageCount>3</PageCount>"));
+        assert!(xml.contains("<Page Image=\"0\" Type=\"FrontCover\" />"));
+        assert!(xml.contains("<Page Image=\"3\" />"));
+        assert!(!xml.contains("<Page Image=\"2\" />"));
+    }
+
+    #[test]
+    fn unconverted_ugoira_zips_are_pages() {
+        let info = comic_info(&["00000.jpg", "00001.zip", "00001.txt", "00002.webp"]);
+
+        assert_eq!(info.page_entries(), vec![0, 2, 3]);
+    }
+}
@@ -0,0 +1,438 @@
+//! EPUB 3 fixed-layout books generated from pools, for e-readers that can't open cbz files.
+//!
+//! Each pool page becomes an XHTML page holding a single image, in pool order. The first page is
+//! also used as the cover, and the pool name and artists are written into the package metadata.
+//! Images can be downscaled to the resolution of the device, since e-ink readers struggle with
+//! very large pages.
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{Cursor, Read, Write},
+    path::Path,
+};
+
+use chrono::Utc;
+use ibdl_common::{
+    log::debug,
+    post::{tags::TagType, Post},
+};
+use ibdl_extractors::imageboards::PoolInfo;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, ImageFormat, ImageReader};
+use image_webp::WebPDecoder;
+use owo_colors::OwoColorize;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::error::QueueError;
+
+use super::{archive::PostArchive, comic_info::escape_xml, get_counters, spool::EntryData};
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+/// Quality of the JPEG pages re-encoded after being downscaled.
+const JPEG_QUALITY: u8 = 90;
+
+/// Image page written into the book.
+struct EpubPage {
+    /// Position of the page in the pool
+    id: u64,
+    /// File name of the image, inside `OEBPS/images`
+    image: String,
+    media_type: &'static str,
+    width: u32,
+    height: u32,
+}
+
+impl EpubPage {
+    fn xhtml_name(&self) -> String {
+        format!("page-{:06}.xhtml", self.id)
+    }
+
+    fn to_xhtml(&self, number: usize) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>Page {number}</title>
+  <meta name="viewport" content="width={width}, height={height}"/>
+  <style>html, body {{ margin: 0; padding: 0; }} img {{ display: block; width: 100%; height: 100%; }}</style>
+</head>
+<body>
+  <img src="images/{image}" alt="Page {number}"/>
+</body>
+</html>
+"#,
+            width = self.width,
+            height = self.height,
+            image = escape_xml(&self.image),
+        )
+    }
+}
+
+/// EPUB book pool pages are being written to.
+pub struct EpubArchive {
+    zip: ZipWriter<File>,
+    pool_info: Option<PoolInfo>,
+    /// Max width and height of the pages. Larger images are downscaled to fit.
+    max_size: Option<(u32, u32)>,
+    pages: Vec<EpubPage>,
+    /// Pages written or skipped, so they are not waited for by the queue
+    done: BTreeSet<u64>,
+    artists: BTreeSet<String>,
+}
+
+impl EpubArchive {
+    /// Creates the book at `path`, overwriting it if it already exists.
+    pub fn create(
+        path: &Path,
+        pool_info: Option<PoolInfo>,
+        max_size: Option<(u32, u32)>,
+    ) -> Result<Self, QueueError> {
+        debug!("Target file: {}", path.display());
+
+        let mut zip = ZipWriter::new(File::create(path)?);
+
+        // The mimetype must be the first entry and can't be compressed
+        zip.start_file(
+            "mimetype",
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        zip.write_all(b"application/epub+zip")?;
+
+        zip.start_file("META-INF/container.xml", Self::text_options())?;
+        zip.write_all(CONTAINER_XML.as_bytes())?;
+
+        Ok(Self {
+            zip,
+            pool_info,
+            max_size,
+            pages: Vec::new(),
+            done: BTreeSet::new(),
+            artists: BTreeSet::new(),
+        })
+    }
+
+    fn text_options() -> FileOptions {
+        FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(5))
+    }
+
+    /// Media type of the images that can be shown in an EPUB page.
+    fn media_type(file_name: &str) -> Option<&'static str> {
+        let (_, ext) = file_name.rsplit_once('.')?;
+
+        match ext.to_lowercase().as_str() {
+            "jpg" | "jpeg" => Some("image/jpeg"),
+            "png" => Some("image/png"),
+            "gif" => Some("image/gif"),
+            "webp" => Some("image/webp"),
+            _ => None,
+        }
+    }
+
+    /// Reads the image, downscaling it if it's larger than `max_size`.
+    ///
+    /// Only JPEG and PNG images are downscaled. Returns the image data along with it's dimensions.
+    fn prepare_image(
+        &self,
+        data: &EntryData,
+        media_type: &str,
+    ) -> Result<(Vec<u8>, u32, u32), QueueError> {
+        let mut bytes = Vec::with_capacity(data.size()?.try_into()?);
+        data.reader()?.read_to_end(&mut bytes)?;
+
+        let (width, height) = image_dimensions(&bytes, media_type)?;
+
+        let Some((max_width, max_height)) = self.max_size else {
+            return Ok((bytes, width, height));
+        };
+
+        let format = match media_type {
+            "image/jpeg" => ImageFormat::Jpeg,
+            "image/png" => ImageFormat::Png,
+            _ => return Ok((bytes, width, height)),
+        };
+
+        if width <= max_width && height <= max_height {
+            return Ok((bytes, width, height));
+        }
+
+        debug!("Downscaling {width}x{height} page to fit in {max_width}x{max_height}");
+
+        let image = image::load_from_memory_with_format(&bytes, format)
+            .map_err(|error| image_error(&error))?
+            .resize(max_width, max_height, FilterType::Lanczos3);
+
+        let mut resized = Cursor::new(Vec::new());
+
+        if format == ImageFormat::Jpeg {
+            JpegEncoder::new_with_quality(&mut resized, JPEG_QUALITY)
+                .encode_image(&image.to_rgb8())
+                .map_err(|error| image_error(&error))?;
+        } else {
+            image
+                .write_to(&mut resized, ImageFormat::Png)
+                .map_err(|error| image_error(&error))?;
+        }
+
+        Ok((resized.into_inner(), image.width(), image.height()))
+    }
+
+    fn to_opf(&self) -> String {
+        let (identifier, title, description, source) = self.pool_info.as_ref().map_or_else(
+            || {
+                (
+                    String::from("urn:ibdl:pool"),
+                    String::from("Pool"),
+                    None,
+                    None,
+                )
+            },
+            |info| {
+                (
+                    format!("urn:ibdl:pool:{}", info.id),
+                    info.pretty_name(),
+                    Some(info.description.as_str()).filter(|d| !d.is_empty()),
+                    info.url.as_deref(),
+                )
+            },
+        );
+
+        let mut opf = String::with_capacity(2048 + self.pages.len() * 256);
+
+        opf.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        opf.push_str("<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" prefix=\"rendition: http://www.idpf.org/vocab/rendition/#\">\n");
+        opf.push_str("  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n");
+        opf.push_str(&format!(
+            "    <dc:identifier id=\"book-id\">{}</dc:identifier>\n",
+            escape_xml(&identifier)
+        ));
+        opf.push_str(&format!(
+            "    <dc:title>{}</dc:title>\n",
+            escape_xml(&title)
+        ));
+        opf.push_str("    <dc:language>en</dc:language>\n");
+
+        for artist in &self.artists {
+            opf.push_str(&format!(
+                "    <dc:creator>{}</dc:creator>\n",
+                escape_xml(artist)
+            ));
+        }
+
+        if let Some(description) = description {
+            opf.push_str(&format!(
+                "    <dc:description>{}</dc:description>\n",
+                escape_xml(description)
+            ));
+        }
+
+        if let Some(source) = source {
+            opf.push_str(&format!(
+                "    <dc:source>{}</dc:source>\n",
+                escape_xml(source)
+            ));
+        }
+
+        opf.push_str(&format!(
+            "    <meta property=\"dcterms:modified\">{}</meta>\n",
+            Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+        ));
+        opf.push_str("    <meta property=\"rendition:layout\">pre-paginated</meta>\n");
+        opf.push_str("    <meta property=\"rendition:spread\">none</meta>\n");
+        // Cover declaration for EPUB 2 readers
+        opf.push_str("    <meta name=\"cover\" content=\"image-0\"/>\n");
+        opf.push_str("  </metadata>\n");
+
+        opf.push_str("  <manifest>\n");
+        opf.push_str("    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n");
+        for (idx, page) in self.pages.iter().enumerate() {
+            let cover = if idx == 0 {
+                " properties=\"cover-image\""
+            } else {
+                ""
+            };
+            opf.push_str(&format!(
+                "    <item id=\"image-{idx}\" href=\"images/{}\" media-type=\"{}\"{cover}/>\n",
+                escape_xml(&page.image),
+                page.media_type
+            ));
+            opf.push_str(&format!(
+                "    <item id=\"page-{idx}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+                page.xhtml_name()
+            ));
+        }
+        opf.push_str("  </manifest>\n");
+
+        opf.push_str("  <spine>\n");
+        for idx in 0..self.pages.len() {
+            opf.push_str(&format!("    <itemref idref=\"page-{idx}\"/>\n"));
+        }
+        opf.push_str("  </spine>\n");
+
+        opf.push_str("</package>\n");
+        opf
+    }
+
+    fn to_nav(&self) -> String {
+        let first = self
+            .pages
+            .first()
+            .map(EpubPage::xhtml_name)
+            .unwrap_or_default();
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>Contents</title>
+</head>
+<body>
+  <nav epub:type="toc">
+    <ol>
+      <li><a href="{first}">Start</a></li>
+    </ol>
+  </nav>
+</body>
+</html>
+"#
+        )
+    }
+}
+
+impl PostArchive for EpubArchive {
+    fn contains(&self, post: &Post) -> bool {
+        self.done.contains(&post.id)
+    }
+
+    fn next_missing_page(&self, page: u64) -> u64 {
+        let mut page = page;
+        while self.done.contains(&page) {
+            page += 1;
+        }
+        page
+    }
+
+    fn write_post(
+        &mut self,
+        post: Post,
+        entries: &[(String, EntryData)],
+    ) -> Result<(), QueueError> {
+        self.done.insert(post.id);
+
+        let image = entries
+            .iter()
+            .find_map(|(name, data)| Some((name, data, Self::media_type(name)?)));
+
+        let Some((name, data, media_type)) = image else {
+            get_counters().multi.println(format!(
+                "{} {} {}",
+                "Page".bold().yellow(),
+                post.id.bold().yellow(),
+                "is not an image and was left out of the EPUB file."
+                    .bold()
+                    .yellow()
+            ))?;
+            return Ok(());
+        };
+
+        // A single broken page shouldn't cost the whole book
+        let (bytes, width, height) = match self.prepare_image(data, media_type) {
+            Ok(image) => image,
+            Err(error) => {
+                get_counters().multi.println(format!(
+                    "{} {} {} {}",
+                    "Page".bold().yellow(),
+                    post.id.bold().yellow(),
+                    "couldn't be read and was left out of the EPUB file:"
+                        .bold()
+                        .yellow(),
+                    error.to_string().yellow()
+                ))?;
+                return Ok(());
+            }
+        };
+
+        let page = EpubPage {
+            id: post.id,
+            image: name.clone(),
+            media_type,
+            width,
+            height,
+        };
+
+        debug!("Writing {} to epub file", page.image);
+        self.zip.start_file(
+            format!("OEBPS/images/{}", page.image),
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        self.zip.write_all(&bytes)?;
+
+        self.zip
+            .start_file(format!("OEBPS/{}", page.xhtml_name()), Self::text_options())?;
+        self.zip
+            .write_all(page.to_xhtml(self.pages.len() + 1).as_bytes())?;
+
+        for tag in post.tags.iter().filter(|t| t.tag_type() == TagType::Author) {
+            self.artists.insert(tag.tag().replace('_', " "));
+        }
+
+        self.pages.push(page);
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), QueueError> {
+        debug!("Writing package document to epub file");
+        self.zip
+            .start_file("OEBPS/content.opf", Self::text_options())?;
+        self.zip.write_all(self.to_opf().as_bytes())?;
+
+        self.zip
+            .start_file("OEBPS/nav.xhtml", Self::text_options())?;
+        self.zip.write_all(self.to_nav().as_bytes())?;
+
+        self.zip.finish()?;
+
+        Ok(())
+    }
+}
+
+#[inline]
+fn image_error(error: &dyn std::error::Error) -> QueueError {
+    QueueError::EpubImageFail {
+        message: error.to_string(),
+    }
+}
+
+/// Reads the dimensions of a JPEG, PNG, GIF or WebP image without decoding it.
+fn image_dimensions(data: &[u8], media_type: &str) -> Result<(u32, u32), QueueError> {
+    match media_type {
+        "image/gif" => {
+            if data.len() < 10 {
+                return Err(QueueError::EpubImageFail {
+                    message: String::from("truncated GIF header"),
+                });
+            }
+
+            let width = u16::from_le_bytes([data[6], data[7]]);
+            let height = u16::from_le_bytes([data[8], data[9]]);
+            Ok((width.into(), height.into()))
+        }
+        "image/webp" => Ok(WebPDecoder::new(Cursor::new(data))
+            .map_err(|error| image_error(&error))?
+            .dimensions()),
+        _ => ImageReader::new(Cursor::new(data))
+            .with_guessed_format()?
+            .into_dimensions()
+            .map_err(|error| image_error(&error)),
+    }
+}
@@ -1,25 +1,33 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
-use futures::StreamExt;
+use futures::{FutureExt, StreamExt};
 use ibdl_common::{
     log::debug,
-    post::{error::PostError, NameType, Post},
-    reqwest::Client,
+    post::{error::PostError, extension::Extension, NameType, Post},
+    reqwest::{Client, Response},
     tokio::{
-        fs::{read, remove_file, rename, OpenOptions},
+        fs::{read, remove_file, rename, write, OpenOptions},
         io::{AsyncWriteExt, BufWriter},
         sync::mpsc::Sender,
         task,
     },
     ImageBoards,
 };
+use indicatif::ProgressBar;
 use md5::compute;
 use owo_colors::OwoColorize;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
 use crate::error::QueueError;
 
-use super::{get_counters, Queue};
+use super::{
+    get_counters, stats::PostOutcome, summary::CbzSummary, Queue, UgoiraConversion, UgoiraFormat,
+    SNIFF_LEN,
+};
 
 impl Queue {
     pub(crate) async fn download_channel(
@@ -28,49 +36,204 @@ impl Queue {
         progress: Sender<bool>,
         output_dir: PathBuf,
         pool: bool,
-    ) {
+    ) -> Result<(), QueueError> {
         let sender = progress.clone();
 
+        let saved_pages = Mutex::new(Vec::new());
+        // Without a summary, there's no way to tell which server each file came from
+        let keep_summary = pool || !self.other_servers.is_empty();
+
         channel
-            .map(|d| {
+            .map(|mut d| {
+                let (server, client) = self.server_of(&d);
+                d.url = server.route_file_url(&d.url);
                 let nt = self.name_type;
 
-                let cli = self.client.clone();
+                let cli = client.clone();
                 let output = output_dir.clone();
-                let file_path = output_dir.join(d.file_name(self.name_type));
-                let variant = self.imageboard.server;
+                let variant = d.website;
                 let sender_chn = sender.clone();
+                let id = d.id;
+                let ugoira = self.ugoira.filter(|_| UgoiraFormat::can_convert(&d));
+                let limit = self.new_post_limit.clone();
 
                 task::spawn(async move {
-                    if !Self::check_file_exists(&d, &file_path, nt).await? {
-                        Self::fetch(cli, variant, &d, &output, nt, pool).await?;
-                    }
+                    let file_name = |d: &Post| {
+                        if pool {
+                            d.seq_file_name(6)
+                        } else {
+                            d.file_name(nt)
+                        }
+                    };
+
+                    Self::adopt_saved_extension(&mut d, &output, file_name);
+                    let file_path = output.join(file_name(&d));
+
+                    let converted =
+                        ugoira.map(|conv| (conv, output.join(conv.converted_name(&file_name(&d)))));
+
+                    let outcome = match converted {
+                        Some((_, ref converted_path)) if converted_path.exists() => {
+                            get_counters().multi.println(format!(
+                                "{} {} {}",
+                                "File".bold().green(),
+                                converted_path
+                                    .file_name()
+                                    .unwrap()
+                                    .to_string_lossy()
+                                    .bold()
+                                    .blue()
+                                    .italic(),
+                                "already exists. Skipping.".bold().green()
+                            ))?;
+
+                            PostOutcome::Skipped
+                        }
+                        _ => {
+                            let exists = Self::check_file_exists(&d, &file_path, nt).await?;
+
+                            if !exists {
+                                if !Self::take_new_post(limit.as_deref()) {
+                                    debug!("Post {} is left for a later download.", d.id);
+                                    let _ = sender_chn.send(true).await;
+                                    return Ok((d, PostOutcome::Left));
+                                }
+
+                                Self::fetch(cli, variant, &mut d, &output, nt, pool).await?;
+                            }
+
+                            if let Some((conv, converted_path)) = converted {
+                                if UgoiraFormat::can_convert(&d) {
+                                    Self::convert_ugoira_file(
+                                        conv,
+                                        &d,
+                                        &output.join(file_name(&d)),
+                                        &converted_path,
+                                    )
+                                    .await?;
+                                }
+                            }
+
+                            if exists {
+                                PostOutcome::Skipped
+                            } else {
+                                PostOutcome::Downloaded
+                            }
+                        }
+                    };
                     let _ = sender_chn.send(true).await;
 
-                    Ok::<Post, QueueError>(d)
+                    Ok::<(Post, PostOutcome), QueueError>((d, outcome))
                 })
+                .map(move |task| (id, task))
             })
             .buffer_unordered(self.sim_downloads as usize)
-            .for_each(|task| async {
-                if let Ok(Ok(post)) = task {
-                    if self.annotate {
-                        if let Err(error) =
-                            Self::write_caption(&post, self.name_type, &output_dir).await
-                        {
-                            let ctrs = get_counters();
-                            ctrs.multi
-                                .println(format!(
-                                    "{} {}: {}",
-                                    "Failed to write caption file for".red().bold(),
-                                    post.file_name(self.name_type).red().bold(),
-                                    error
-                                ))
-                                .unwrap();
-                        };
+            .for_each(|(id, task)| {
+                let outcome = match &task {
+                    Ok(Ok((_, outcome))) => *outcome,
+                    _ => PostOutcome::Failed,
+                };
+
+                if outcome != PostOutcome::Left {
+                    self.record_existing(outcome == PostOutcome::Skipped);
+                }
+
+                self.stats.record(id, outcome);
+
+                async {
+                    if let Ok(Ok((post, PostOutcome::Downloaded | PostOutcome::Skipped))) = task {
+                        if keep_summary {
+                            saved_pages.lock().unwrap().push(post.clone());
+                        }
+
+                        if self.annotate {
+                            if let Err(error) =
+                                Self::write_caption(&post, self.name_type, &output_dir).await
+                            {
+                                let ctrs = get_counters();
+                                ctrs.multi
+                                    .println(format!(
+                                        "{} {}: {}",
+                                        "Failed to write caption file for".red().bold(),
+                                        post.file_name(self.name_type).red().bold(),
+                                        error
+                                    ))
+                                    .unwrap();
+                            };
+                        }
                     }
                 }
             })
-            .await
+            .await;
+
+        if keep_summary {
+            self.write_folder_summary(&output_dir, saved_pages.into_inner().unwrap(), pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the downloaded posts to the summary of the folder, so later downloads know which post
+    /// each pool page came from, or which server each file of a search was found in.
+    async fn write_folder_summary(
+        &self,
+        output: &Path,
+        pages: Vec<Post>,
+        pool: bool,
+    ) -> Result<(), QueueError> {
+        let blank = CbzSummary::new(
+            &self.server_names(),
+            self.imageboard.server,
+            self.name_type,
+            pool,
+        );
+
+        let mut summary = match CbzSummary::read_from_dir(output) {
+            Ok(summary) => {
+                summary.check_origin(&blank, output)?;
+                summary
+            }
+            Err(_) => blank,
+        };
+
+        // Posts of different servers can share ids, but not their MD5
+        if pool {
+            let new_pages = pages.iter().map(|page| page.id).collect::<HashSet<_>>();
+            summary.posts.retain(|saved| !new_pages.contains(&saved.id));
+        } else {
+            let new_posts = pages.iter().map(|post| &post.md5).collect::<HashSet<_>>();
+            summary
+                .posts
+                .retain(|saved| !new_posts.contains(&saved.md5));
+        }
+
+        summary.posts.extend(pages);
+
+        write(output.join(CbzSummary::FILE_NAME), summary.to_json()?).await?;
+
+        Ok(())
+    }
+
+    /// Points the post to a file saved by an earlier download with the extension sniffed from it's
+    /// contents, when it differs from the one reported by the imageboard.
+    fn adopt_saved_extension(post: &mut Post, output: &Path, file_name: impl Fn(&Post) -> String) {
+        if output.join(file_name(post)).exists() {
+            return;
+        }
+
+        let reported = post.extension;
+
+        for ext in Extension::SNIFFABLE {
+            post.extension = ext;
+
+            if output.join(file_name(post)).exists() {
+                debug!("Post {} was saved as {}", post.id, ext);
+                return;
+            }
+        }
+
+        post.extension = reported;
     }
 
     async fn check_file_exists(
@@ -108,6 +271,23 @@ impl Queue {
                 "Found file {}",
                 actual.file_name().unwrap().to_str().unwrap()
             );
+
+            // Samples and previews never match the hash of the original file
+            if !post.variant.is_original() {
+                counters.multi.println(format!(
+                    "{} {} {}",
+                    "File".bold().green(),
+                    name.bold().blue().italic(),
+                    "already exists. Skipping.".bold().green()
+                ))?;
+
+                if file_is_same {
+                    rename(&actual, output).await?;
+                }
+
+                return Ok(true);
+            }
+
             let file_digest = compute(read(&actual).await?);
             let hash = format!("{:x}", file_digest);
             if hash == post.md5 {
@@ -159,10 +339,32 @@ impl Queue {
         Ok(false)
     }
 
+    /// Converts an already downloaded Ugoira zip, removing it afterwards unless it should be kept.
+    async fn convert_ugoira_file(
+        conversion: UgoiraConversion,
+        post: &Post,
+        zip_path: &Path,
+        output: &Path,
+    ) -> Result<(), PostError> {
+        let data = read(zip_path).await?;
+
+        let (_, converted) = Self::convert_ugoira_buffer(conversion, post, data).await?;
+
+        if let Some(converted) = converted {
+            write(output, converted).await?;
+
+            if !conversion.keep_zip {
+                remove_file(zip_path).await?;
+            }
+        }
+
+        Ok(())
+    }
+
     async fn fetch(
         client: Client,
         variant: ImageBoards,
-        post: &Post,
+        post: &mut Post,
         output: &Path,
         name_type: NameType,
         pool: bool,
@@ -188,27 +390,58 @@ impl Queue {
 
         let pb = counters.add_download_bar(size, variant);
 
-        // Download the file chunk by chunk.
-        let mut stream = res.bytes_stream();
-
         let buf_size: usize = size.try_into()?;
 
-        let fname = if pool {
-            post.seq_file_name(6)
-        } else {
-            post.file_name(name_type)
+        let fname = |post: &Post| {
+            if pool {
+                post.seq_file_name(6)
+            } else {
+                post.file_name(name_type)
+            }
         };
 
-        let out = output.join(fname);
+        // Download into a temporary file, since the real extension is only known after the first bytes arrive
+        let part = output.join(format!("{}.part", fname(post)));
 
-        debug!("Creating {:?}", &out);
+        let header = match Self::write_part(res, &part, buf_size, &pb).await {
+            Ok(header) => header,
+            Err(error) => {
+                // Don't leave half downloaded files behind
+                let _ = remove_file(&part).await;
+                pb.finish_and_clear();
+                return Err(error);
+            }
+        };
+
+        Self::fix_extension(post, &header)?;
+
+        rename(&part, output.join(fname(post))).await?;
+
+        pb.finish_and_clear();
+
+        Ok(())
+    }
+
+    /// Writes the response to the temporary `part` file, returning the first bytes of the file.
+    async fn write_part(
+        res: Response,
+        part: &Path,
+        buf_size: usize,
+        pb: &ProgressBar,
+    ) -> Result<Vec<u8>, PostError> {
+        debug!("Creating {:?}", part);
         let file = OpenOptions::new()
-            .append(true)
+            .write(true)
             .create(true)
-            .open(out)
+            .truncate(true)
+            .open(part)
             .await?;
 
+        // Download the file chunk by chunk.
+        let mut stream = res.bytes_stream();
+
         let mut bw = BufWriter::with_capacity(buf_size, file);
+        let mut header: Vec<u8> = Vec::with_capacity(SNIFF_LEN);
 
         while let Some(item) = stream.next().await {
             // Retrieve chunk.
@@ -222,13 +455,16 @@ impl Queue {
             };
             pb.inc(chunk.len().try_into()?);
 
+            if header.len() < SNIFF_LEN {
+                let missing = (SNIFF_LEN - header.len()).min(chunk.len());
+                header.extend_from_slice(&chunk[..missing]);
+            }
+
             // Write to file.
             bw.write_all_buf(&mut chunk).await?;
         }
         bw.flush().await?;
 
-        pb.finish_and_clear();
-
-        Ok(())
+        Ok(header)
     }
 }
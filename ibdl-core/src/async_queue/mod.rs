@@ -4,69 +4,102 @@
 //!
 //! Conveniently using the same example from [here](ibdl-extractors::websites)
 //!
-//! ```rust
-//! use imageboard_downloader::*;
-//! use std::path::PathBuf;
+//! ```no_run
+//! use std::{
+//!     path::PathBuf,
+//!     sync::{atomic::AtomicU64, Arc},
+//! };
+//!
+//! use ibdl_common::{
+//!     post::{rating::Rating, NameType},
+//!     tokio::sync::mpsc::{channel, unbounded_channel},
+//! };
+//! use ibdl_core::async_queue::Queue;
+//! use ibdl_extractors::{extractor_config::DEFAULT_SERVERS, imageboards::prelude::*, prelude::*};
 //!
 //! async fn download_posts() {
 //!     let tags = ["umbreon", "espeon"]; // The tags to search
-//!     
-//!     let safe_mode = false; // Setting this to true, will ignore searching NSFW posts
+//!
+//!     let ratings = [Rating::General, Rating::Sensitive]; // Only download posts with these ratings
 //!
 //!     let disable_blacklist = false; // Will filter all items according to what's set in GBL
 //!
-//!     let mut unit = DanbooruExtractor::new(&tags, safe_mode, disable_blacklist); // Initialize
+//!     let unit = DanbooruExtractor::new(&tags, &ratings, disable_blacklist, true); // Initialize
 //!
-//!     let prompt = true; // If true, will ask the user to input thei username and API key.
+//!     let client = unit.client(); // Re-use the client from the extractor
 //!
-//!     unit.auth(prompt).await.unwrap(); // Try to authenticate
+//!     let (channel_tx, channel_rx) = unbounded_channel(); // Posts found by the extractor
 //!
-//!     let start_page = Some(1); // Start searching from the first page
+//!     let (length_tx, length_rx) = channel(10); // Number of posts found, for the progress bar
 //!
 //!     let limit = Some(50); // Max number of posts to download
 //!
-//!     let posts = unit.full_search(start_page, limit).await.unwrap(); // and then, finally search
+//!     let extractor = unit.setup_fetch_thread(channel_tx, None, limit, Some(length_tx));
 //!
 //!     let sd = 10; // Number of simultaneous downloads.
 //!
-//!     let limit = Some(1000); // Max number of posts to download
+//!     let archive = None; // Set to Some(ArchiveFormat::Cbz) to download everything into a .cbz file
 //!
-//!     let cbz = false; // Set to true to download everything into a .cbz file
-//!
-//!     let mut qw = Queue::new( // Initialize the queue
-//!         ImageBoards::Danbooru,
-//!         posts,
+//!     let qw = Queue::new( // Initialize the queue
+//!         DEFAULT_SERVERS.get("danbooru").unwrap().clone(),
 //!         sd,
-//!         Some(unit.client()), // Re-use the client from the extractor
-//!         limit,
-//!         cbz,
+//!         Some(client),
+//!         archive,
+//!         false,
+//!         NameType::ID, // Save file with their ID as the filename instead of MD5
+//!         false,
 //!     );
 //!
-//!     let output = Some(PathBuf::from("./")); // Where to save the downloaded files or .cbz file
+//!     let output = PathBuf::from("./"); // Where to save the downloaded files or archive
+//!
+//!     let downloader =
+//!         qw.setup_async_downloader(output, Arc::new(AtomicU64::new(0)), channel_rx, length_rx);
 //!
-//!     let id = true; // Save file with their ID as the filename instead of MD5
+//!     extractor.await.unwrap().unwrap(); // Wait for the extractor to find every post
 //!
-//!     qw.download(output, id).await.unwrap(); // Start downloading
+//!     downloader.await.unwrap().unwrap(); // and then for the download to finish
 //! }
 //! ```
 
+mod abort;
+mod archive;
 mod cbz;
+mod comic_info;
+mod epub;
 mod folder;
+mod resume;
+mod spool;
+mod stats;
+mod summary;
+mod tarball;
+mod ugoira;
+mod volume;
+
+pub use abort::{AbortOnExisting, NewPostLimit};
+pub use archive::{ArchiveFormat, STDOUT_PATH};
+pub use resume::saved_pool_pages;
+pub use stats::{QueueResult, QueueStats};
+pub use summary::CbzSummary;
+pub use ugoira::{UgoiraConversion, UgoiraFormat};
+pub use volume::CbzSplit;
 
 use crate::error::QueueError;
 use crate::progress_bars::ProgressCounter;
 use ibdl_common::log::debug;
 use ibdl_common::post::error::PostError;
+use ibdl_common::post::extension::Extension;
 use ibdl_common::post::{NameType, Post};
 use ibdl_common::reqwest::Client;
 use ibdl_common::tokio::spawn;
-use ibdl_common::tokio::sync::mpsc::{channel, Receiver, UnboundedReceiver};
+use ibdl_common::tokio::sync::mpsc::{channel, Receiver, Sender, UnboundedReceiver};
 use ibdl_common::tokio::task::JoinHandle;
 use ibdl_common::{client, tokio};
 use ibdl_extractors::extractor_config::ServerConfig;
+use ibdl_extractors::imageboards::PoolInfo;
 use once_cell::sync::OnceCell;
+use owo_colors::OwoColorize;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::fs::{create_dir_all, OpenOptions};
 use tokio::io::AsyncWriteExt;
@@ -74,48 +107,87 @@ use tokio_stream::wrappers::UnboundedReceiverStream;
 
 static PROGRESS_COUNTERS: OnceCell<ProgressCounter> = OnceCell::new();
 
+/// Number of queues currently using the progress bars.
+static RUNNING_QUEUES: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of bytes read from the start of each download to recognize it's real format.
+pub(crate) const SNIFF_LEN: usize = 64;
+
 pub(crate) fn get_counters() -> &'static ProgressCounter {
     PROGRESS_COUNTERS.get().unwrap()
 }
 
+/// Progress bars of the last download started in this process, if any.
+pub fn current_progress() -> Option<&'static ProgressCounter> {
+    PROGRESS_COUNTERS.get()
+}
+
+/// Marks a queue as running while alive. The last queue to finish clears the progress bars.
+struct RunningQueue(&'static ProgressCounter);
+
+impl Drop for RunningQueue {
+    fn drop(&mut self) {
+        if RUNNING_QUEUES.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.main.finish_and_clear();
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 enum DownloadFormat {
-    Cbz,
-    CbzPool,
+    Archive(ArchiveFormat),
+    ArchivePool(ArchiveFormat),
     Folder,
     FolderPool,
 }
 
 impl DownloadFormat {
+    /// Format of the archive all posts are saved into, if any.
     #[inline]
-    pub const fn download_cbz(&self) -> bool {
+    pub const fn archive(&self) -> Option<ArchiveFormat> {
         match self {
-            Self::Cbz => true,
-            Self::CbzPool => true,
-            Self::Folder => false,
-            Self::FolderPool => false,
+            Self::Archive(format) | Self::ArchivePool(format) => Some(*format),
+            Self::Folder | Self::FolderPool => None,
         }
     }
 
     #[inline]
     pub const fn download_pool(&self) -> bool {
         match self {
-            Self::Cbz => false,
-            Self::CbzPool => true,
+            Self::Archive(_) => false,
+            Self::ArchivePool(_) => true,
             Self::Folder => false,
             Self::FolderPool => true,
         }
     }
 }
 
+/// A pool downloaded by [`Queue::setup_async_pool_downloader`].
+#[derive(Debug)]
+pub struct PoolTarget {
+    pub info: PoolInfo,
+    /// Dir or archive the pool is saved into.
+    pub output: PathBuf,
+    /// Channel the posts of this pool are sent through.
+    pub channel: UnboundedReceiver<Post>,
+}
+
 /// Struct where all the downloading will take place
 pub struct Queue {
     imageboard: ServerConfig,
+    other_servers: Vec<(ServerConfig, Client)>,
     sim_downloads: u8,
     client: Client,
     download_fmt: DownloadFormat,
     name_type: NameType,
     annotate: bool,
+    ugoira: Option<UgoiraConversion>,
+    pool_info: Option<PoolInfo>,
+    cbz_split: CbzSplit,
+    epub_max_size: Option<(u32, u32)>,
+    abort_on_existing: Option<AbortOnExisting>,
+    new_post_limit: Option<Arc<NewPostLimit>>,
+    stats: Arc<QueueStats>,
 }
 
 impl Queue {
@@ -124,7 +196,7 @@ impl Queue {
         imageboard: ServerConfig,
         sim_downloads: u8,
         custom_client: Option<Client>,
-        save_as_cbz: bool,
+        archive: Option<ArchiveFormat>,
         pool_download: bool,
         name_type: NameType,
         annotate: bool,
@@ -135,79 +207,258 @@ impl Queue {
             client!(imageboard)
         };
 
-        let download_fmt = if save_as_cbz && pool_download {
-            DownloadFormat::CbzPool
-        } else if save_as_cbz {
-            DownloadFormat::Cbz
-        } else if pool_download {
-            DownloadFormat::FolderPool
-        } else {
-            DownloadFormat::Folder
+        let download_fmt = match archive {
+            Some(format) if pool_download => DownloadFormat::ArchivePool(format),
+            Some(format) => DownloadFormat::Archive(format),
+            None if pool_download => DownloadFormat::FolderPool,
+            None => DownloadFormat::Folder,
         };
 
         Self {
             download_fmt,
             imageboard,
+            other_servers: Vec::new(),
             sim_downloads,
             annotate,
             client,
             name_type,
+            ugoira: None,
+            pool_info: None,
+            cbz_split: CbzSplit::default(),
+            epub_max_size: None,
+            abort_on_existing: None,
+            new_post_limit: None,
+            stats: Arc::default(),
         }
     }
 
+    /// Metadata of the pool being downloaded, used to write a `ComicInfo.xml` into pool cbz files.
+    pub fn pool_info(&mut self, info: Option<PoolInfo>) -> &mut Self {
+        self.pool_info = info;
+        self
+    }
+
+    /// Split cbz downloads into multiple volumes once they reach the given size or number of posts.
+    pub const fn split_cbz(&mut self, split: CbzSplit) -> &mut Self {
+        self.cbz_split = split;
+        self
+    }
+
+    /// Downscale the pages of EPUB files to fit in the given width and height.
+    pub const fn epub_max_size(&mut self, size: Option<(u32, u32)>) -> &mut Self {
+        self.epub_max_size = size;
+        self
+    }
+
+    /// Tell the extractor to stop once this many posts in a row were already downloaded.
+    pub fn abort_on_existing(&mut self, tracker: Option<AbortOnExisting>) -> &mut Self {
+        self.abort_on_existing = tracker;
+        self
+    }
+
+    /// Stop downloading once this many new posts were downloaded. Posts that were already
+    /// downloaded don't count.
+    pub fn new_post_limit(&mut self, limit: Option<Arc<NewPostLimit>>) -> &mut Self {
+        self.new_post_limit = limit;
+        self
+    }
+
+    /// Count the posts of this queue into `stats`, so they can be read while it's running.
+    pub fn share_stats(&mut self, stats: Arc<QueueStats>) -> &mut Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Other servers the posts can come from, when a search goes through more than one server,
+    /// with the client used to download their files.
+    pub fn other_servers(&mut self, servers: Vec<(ServerConfig, Client)>) -> &mut Self {
+        self.other_servers = servers;
+        self
+    }
+
+    /// Convert Ugoira posts that have frame delays into animated images after download.
+    pub const fn convert_ugoira(&mut self, conversion: Option<UgoiraConversion>) -> &mut Self {
+        self.ugoira = conversion;
+        self
+    }
+
     pub fn setup_async_downloader(
         self,
         output_dir: PathBuf,
         post_counter: Arc<AtomicU64>,
         channel_rx: UnboundedReceiver<Post>,
         length_rx: Receiver<u64>,
-    ) -> JoinHandle<Result<u64, QueueError>> {
+    ) -> JoinHandle<Result<QueueResult, QueueError>> {
         spawn(async move {
             debug!("Async Downloader thread initialized");
 
-            let counters = PROGRESS_COUNTERS.get_or_init(|| {
-                ProgressCounter::initialize(
-                    post_counter.load(Ordering::Relaxed),
-                    self.imageboard.server,
-                )
-            });
+            let (_running, progress_sender) = self.init_counters(post_counter, length_rx).await;
 
             self.create_out(&output_dir).await?;
 
             let post_channel = UnboundedReceiverStream::new(channel_rx);
-            let (progress_sender, progress_channel) = channel(self.sim_downloads as usize);
-
-            counters.init_length_updater(length_rx).await;
-            counters.init_download_counter(progress_channel).await;
-
-            if self.download_fmt.download_cbz() {
-                self.cbz_path(
-                    output_dir,
-                    progress_sender,
-                    post_channel,
-                    self.download_fmt.download_pool(),
-                )
+
+            self.download_to(output_dir, progress_sender, post_channel)
                 .await?;
-            } else {
-                self.download_channel(
-                    post_channel,
-                    progress_sender,
-                    output_dir,
-                    self.download_fmt.download_pool(),
-                )
-                .await;
-            }
 
-            counters.main.finish_and_clear();
+            Ok(self.stats.result())
+        })
+    }
+
+    /// Downloads many pools one after another, each into it's own dir or archive, sharing the
+    /// same progress bars.
+    pub fn setup_async_pool_downloader(
+        mut self,
+        pools: Vec<PoolTarget>,
+        post_counter: Arc<AtomicU64>,
+        length_rx: Receiver<u64>,
+    ) -> JoinHandle<Result<QueueResult, QueueError>> {
+        spawn(async move {
+            debug!("Async Pool Downloader thread initialized");
+
+            let (running, progress_sender) = self.init_counters(post_counter, length_rx).await;
 
-            let tot = counters.downloaded_mtx.load(Ordering::SeqCst);
+            let batch = pools.len() > 1;
+
+            for pool in pools {
+                if batch {
+                    running.0.multi.println(format!(
+                        "{} {}",
+                        "Downloading pool".bold(),
+                        pool.info.pretty_name().bold().blue()
+                    ))?;
+                }
+
+                self.create_out(&pool.output).await?;
+                self.pool_info = Some(pool.info);
+
+                let post_channel = UnboundedReceiverStream::new(pool.channel);
+
+                self.download_to(pool.output, progress_sender.clone(), post_channel)
+                    .await?;
+            }
 
-            Ok(tot)
+            Ok(self.stats.result())
         })
     }
 
+    /// Config of the server the post was found in, with the client used to download it's file.
+    fn server_of(&self, post: &Post) -> (&ServerConfig, &Client) {
+        post.server
+            .as_ref()
+            .and_then(|name| {
+                self.other_servers
+                    .iter()
+                    .find(|(server, _)| server.name == *name)
+            })
+            .map_or((&self.imageboard, &self.client), |(server, client)| {
+                (server, client)
+            })
+    }
+
+    /// Names of every server the posts can come from, joined by commas.
+    fn server_names(&self) -> String {
+        let mut names = vec![self.imageboard.name.as_str()];
+        names.extend(
+            self.other_servers
+                .iter()
+                .map(|(server, _)| server.name.as_str()),
+        );
+
+        names.join(",")
+    }
+
+    async fn init_counters(
+        &self,
+        post_counter: Arc<AtomicU64>,
+        length_rx: Receiver<u64>,
+    ) -> (RunningQueue, Sender<bool>) {
+        let len = post_counter.load(Ordering::Relaxed);
+
+        // Counters are reused when more than one queue runs in the same process
+        let mut reused = true;
+        let counters = PROGRESS_COUNTERS.get_or_init(|| {
+            reused = false;
+            ProgressCounter::initialize(len, self.imageboard.server)
+        });
+
+        // Queues running at the same time share the main bar, so only the first one resets it
+        if RUNNING_QUEUES.fetch_add(1, Ordering::SeqCst) > 0 {
+            counters.main.inc_length(len);
+        } else if reused {
+            counters.restart(len);
+        }
+
+        let (progress_sender, progress_channel) = channel(self.sim_downloads as usize);
+
+        // The posts found are also counted into the stats, which can be read while the queue runs
+        self.stats.add_found(len);
+
+        let (found_sender, found_channel) = channel(self.sim_downloads as usize);
+        let stats = self.stats.clone();
+        let mut length_rx = length_rx;
+
+        spawn(async move {
+            while let Some(delta) = length_rx.recv().await {
+                stats.add_found(delta);
+
+                if found_sender.send(delta).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        counters.init_length_updater(found_channel).await;
+        counters.init_download_counter(progress_channel).await;
+
+        (RunningQueue(counters), progress_sender)
+    }
+
+    /// Downloads all posts from the channel into a dir or archive.
+    async fn download_to(
+        &self,
+        output_dir: PathBuf,
+        progress_sender: Sender<bool>,
+        post_channel: UnboundedReceiverStream<Post>,
+    ) -> Result<(), QueueError> {
+        if let Some(format) = self.download_fmt.archive() {
+            self.archive_path(
+                output_dir,
+                format,
+                progress_sender,
+                post_channel,
+                self.download_fmt.download_pool(),
+            )
+            .await
+        } else {
+            self.download_channel(
+                post_channel,
+                progress_sender,
+                output_dir,
+                self.download_fmt.download_pool(),
+            )
+            .await
+        }
+    }
+
+    /// Reports to the extractor whether a post was skipped because it was already downloaded.
+    /// Takes a slot for downloading a new post, if the queue has a limit.
+    fn take_new_post(limit: Option<&NewPostLimit>) -> bool {
+        limit.is_none_or(NewPostLimit::take)
+    }
+
+    fn record_existing(&self, existing: bool) {
+        if let Some(tracker) = &self.abort_on_existing {
+            tracker.record(existing);
+        }
+    }
+
     async fn create_out(&self, dir: &Path) -> Result<(), QueueError> {
-        if self.download_fmt.download_cbz() {
+        if self.download_fmt.archive().is_some() {
+            if ArchiveFormat::is_stdout(dir) {
+                return Ok(());
+            }
+
             let output_file = dir.parent().unwrap().to_path_buf();
 
             match create_dir_all(&output_file).await {
@@ -234,6 +485,39 @@ impl Queue {
         Ok(())
     }
 
+    /// Corrects the extension of the post with the format sniffed from the first bytes of the file,
+    /// warning when the extension reported by the imageboard was wrong.
+    pub(crate) fn fix_extension(post: &mut Post, header: &[u8]) -> Result<(), PostError> {
+        let Some(sniffed) = Extension::sniff(header) else {
+            return Ok(());
+        };
+
+        if sniffed == post.extension {
+            return Ok(());
+        }
+
+        if post.extension == Extension::Unknown {
+            debug!(
+                "Post {} has an unknown extension. Saving as {}",
+                post.id, sniffed
+            );
+        } else {
+            get_counters().multi.println(format!(
+                "{} {} {} {}{}",
+                "Post".bold().yellow(),
+                post.id.bold().blue(),
+                "was reported as".bold().yellow(),
+                post.extension.bold().red(),
+                format!(", but it's a {sniffed} file. Fixing extension.")
+                    .bold()
+                    .yellow()
+            ))?;
+        }
+
+        post.extension = sniffed;
+        Ok(())
+    }
+
     async fn write_caption(
         post: &Post,
         name_type: NameType,
@@ -242,6 +526,7 @@ impl Queue {
         let outpath = output.join(format!("{}.txt", post.name(name_type)));
         let mut prompt_file = OpenOptions::new()
             .create(true)
+            .truncate(true)
             .write(true)
             .open(outpath)
             .await?;
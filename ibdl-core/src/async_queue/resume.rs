@@ -0,0 +1,81 @@
+//! Pages of a pool that were saved by a previous download, so updating a pool only fetches the
+//! new posts.
+use std::{collections::BTreeMap, num::NonZeroU64, path::Path};
+
+use ibdl_common::log::debug;
+use ibdl_extractors::imageboards::PoolInfo;
+
+use super::{archive::ArchiveFormat, summary::CbzSummary, volume::CbzSplit};
+
+/// Finds which posts of the pool were already saved to `path`, mapped to their page number.
+///
+/// Pages are read from the summary of the cbz file (or all of it's volumes) or the pool folder.
+/// Pages saved before the summary kept the original post id are matched to the current order of
+/// the pool. Other archive formats are always overwritten, so nothing is returned for them.
+pub fn saved_pool_pages(
+    path: &Path,
+    format: Option<ArchiveFormat>,
+    split: CbzSplit,
+    pool: &PoolInfo,
+) -> BTreeMap<u64, u64> {
+    let mut pages = BTreeMap::new();
+
+    let mut add_page = |page: u64, post_id: Option<u64>| {
+        let post_id = post_id.or_else(|| pool.post_ids.get(usize::try_from(page).ok()?).copied());
+
+        if let Some(id) = post_id {
+            pages.insert(id, page);
+        }
+    };
+
+    match format {
+        Some(ArchiveFormat::Cbz) => {
+            for summary in cbz_summaries(path, split) {
+                for post in summary.posts.iter().filter(|_| summary.pool) {
+                    add_page(post.id, post.pool_post_id.map(NonZeroU64::get));
+                }
+            }
+        }
+        Some(_) => {}
+        None => {
+            let summary = CbzSummary::read_from_dir(path).ok().filter(|s| s.pool);
+            let in_summary = |page: u64| summary.as_ref().is_some_and(|s| s.has_page(page));
+
+            if let Some(summary) = &summary {
+                for post in &summary.posts {
+                    add_page(post.id, post.pool_post_id.map(NonZeroU64::get));
+                }
+            }
+
+            // Files downloaded before pool folders had a summary
+            if let Ok(entries) = path.read_dir() {
+                for entry in entries.flatten() {
+                    let page = entry
+                        .path()
+                        .file_stem()
+                        .and_then(|stem| stem.to_str()?.parse::<u64>().ok());
+
+                    if let Some(page) = page.filter(|page| !in_summary(*page)) {
+                        add_page(page, None);
+                    }
+                }
+            }
+        }
+    }
+
+    debug!("Found {} saved pages of pool {}", pages.len(), pool.id);
+    pages
+}
+
+/// Summaries of the cbz file, or of every volume if it's split.
+fn cbz_summaries(path: &Path, split: CbzSplit) -> Vec<CbzSummary> {
+    if !split.is_enabled() {
+        return CbzSummary::read_from(path).into_iter().collect();
+    }
+
+    (1..)
+        .map(|volume| CbzSplit::volume_path(path, volume))
+        .take_while(|volume| volume.exists())
+        .filter_map(|volume| CbzSummary::read_from(&volume).ok())
+        .collect()
+}
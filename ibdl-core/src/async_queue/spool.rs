@@ -0,0 +1,106 @@
+//! Temporary files holding downloads until they are written into an archive.
+//!
+//! Downloads are streamed chunk by chunk into a hidden file next to the archive, instead of being
+//! collected in memory, so the memory used by each download doesn't grow with the size of the file.
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use futures::StreamExt;
+use ibdl_common::{
+    log::debug,
+    post::error::PostError,
+    reqwest::Response,
+    tokio::{self, io::AsyncWriteExt},
+};
+use indicatif::ProgressBar;
+use tempfile::{Builder, TempPath};
+
+use super::SNIFF_LEN;
+
+/// Data of a file to be written into an archive.
+#[derive(Debug)]
+pub enum EntryData {
+    /// Small files generated in memory, like converted Ugoira.
+    Buffer(Vec<u8>),
+    /// Text files, like captions. These are the only entries compressed inside the archive.
+    Text(String),
+    /// Downloaded file spooled to disk. It's removed once dropped.
+    Spooled(TempPath),
+}
+
+impl EntryData {
+    /// Size of the data in bytes.
+    pub fn size(&self) -> io::Result<u64> {
+        match self {
+            Self::Buffer(data) => Ok(data.len() as u64),
+            Self::Text(text) => Ok(text.len() as u64),
+            Self::Spooled(path) => Ok(path.metadata()?.len()),
+        }
+    }
+
+    /// Opens the data for reading. Spooled files are read from disk in small chunks.
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match self {
+            Self::Buffer(data) => Ok(Box::new(data.as_slice())),
+            Self::Text(text) => Ok(Box::new(text.as_bytes())),
+            Self::Spooled(path) => Ok(Box::new(File::open(path)?)),
+        }
+    }
+
+    /// Copies the data into `writer`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        io::copy(&mut self.reader()?, writer)?;
+        Ok(())
+    }
+}
+
+/// Streams the response body into a temporary file inside `dir`.
+///
+/// Returns the path of the file, which is deleted when dropped, along with the first bytes of it,
+/// used to sniff the real file type.
+pub async fn spool_response(
+    res: Response,
+    pb: &ProgressBar,
+    dir: &Path,
+) -> Result<(TempPath, Vec<u8>), PostError> {
+    let (file, path) = Builder::new()
+        .prefix(".ibdl-")
+        .suffix(".part")
+        .tempfile_in(dir)?
+        .into_parts();
+
+    debug!("Spooling download to {}", path.display());
+
+    let mut file = tokio::fs::File::from_std(file);
+    let mut header: Vec<u8> = Vec::with_capacity(SNIFF_LEN);
+
+    let mut stream = res.bytes_stream();
+
+    while let Some(item) = stream.next().await {
+        // Retrieve chunk.
+        let chunk = match item {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                return Err(PostError::ChunkDownloadFail {
+                    message: e.to_string(),
+                })
+            }
+        };
+        pb.inc(chunk.len().try_into()?);
+
+        if header.len() < SNIFF_LEN {
+            let missing = SNIFF_LEN - header.len();
+            header.extend_from_slice(&chunk[..chunk.len().min(missing)]);
+        }
+
+        // Write to file.
+        file.write_all(&chunk).await?;
+    }
+
+    file.flush().await?;
+
+    Ok((path, header))
+}
@@ -0,0 +1,181 @@
+//! Outcome of every post handled by a single queue.
+//!
+//! The progress bars are shared by every queue running in the process, so each queue keeps it's
+//! own counts to report once it finishes, or to be read while it's running.
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+/// What happened to a post sent to the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostOutcome {
+    Downloaded,
+    /// The post was already saved in the output dir or archive.
+    Skipped,
+    Failed,
+    /// The post wasn't downloaded, since the queue reached it's limit of new posts.
+    Left,
+}
+
+#[derive(Debug)]
+pub struct QueueStats {
+    /// Posts found by the extractor so far, including the ones the queue didn't get to yet.
+    found: AtomicU64,
+    downloaded: AtomicU64,
+    skipped: AtomicU64,
+    failed: AtomicU64,
+    /// Ids of the posts that were downloaded or skipped.
+    saved_ids: Mutex<Vec<u64>>,
+    oldest_failed: AtomicU64,
+}
+
+impl Default for QueueStats {
+    fn default() -> Self {
+        Self {
+            found: AtomicU64::default(),
+            downloaded: AtomicU64::default(),
+            skipped: AtomicU64::default(),
+            failed: AtomicU64::default(),
+            saved_ids: Mutex::default(),
+            oldest_failed: AtomicU64::new(u64::MAX),
+        }
+    }
+}
+
+/// Number of posts downloaded, skipped and failed by a queue.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueResult {
+    pub downloaded: u64,
+    /// Posts that were already downloaded.
+    pub skipped: u64,
+    pub failed: u64,
+    /// Highest id of the posts that were downloaded or skipped, older than every failed post.
+    ///
+    /// All posts up to it were saved, so it's safe to continue from it in a later download.
+    pub last_saved_id: Option<u64>,
+}
+
+impl QueueStats {
+    pub(crate) fn record(&self, id: u64, outcome: PostOutcome) {
+        let counter = match outcome {
+            PostOutcome::Downloaded => &self.downloaded,
+            PostOutcome::Skipped => &self.skipped,
+            PostOutcome::Failed => &self.failed,
+            PostOutcome::Left => return,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        if outcome == PostOutcome::Failed {
+            self.oldest_failed.fetch_min(id, Ordering::Relaxed);
+        } else {
+            self.saved_ids.lock().unwrap().push(id);
+        }
+    }
+
+    pub(crate) fn add_found(&self, posts: u64) {
+        self.found.fetch_add(posts, Ordering::Relaxed);
+    }
+
+    /// Number of posts found so far.
+    pub fn found(&self) -> u64 {
+        self.found.load(Ordering::Relaxed)
+    }
+
+    pub fn result(&self) -> QueueResult {
+        let oldest_failed = self.oldest_failed.load(Ordering::Relaxed);
+
+        let last_saved_id = self
+            .saved_ids
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .filter(|id| *id < oldest_failed)
+            .max();
+
+        QueueResult {
+            downloaded: self.downloaded.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            last_saved_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(outcomes: &[(u64, PostOutcome)]) -> QueueResult {
+        let stats = QueueStats::default();
+
+        for (id, outcome) in outcomes {
+            stats.record(*id, *outcome);
+        }
+
+        stats.result()
+    }
+
+    #[test]
+    fn empty_queue_has_no_saved_id() {
+        let result = stats(&[]);
+
+        assert_eq!(result.downloaded + result.skipped + result.failed, 0);
+        assert_eq!(result.last_saved_id, None);
+    }
+
+    #[test]
+    fn outcomes_are_counted() {
+        let result = stats(&[
+            (5, PostOutcome::Downloaded),
+            (4, PostOutcome::Downloaded),
+            (3, PostOutcome::Skipped),
+            (2, PostOutcome::Failed),
+            (1, PostOutcome::Left),
+        ]);
+
+        assert_eq!(result.downloaded, 2);
+        assert_eq!(result.skipped, 1);
+        assert_eq!(result.failed, 1);
+    }
+
+    #[test]
+    fn saved_id_is_the_highest_without_failures() {
+        let result = stats(&[
+            (10, PostOutcome::Skipped),
+            (30, PostOutcome::Downloaded),
+            (20, PostOutcome::Downloaded),
+        ]);
+
+        assert_eq!(result.last_saved_id, Some(30));
+    }
+
+    #[test]
+    fn saved_id_stays_below_the_oldest_failure() {
+        let result = stats(&[
+            (50, PostOutcome::Downloaded),
+            (40, PostOutcome::Failed),
+            (30, PostOutcome::Downloaded),
+            (20, PostOutcome::Failed),
+            (10, PostOutcome::Skipped),
+        ]);
+
+        assert_eq!(result.last_saved_id, Some(10));
+    }
+
+    #[test]
+    fn failed_newest_post_keeps_no_saved_id() {
+        let result = stats(&[(20, PostOutcome::Failed), (30, PostOutcome::Downloaded)]);
+
+        assert_eq!(result.last_saved_id, None);
+    }
+
+    #[test]
+    fn left_posts_are_not_saved() {
+        let result = stats(&[(20, PostOutcome::Downloaded), (30, PostOutcome::Left)]);
+
+        assert_eq!(result.last_saved_id, Some(20));
+    }
+}
@@ -0,0 +1,148 @@
+//! The `00_summary.json` entry stored at the top level of every cbz file (and every pool folder).
+//!
+//! It holds the metadata of all posts inside the archive, so later downloads to the same file can
+//! skip the posts that were already saved and only append the new ones.
+use std::{
+    collections::HashSet,
+    fs::{read_to_string, File},
+    io::Read,
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use ibdl_common::{
+    post::{NameType, Post},
+    serde::{self, Deserialize, Serialize},
+    serde_json, ImageBoards,
+};
+use owo_colors::OwoColorize;
+use zip::ZipArchive;
+
+use crate::error::QueueError;
+
+use super::get_counters;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct CbzSummary {
+    /// Server the posts were downloaded from.
+    pub server: String,
+    pub imageboard: ImageBoards,
+    pub name_type: NameType,
+    /// Whether the archive holds a pool. If so, the id of each post is it's position in the pool.
+    pub pool: bool,
+    pub last_updated: DateTime<Utc>,
+    /// Posts saved in the archive. New posts should be added with [`push`](Self::push), so
+    /// [`contains`](Self::contains) keeps finding them.
+    pub posts: Vec<Post>,
+    #[serde(skip)]
+    saved_md5: HashSet<String>,
+    #[serde(skip)]
+    saved_pages: HashSet<u64>,
+}
+
+impl CbzSummary {
+    pub const FILE_NAME: &'static str = "00_summary.json";
+
+    pub fn new(server: &str, imageboard: ImageBoards, name_type: NameType, pool: bool) -> Self {
+        Self {
+            server: server.to_string(),
+            imageboard,
+            name_type,
+            pool,
+            last_updated: Utc::now(),
+            posts: Vec::new(),
+            saved_md5: HashSet::new(),
+            saved_pages: HashSet::new(),
+        }
+    }
+
+    /// Reads the summary of an existing cbz file.
+    pub fn read_from(path: &Path) -> Result<Self, QueueError> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+
+        let mut raw = String::new();
+
+        archive
+            .by_name(Self::FILE_NAME)
+            .map_err(|_| QueueError::ZipSummaryReadError {
+                file: path.display().to_string(),
+            })?
+            .read_to_string(&mut raw)?;
+
+        Self::from_json(&raw)
+    }
+
+    /// Reads the summary of a pool downloaded into a folder.
+    pub fn read_from_dir(dir: &Path) -> Result<Self, QueueError> {
+        let raw = read_to_string(dir.join(Self::FILE_NAME))?;
+
+        Self::from_json(&raw)
+    }
+
+    fn from_json(raw: &str) -> Result<Self, QueueError> {
+        let mut summary: Self =
+            serde_json::from_str(raw).map_err(|error| QueueError::SummaryDeserializeFail {
+                error: error.to_string(),
+            })?;
+
+        summary.saved_md5 = summary.posts.iter().map(|p| p.md5.clone()).collect();
+        summary.saved_pages = summary.posts.iter().map(|p| p.id).collect();
+
+        Ok(summary)
+    }
+
+    /// Warns when the existing summary at `path` was written by downloads from another server,
+    /// since it's posts will be mixed with the ones from `current`.
+    pub fn check_origin(&self, current: &Self, path: &Path) -> Result<(), QueueError> {
+        if self.server == current.server && self.imageboard == current.imageboard {
+            return Ok(());
+        }
+
+        get_counters().multi.println(format!(
+            "{} {} {} {}{}",
+            "The existing".bold().yellow(),
+            path.display().bold().blue().italic(),
+            "holds posts from".bold().yellow(),
+            self.server.bold().red(),
+            format!(". Adding posts from {} to it.", current.server)
+                .bold()
+                .yellow()
+        ))?;
+
+        Ok(())
+    }
+
+    /// Whether the post was already saved in the archive.
+    ///
+    /// Pool pages are compared by their position, while other posts are compared by their MD5 hash.
+    pub fn contains(&self, post: &Post) -> bool {
+        if self.pool {
+            self.has_page(post.id)
+        } else {
+            self.saved_md5.contains(&post.md5)
+        }
+    }
+
+    /// Whether the pool page was already saved in the archive.
+    #[inline]
+    pub fn has_page(&self, page: u64) -> bool {
+        self.saved_pages.contains(&page)
+    }
+
+    /// Adds a saved post to the summary.
+    pub fn push(&mut self, post: Post) {
+        self.saved_md5.insert(post.md5.clone());
+        self.saved_pages.insert(post.id);
+        self.posts.push(post);
+    }
+
+    pub fn to_json(&mut self) -> Result<String, QueueError> {
+        self.last_updated = Utc::now();
+        self.posts.sort();
+
+        serde_json::to_string_pretty(self).map_err(|error| QueueError::SummarySerializeFail {
+            error: error.to_string(),
+        })
+    }
+}
@@ -0,0 +1,207 @@
+//! Tar (and zstd compressed tar) archives, written as a stream so they can also be sent to the
+//! standard output.
+//!
+//! They use the same layout as cbz files: rating dirs (or numbered pages for pools), captions next
+//! to each file, a `00_summary.json` and, for pools, a `ComicInfo.xml`. Since the summary is only
+//! written at the end of the stream, existing tar files are always overwritten.
+use std::{
+    fs::File,
+    io::{self, BufWriter, Stdout, Write},
+    path::Path,
+};
+
+use chrono::Utc;
+use ibdl_common::{log::debug, post::rating::Rating, post::Post};
+use ibdl_extractors::imageboards::PoolInfo;
+use tar::{Builder, EntryType, Header};
+use zstd::Encoder;
+
+use crate::error::QueueError;
+
+use super::{
+    archive::{ArchiveFormat, PostArchive},
+    comic_info::ComicInfo,
+    spool::EntryData,
+    summary::CbzSummary,
+};
+
+/// Level used for tar.zst archives. Most files are already compressed images, so higher levels
+/// are not worth the extra time.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Destination of the tar stream.
+enum TarOutput {
+    File(BufWriter<File>),
+    Stdout(BufWriter<Stdout>),
+}
+
+impl Write for TarOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.write(buf),
+            Self::Stdout(stdout) => stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::File(file) => file.flush(),
+            Self::Stdout(stdout) => stdout.flush(),
+        }
+    }
+}
+
+/// Tar stream, optionally compressed.
+enum TarWriter {
+    Plain(TarOutput),
+    Zstd(Encoder<'static, TarOutput>),
+}
+
+impl Write for TarWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(output) => output.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(output) => output.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl TarWriter {
+    /// Ends the compressed stream, if any, and flushes the output.
+    fn finish(self) -> io::Result<()> {
+        let mut output = match self {
+            Self::Plain(output) => output,
+            Self::Zstd(encoder) => encoder.finish()?,
+        };
+
+        output.flush()
+    }
+}
+
+/// Tar file posts are being written to.
+pub struct TarArchive {
+    /// `None` once the archive is finished.
+    builder: Option<Builder<TarWriter>>,
+    summary: CbzSummary,
+    comic_info: Option<ComicInfo>,
+}
+
+impl TarArchive {
+    /// Creates the archive at `path`, or writes it to the standard output if `path` is `-`.
+    pub fn create(
+        path: &Path,
+        format: ArchiveFormat,
+        summary: CbzSummary,
+        pool_info: Option<PoolInfo>,
+    ) -> Result<Self, QueueError> {
+        let output = if ArchiveFormat::is_stdout(path) {
+            debug!("Writing archive to stdout");
+            TarOutput::Stdout(BufWriter::new(io::stdout()))
+        } else {
+            debug!("Target file: {}", path.display());
+            TarOutput::File(BufWriter::new(File::create(path)?))
+        };
+
+        let writer = match format {
+            ArchiveFormat::TarZst => TarWriter::Zstd(Encoder::new(output, ZSTD_LEVEL)?),
+            _ => TarWriter::Plain(output),
+        };
+
+        let mut builder = Builder::new(writer);
+
+        if !summary.pool {
+            for rating in Rating::board_ratings(summary.imageboard) {
+                let mut header = Self::header(EntryType::Directory, 0);
+                header.set_mode(0o755);
+                builder.append_data(&mut header, format!("{rating}/"), io::empty())?;
+            }
+        }
+
+        let comic_info = pool_info.filter(|_| summary.pool).map(ComicInfo::new);
+
+        Ok(Self {
+            builder: Some(builder),
+            summary,
+            comic_info,
+        })
+    }
+
+    fn header(entry_type: EntryType, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(Utc::now().timestamp().try_into().unwrap_or_default());
+        header
+    }
+
+    fn append(&mut self, name: &str, data: &EntryData) -> Result<(), QueueError> {
+        let Some(builder) = &mut self.builder else {
+            return Ok(());
+        };
+
+        debug!("Writing {} to tar file", name);
+        let mut header = Self::header(EntryType::Regular, data.size()?);
+        builder.append_data(&mut header, name, data.reader()?)?;
+
+        Ok(())
+    }
+}
+
+impl PostArchive for TarArchive {
+    fn contains(&self, post: &Post) -> bool {
+        self.summary.contains(post)
+    }
+
+    fn next_missing_page(&self, page: u64) -> u64 {
+        let mut page = page;
+        while self.summary.has_page(page) {
+            page += 1;
+        }
+        page
+    }
+
+    fn write_post(
+        &mut self,
+        post: Post,
+        entries: &[(String, EntryData)],
+    ) -> Result<(), QueueError> {
+        for (name, data) in entries {
+            self.append(name, data)?;
+        }
+
+        if let Some(info) = &mut self.comic_info {
+            info.add_page(&post);
+            for (name, _) in entries {
+                info.add_entry(name);
+            }
+        }
+
+        self.summary.push(post);
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), QueueError> {
+        let summary = EntryData::Text(self.summary.to_json()?);
+        self.append(CbzSummary::FILE_NAME, &summary)?;
+
+        if let Some(info) = &self.comic_info {
+            let xml = EntryData::Text(info.to_xml());
+            self.append(ComicInfo::FILE_NAME, &xml)?;
+        }
+
+        if let Some(builder) = self.builder.take() {
+            builder.into_inner()?.finish()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+//! Conversion of Pixiv Ugoira zip files into animated images.
+//!
+//! Ugoira posts are downloaded as a zip with one image per frame and no timing information.
+//! When the imageboard provides the frame delays (see [`Post::frame_delays`]), the frames can be
+//! assembled into an animated WebP or APNG file that any image viewer can play.
+use std::io::{Cursor, Read, Write};
+
+use clap::ValueEnum;
+use ibdl_common::{
+    log::debug,
+    post::{error::PostError, extension::Extension, Post},
+    tokio::{fs::read, task::spawn_blocking},
+};
+use image::RgbaImage;
+use image_webp::{ColorType, WebPEncoder};
+use owo_colors::OwoColorize;
+use tempfile::TempPath;
+use zip::ZipArchive;
+
+use super::{get_counters, spool::EntryData, Queue};
+
+/// Settings for converting downloaded Ugoira posts into animated images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UgoiraConversion {
+    /// Format of the converted file
+    pub format: UgoiraFormat,
+    /// Also save the original zip along with the converted file
+    pub keep_zip: bool,
+}
+
+impl UgoiraConversion {
+    pub const fn new(format: UgoiraFormat, keep_zip: bool) -> Self {
+        Self { format, keep_zip }
+    }
+
+    /// Name of the converted file, replacing the `.zip` extension of `file_name`.
+    pub fn converted_name(&self, file_name: &str) -> String {
+        let stem = file_name.strip_suffix(".zip").unwrap_or(file_name);
+        format!("{}.{}", stem, self.format.extension())
+    }
+}
+
+/// Animated image format Ugoira posts are converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UgoiraFormat {
+    /// Lossless animated WebP
+    Webp,
+    /// Animated PNG
+    Apng,
+}
+
+impl UgoiraFormat {
+    /// File extension of the converted file.
+    #[inline]
+    pub const fn extension(&self) -> &'static str {
+        match self {
+            Self::Webp => "webp",
+            Self::Apng => "png",
+        }
+    }
+
+    /// Whether the post is an Ugoira with enough metadata to be converted.
+    #[inline]
+    pub fn can_convert(post: &Post) -> bool {
+        post.extension == Extension::Ugoira
+            && post.frame_delays.as_ref().is_some_and(|d| !d.is_empty())
+    }
+
+    /// Converts the raw Ugoira zip into an animated image, using `delays` (in milliseconds) as the
+    /// duration of each frame.
+    pub fn convert(&self, zip_data: &[u8], delays: &[u32]) -> Result<Vec<u8>, PostError> {
+        let frames = read_frames(zip_data)?;
+
+        if frames.len() != delays.len() {
+            return Err(conversion_error(format!(
+                "zip has {} frames, but {} frame delays were provided",
+                frames.len(),
+                delays.len()
+            )));
+        }
+
+        let (width, height) = frames[0].dimensions();
+
+        if frames.iter().any(|f| f.dimensions() != (width, height)) {
+            return Err(conversion_error("frames have different dimensions"));
+        }
+
+        match self {
+            Self::Webp => encode_webp(&frames, delays, width, height),
+            Self::Apng => encode_apng(&frames, delays, width, height),
+        }
+    }
+}
+
+impl Queue {
+    /// Converts a downloaded Ugoira zip in a blocking thread.
+    ///
+    /// Returns the original buffer along with the converted file, which is `None` if the conversion
+    /// failed, so the zip can still be saved instead.
+    pub(crate) async fn convert_ugoira_buffer(
+        conversion: UgoiraConversion,
+        post: &Post,
+        data: Vec<u8>,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>), PostError> {
+        let delays = post.frame_delays.clone().unwrap_or_default();
+
+        debug!("Converting Ugoira {} to {:?}", post.id, conversion.format);
+        let (data, result) = spawn_blocking(move || {
+            let result = conversion.format.convert(&data, &delays);
+            (data, result)
+        })
+        .await?;
+
+        match result {
+            Ok(converted) => Ok((data, Some(converted))),
+            Err(error) => {
+                get_counters().multi.println(format!(
+                    "{} {}: {}",
+                    "Failed to convert Ugoira of post".bold().red(),
+                    post.id.bold().red(),
+                    error
+                ))?;
+                Ok((data, None))
+            }
+        }
+    }
+
+    /// Lists the files to be written into a cbz for a spooled post as `(file name, data)`,
+    /// converting it first if it's an Ugoira.
+    pub(crate) async fn ugoira_cbz_entries(
+        conversion: Option<UgoiraConversion>,
+        post: &Post,
+        file_name: String,
+        data: TempPath,
+    ) -> Result<Vec<(String, EntryData)>, PostError> {
+        let Some(conversion) = conversion.filter(|_| UgoiraFormat::can_convert(post)) else {
+            return Ok(vec![(file_name, EntryData::Spooled(data))]);
+        };
+
+        // Every frame is decoded in memory during the conversion anyway
+        let buffer = read(&data).await?;
+
+        let (_, converted) = Self::convert_ugoira_buffer(conversion, post, buffer).await?;
+
+        let Some(converted) = converted else {
+            return Ok(vec![(file_name, EntryData::Spooled(data))]);
+        };
+
+        let mut entries = vec![(
+            conversion.converted_name(&file_name),
+            EntryData::Buffer(converted),
+        )];
+
+        if conversion.keep_zip {
+            entries.push((file_name, EntryData::Spooled(data)));
+        }
+
+        Ok(entries)
+    }
+}
+
+#[inline]
+fn conversion_error<S: ToString>(message: S) -> PostError {
+    PostError::UgoiraConversionFail {
+        message: message.to_string(),
+    }
+}
+
+/// Decodes every image in the zip, ordered by file name.
+fn read_frames(zip_data: &[u8]) -> Result<Vec<RgbaImage>, PostError> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_data)).map_err(conversion_error)?;
+
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/') && !name.ends_with(".json"))
+        .map(ToString::to_string)
+        .collect();
+    names.sort();
+
+    if names.is_empty() {
+        return Err(conversion_error("zip has no frames"));
+    }
+
+    let mut frames = Vec::with_capacity(names.len());
+
+    for name in names {
+        let mut file = archive.by_name(&name).map_err(conversion_error)?;
+        let mut buf = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut buf)?;
+
+        let frame = image::load_from_memory(&buf).map_err(conversion_error)?;
+        frames.push(frame.to_rgba8());
+    }
+
+    Ok(frames)
+}
+
+fn encode_apng(
+    frames: &[RgbaImage],
+    delays: &[u32],
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, PostError> {
+    let mut out = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(conversion_error)?;
+
+    let mut writer = encoder.write_header().map_err(conversion_error)?;
+
+    for (frame, delay) in frames.iter().zip(delays) {
+        let delay = u16::try_from(*delay).unwrap_or(u16::MAX);
+        writer
+            .set_frame_delay(delay, 1000)
+            .map_err(conversion_error)?;
+        writer
+            .write_image_data(frame.as_raw())
+            .map_err(conversion_error)?;
+    }
+
+    writer.finish().map_err(conversion_error)?;
+
+    Ok(out)
+}
+
+/// Encodes every frame as a lossless still image and wraps them in an extended WebP container
+/// with `ANIM` and `ANMF` chunks, since `image-webp` can't encode animations by itself.
+fn encode_webp(
+    frames: &[RgbaImage],
+    delays: &[u32],
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, PostError> {
+    let mut body = Vec::new();
+
+    // VP8X: animation and alpha flags, followed by the canvas size
+    let mut vp8x = vec![0x02 | 0x10, 0, 0, 0];
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    write_chunk(&mut body, b"VP8X", &vp8x)?;
+
+    // ANIM: transparent background color and infinite loop
+    write_chunk(&mut body, b"ANIM", &[0, 0, 0, 0, 0, 0])?;
+
+    for (frame, delay) in frames.iter().zip(delays) {
+        let mut still = Vec::new();
+        WebPEncoder::new(&mut still)
+            .encode(frame.as_raw(), width, height, ColorType::Rgba8)
+            .map_err(conversion_error)?;
+
+        // A still image without metadata is written as `RIFF <size> WEBP <VP8L chunk>`
+        let bitstream = still
+            .get(12..)
+            .ok_or_else(|| conversion_error("encoded frame is truncated"))?;
+
+        let mut anmf = Vec::with_capacity(16 + bitstream.len());
+        anmf.extend_from_slice(&[0; 6]); // frame offset
+        anmf.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        anmf.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        anmf.extend_from_slice(&(*delay).min(0x00FF_FFFF).to_le_bytes()[..3]);
+        anmf.push(0x02); // Don't blend with the previous frame
+        anmf.extend_from_slice(bitstream);
+
+        write_chunk(&mut body, b"ANMF", &anmf)?;
+    }
+
+    let mut out = Vec::with_capacity(body.len() + 12);
+    out.write_all(b"RIFF")?;
+    out.write_all(&(body.len() as u32 + 4).to_le_bytes())?;
+    out.write_all(b"WEBP")?;
+    out.write_all(&body)?;
+
+    Ok(out)
+}
+
+fn write_chunk(out: &mut Vec<u8>, name: &[u8; 4], data: &[u8]) -> Result<(), PostError> {
+    out.write_all(name)?;
+    out.write_all(&(data.len() as u32).to_le_bytes())?;
+    out.write_all(data)?;
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+    Ok(())
+}
@@ -0,0 +1,462 @@
+//! Destination of cbz downloads, optionally split into multiple volumes.
+//!
+//! When splitting, the archive rolls over to `name.001.cbz`, `name.002.cbz` and so on once a volume
+//! reaches the configured size or number of posts. Every volume has it's own summary (and
+//! `ComicInfo.xml` in pool mode), so each one can be read or updated by itself.
+use std::{
+    fs::{metadata, rename, File},
+    io::Write,
+    mem,
+    path::{Path, PathBuf},
+};
+
+use ibdl_common::{
+    log::debug,
+    post::{error::PostError, rating::Rating, Post},
+    ImageBoards,
+};
+use ibdl_extractors::imageboards::PoolInfo;
+use owo_colors::OwoColorize;
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::error::QueueError;
+
+use super::{
+    archive::PostArchive, comic_info::ComicInfo, get_counters, spool::EntryData,
+    summary::CbzSummary,
+};
+
+/// Limits that make a cbz download roll over into a new volume.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CbzSplit {
+    /// Max size of each volume in bytes
+    pub max_size: Option<u64>,
+    /// Max number of posts in each volume
+    pub max_posts: Option<usize>,
+}
+
+impl CbzSplit {
+    pub const fn new(max_size: Option<u64>, max_posts: Option<usize>) -> Self {
+        Self {
+            max_size,
+            max_posts,
+        }
+    }
+
+    #[inline]
+    pub const fn is_enabled(&self) -> bool {
+        self.max_size.is_some() || self.max_posts.is_some()
+    }
+
+    /// Path of the volume `number` (starting from 1) of the archive at `path`, like `name.001.cbz`.
+    pub fn volume_path(path: &Path, number: usize) -> PathBuf {
+        path.with_extension(format!("{number:03}.cbz"))
+    }
+
+    /// Whether a post of `new_size` bytes doesn't fit in a volume with `posts` posts and `size`
+    /// bytes.
+    ///
+    /// Empty volumes always take the post, so files larger than `max_size` get a volume of their own.
+    fn exceeded(&self, posts: usize, size: u64, new_size: u64) -> bool {
+        if posts == 0 {
+            return false;
+        }
+
+        self.max_posts.is_some_and(|max| posts >= max)
+            || self.max_size.is_some_and(|max| size + new_size > max)
+    }
+}
+
+/// Writer of a volume, with it's summary and the files copied from the existing archive.
+type OpenedVolume = (ZipWriter<File>, CbzSummary, Option<Vec<String>>);
+
+/// The cbz file (or current volume) posts are being written to.
+pub struct CbzArchive {
+    /// Path of the archive, also used as the base name of the volumes when splitting.
+    path: PathBuf,
+    split: CbzSplit,
+    /// Number of the current volume. Always `0` when not splitting.
+    volume: usize,
+    zip: ZipWriter<File>,
+    /// Whether the current volume is a copy of an existing archive, to be renamed once finished.
+    appending: bool,
+    /// Names of the files copied from the existing archive, until the volume is started.
+    copied_entries: Vec<String>,
+    /// Size of the files written into the current volume, not counting the zip headers.
+    size: u64,
+    /// Summary with no posts, copied into every new volume
+    blank: CbzSummary,
+    summary: CbzSummary,
+    /// Summaries of the volumes finished before the current one.
+    previous: Vec<CbzSummary>,
+    pool_info: Option<PoolInfo>,
+    comic_info: Option<ComicInfo>,
+}
+
+impl CbzArchive {
+    /// Opens the archive at `path`, appending to it if it already exists.
+    ///
+    /// When splitting, the summaries of all existing volumes are read, and new posts are appended
+    /// to the last one.
+    pub fn open(
+        path: PathBuf,
+        blank: CbzSummary,
+        split: CbzSplit,
+        pool_info: Option<PoolInfo>,
+    ) -> Result<Self, QueueError> {
+        let mut previous = Vec::new();
+        let mut volume = 0;
+
+        let volume_path = if split.is_enabled() {
+            volume = 1;
+            while CbzSplit::volume_path(&path, volume + 1).exists() {
+                let previous_path = CbzSplit::volume_path(&path, volume);
+
+                match CbzSummary::read_from(&previous_path) {
+                    Ok(summary) if summary.pool == blank.pool => previous.push(summary),
+                    Ok(_) | Err(_) => {
+                        return Err(QueueError::ZipSummaryReadError {
+                            file: previous_path.display().to_string(),
+                        })
+                    }
+                }
+
+                volume += 1;
+            }
+            CbzSplit::volume_path(&path, volume)
+        } else {
+            path.clone()
+        };
+
+        debug!("Target file: {}", volume_path.display());
+
+        let (zip, summary, copied_entries) = Self::open_volume(&volume_path, &blank)?;
+        let appending = copied_entries.is_some();
+
+        let size = if appending {
+            metadata(&volume_path)?.len()
+        } else {
+            0
+        };
+
+        let mut archive = Self {
+            path,
+            split,
+            volume,
+            zip,
+            appending,
+            copied_entries: copied_entries.unwrap_or_default(),
+            size,
+            blank,
+            summary,
+            previous,
+            pool_info,
+            comic_info: None,
+        };
+
+        archive.start_volume()?;
+
+        Ok(archive)
+    }
+
+    /// Opens a single cbz file, returning the names of the files copied when it's appending to an
+    /// existing one.
+    ///
+    /// If it already exists and has a summary, all it's entries are copied into a new archive
+    /// (without recompressing them), so new posts can be appended and the summary replaced.
+    fn open_volume(path: &Path, blank: &CbzSummary) -> Result<OpenedVolume, QueueError> {
+        if !path.exists() {
+            return Ok((ZipWriter::new(File::create(path)?), blank.clone(), None));
+        }
+
+        let summary = match CbzSummary::read_from(path) {
+            Ok(summary) if summary.pool == blank.pool => {
+                summary.check_origin(blank, path)?;
+                summary
+            }
+            Ok(_) | Err(_) => {
+                get_counters().multi.println(format!(
+                    "{} {}",
+                    "No valid summary found in".bold().yellow(),
+                    "the existing cbz file. It will be overwritten."
+                        .bold()
+                        .yellow()
+                ))?;
+                return Ok((ZipWriter::new(File::create(path)?), blank.clone(), None));
+            }
+        };
+
+        debug!(
+            "Appending to existing cbz file with {} posts",
+            summary.posts.len()
+        );
+
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let mut zip = ZipWriter::new(File::create(Self::part_path(path))?);
+        let mut copied = Vec::with_capacity(archive.len());
+
+        for idx in 0..archive.len() {
+            let entry = archive.by_index_raw(idx)?;
+
+            if entry.name() == CbzSummary::FILE_NAME || entry.name() == ComicInfo::FILE_NAME {
+                continue;
+            }
+
+            if !entry.is_dir() {
+                copied.push(entry.name().to_string());
+            }
+
+            zip.raw_copy_file(entry)?;
+        }
+
+        Ok((zip, summary, Some(copied)))
+    }
+
+    /// Writes the rating dirs into new volumes and collects the pages already inside the current one.
+    fn start_volume(&mut self) -> Result<(), QueueError> {
+        if !self.blank.pool && !self.appending {
+            Self::write_zip_structure(&mut self.zip, self.blank.imageboard)?;
+        }
+
+        let copied_entries = mem::take(&mut self.copied_entries);
+
+        self.comic_info = self
+            .pool_info
+            .clone()
+            .filter(|_| self.blank.pool)
+            .map(|info| {
+                let mut comic_info = ComicInfo::new(info);
+                if self.volume > 0 {
+                    comic_info.volume(self.volume);
+                }
+                for post in &self.summary.posts {
+                    comic_info.add_page(post);
+                }
+                for name in &copied_entries {
+                    comic_info.add_entry(name);
+                }
+                comic_info
+            });
+
+        Ok(())
+    }
+
+    fn write_zip_structure(
+        zip: &mut ZipWriter<File>,
+        imageboard: ImageBoards,
+    ) -> Result<(), QueueError> {
+        for rating in Rating::board_ratings(imageboard) {
+            zip.add_directory(rating.to_string(), FileOptions::default())?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn part_path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".part");
+        PathBuf::from(name)
+    }
+
+    #[inline]
+    fn current_path(&self) -> PathBuf {
+        if self.volume == 0 {
+            self.path.clone()
+        } else {
+            CbzSplit::volume_path(&self.path, self.volume)
+        }
+    }
+
+    /// Writes entries into the cbz, copying spooled files in small chunks.
+    fn write_entries(
+        zip: &mut ZipWriter<File>,
+        entries: &[(String, EntryData)],
+    ) -> Result<(), PostError> {
+        for (filename, data) in entries {
+            let options = match data {
+                EntryData::Text(_) => FileOptions::default()
+                    .compression_method(CompressionMethod::Deflated)
+                    .compression_level(Some(5)),
+                _ => FileOptions::default()
+                    .compression_method(CompressionMethod::Stored)
+                    .large_file(data.size()? >= u64::from(u32::MAX)),
+            };
+
+            debug!("Writing {} to cbz file", filename);
+            if let Err(error) = zip.start_file(filename, options) {
+                return Err(PostError::ZipFileWriteError {
+                    message: error.to_string(),
+                });
+            }
+
+            data.write_to(zip)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finishes the current volume and starts the next one.
+    fn next_volume(&mut self) -> Result<(), QueueError> {
+        self.finish()?;
+
+        let summary = mem::replace(&mut self.summary, self.blank.clone());
+        self.previous.push(summary);
+
+        self.volume += 1;
+        self.appending = false;
+        self.size = 0;
+
+        let path = self.current_path();
+
+        get_counters().multi.println(format!(
+            "{} {}",
+            "Starting new volume".bold().blue(),
+            path.display().bold().blue().italic()
+        ))?;
+
+        self.zip = ZipWriter::new(File::create(&path)?);
+
+        self.start_volume()
+    }
+}
+
+impl PostArchive for CbzArchive {
+    /// Whether the post was already saved in any volume of the archive.
+    fn contains(&self, post: &Post) -> bool {
+        self.summary.contains(post) || self.previous.iter().any(|s| s.contains(post))
+    }
+
+    /// First pool page starting from `page` that isn't inside the archive yet.
+    fn next_missing_page(&self, page: u64) -> u64 {
+        let has_page = |page: u64| {
+            self.summary.has_page(page) || self.previous.iter().any(|s| s.has_page(page))
+        };
+
+        let mut page = page;
+        while has_page(page) {
+            page += 1;
+        }
+        page
+    }
+
+    /// Writes the files of a downloaded post, rolling over to a new volume if they don't fit in the
+    /// current one.
+    fn write_post(
+        &mut self,
+        post: Post,
+        entries: &[(String, EntryData)],
+    ) -> Result<(), QueueError> {
+        let mut post_size = 0;
+        for (_, data) in entries {
+            post_size += data.size()?;
+        }
+
+        if self
+            .split
+            .exceeded(self.summary.posts.len(), self.size, post_size)
+        {
+            self.next_volume()?;
+        }
+
+        Self::write_entries(&mut self.zip, entries)?;
+
+        self.size += post_size;
+
+        if let Some(info) = &mut self.comic_info {
+            info.add_page(&post);
+            for (name, _) in entries {
+                info.add_entry(name);
+            }
+        }
+
+        self.summary.push(post);
+
+        Ok(())
+    }
+
+    /// Writes the summary and `ComicInfo.xml` of the current volume and closes it.
+    fn finish(&mut self) -> Result<(), QueueError> {
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(Some(5));
+
+        debug!("Writing {} to cbz file", CbzSummary::FILE_NAME);
+        let summary_json = self.summary.to_json()?;
+        self.zip.start_file(CbzSummary::FILE_NAME, options)?;
+        self.zip.write_all(summary_json.as_bytes())?;
+
+        if let Some(info) = &self.comic_info {
+            debug!("Writing {} to cbz file", ComicInfo::FILE_NAME);
+            self.zip.start_file(ComicInfo::FILE_NAME, options)?;
+            self.zip.write_all(info.to_xml().as_bytes())?;
+        }
+
+        self.zip.finish()?;
+
+        if self.appending {
+            let path = self.current_path();
+            rename(Self::part_path(&path), &path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_limits_never_split() {
+        let split = CbzSplit::default();
+
+        assert!(!split.is_enabled());
+        assert!(!split.exceeded(10_000, u64::MAX / 2, u64::MAX / 2));
+    }
+
+    #[test]
+    fn split_by_post_count() {
+        let split = CbzSplit::new(None, Some(3));
+
+        assert!(!split.exceeded(2, 0, 0));
+        assert!(split.exceeded(3, 0, 0));
+    }
+
+    #[test]
+    fn split_by_size() {
+        let split = CbzSplit::new(Some(100), None);
+
+        assert!(!split.exceeded(1, 60, 40));
+        assert!(split.exceeded(1, 60, 41));
+    }
+
+    #[test]
+    fn empty_volumes_take_any_post() {
+        let split = CbzSplit::new(Some(100), Some(1));
+
+        assert!(!split.exceeded(0, 0, 1000));
+        assert!(split.exceeded(1, 10, 10));
+    }
+
+    #[test]
+    fn either_limit_splits() {
+        let split = CbzSplit::new(Some(100), Some(5));
+
+        assert!(split.exceeded(5, 10, 10));
+        assert!(split.exceeded(2, 90, 20));
+        assert!(!split.exceeded(4, 80, 20));
+    }
+
+    #[test]
+    fn volume_paths_are_numbered() {
+        assert_eq!(
+            CbzSplit::volume_path(Path::new("out/pool.cbz"), 1),
+            Path::new("out/pool.001.cbz")
+        );
+        assert_eq!(
+            CbzSplit::volume_path(Path::new("out/pool.cbz"), 12),
+            Path::new("out/pool.012.cbz")
+        );
+    }
+}
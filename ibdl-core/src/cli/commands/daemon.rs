@@ -0,0 +1,247 @@
+use std::{
+    collections::HashSet,
+    fs::{create_dir_all, metadata, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local};
+use clap::Args;
+use ibdl_common::{
+    log::warn,
+    tokio::{
+        spawn,
+        sync::mpsc::unbounded_channel,
+        time::{sleep, Instant},
+    },
+};
+use ibdl_extractors::rate_limit::set_rate_limits;
+use owo_colors::OwoColorize;
+
+use crate::{
+    cli::{
+        download::DownloadResult,
+        job::{Job, JobFile},
+    },
+    error::CliError,
+};
+
+/// How often the job file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Args)]
+pub struct Daemon {
+    /// Read the jobs from this file instead of `jobs.toml` in the config dir
+    #[clap(long, value_parser, value_name = "PATH")]
+    pub file: Option<PathBuf>,
+}
+
+/// Jobs of the job file with the next time each one runs.
+struct Schedule {
+    jobs: Vec<(Job, DateTime<Local>)>,
+    log_dir: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Schedule {
+    fn load(path: &Path) -> Result<Self, CliError> {
+        let modified = modified_time(path);
+        let file = JobFile::read(path)?;
+
+        set_rate_limits(file.rate_limits());
+
+        let now = Local::now();
+
+        let jobs = file
+            .jobs
+            .iter()
+            .filter_map(|job| {
+                let next = job.next_run(&now);
+
+                if next.is_none() {
+                    warn!("Job {} has no schedule and will never run", job.name);
+                }
+
+                Some((job.clone(), next?))
+            })
+            .collect();
+
+        Ok(Self {
+            jobs,
+            log_dir: file.log_dir(path),
+            modified,
+        })
+    }
+
+    /// Jobs that should run at `now`, moving their next run forward.
+    fn take_due(&mut self, now: &DateTime<Local>) -> Vec<Job> {
+        let mut due = Vec::new();
+
+        self.jobs.retain_mut(|(job, next)| {
+            if *next > *now {
+                return true;
+            }
+
+            due.push(job.clone());
+
+            // Patterns like `0 0 30 2 *` never match again
+            job.next_run(now).map(|time| *next = time).is_some()
+        });
+
+        due
+    }
+
+    fn next_wake(&self) -> Option<DateTime<Local>> {
+        self.jobs.iter().map(|(_, next)| *next).min()
+    }
+}
+
+impl Daemon {
+    /// Runs the jobs on their schedules until the process is stopped.
+    ///
+    /// Jobs share the download queue and progress bars, so they are queued and run one at a time.
+    /// The job file is reloaded when it changes. If the new file is invalid, the previous jobs keep
+    /// running.
+    pub async fn run(&self) -> Result<(), CliError> {
+        let path = self.file.clone().unwrap_or_else(JobFile::default_path);
+
+        let mut schedule = Schedule::load(&path)?;
+
+        println!(
+            "{} {} {} {}",
+            "Loaded".bold(),
+            schedule.jobs.len().bold().blue(),
+            "scheduled jobs from".bold(),
+            path.display().bold().blue()
+        );
+
+        // Names of the jobs waiting in the queue or running
+        let pending = Arc::new(Mutex::new(HashSet::new()));
+
+        let (job_tx, mut job_rx) = unbounded_channel::<(Job, PathBuf)>();
+
+        let worker_pending = pending.clone();
+        spawn(async move {
+            while let Some((job, log_dir)) = job_rx.recv().await {
+                Self::run_job(&job, &log_dir).await;
+                worker_pending.lock().unwrap().remove(&job.name);
+            }
+        });
+
+        loop {
+            let now = Local::now();
+
+            for job in schedule.take_due(&now) {
+                if !pending.lock().unwrap().insert(job.name.clone()) {
+                    warn!(
+                        "Job {} is still queued or running. Skipping this run.",
+                        job.name
+                    );
+                    continue;
+                }
+
+                job_tx
+                    .send((job, schedule.log_dir.clone()))
+                    .map_err(|_| CliError::ImpossibleExecutionPath)?;
+            }
+
+            let wait = schedule
+                .next_wake()
+                .and_then(|next| (next - now).to_std().ok())
+                .map_or(RELOAD_INTERVAL, |wait| wait.min(RELOAD_INTERVAL));
+
+            sleep(wait).await;
+
+            if modified_time(&path) == schedule.modified {
+                continue;
+            }
+
+            match Schedule::load(&path) {
+                Ok(reloaded) => {
+                    println!(
+                        "{} {} {}",
+                        "Reloaded".bold(),
+                        reloaded.jobs.len().bold().blue(),
+                        "scheduled jobs".bold()
+                    );
+                    schedule = reloaded;
+                }
+                Err(error) => {
+                    warn!("Failed to reload the job file, keeping the previous jobs: {error}");
+                    schedule.modified = modified_time(&path);
+                }
+            }
+        }
+    }
+
+    async fn run_job(job: &Job, log_dir: &Path) {
+        println!("{} {}", "Running job".bold(), job.name.bold().blue());
+
+        let started = Local::now();
+        let timer = Instant::now();
+
+        let result = job.run().await;
+        let elapsed = timer.elapsed();
+
+        match &result {
+            Ok(result) => println!(
+                "{} {} {} {}",
+                "Job".bold(),
+                job.name.bold().blue(),
+                "finished:".bold(),
+                summary(result)
+            ),
+            Err(error) => println!(
+                "{} {} {} {}",
+                "Job".bold(),
+                job.name.bold().blue(),
+                "failed:".bold().red(),
+                error.to_string().red()
+            ),
+        }
+
+        if let Err(error) = write_run_log(log_dir, &job.name, started, elapsed, &result) {
+            warn!("Failed to write the run log of job {}: {error}", job.name);
+        }
+    }
+}
+
+fn summary(result: &DownloadResult) -> String {
+    format!(
+        "{} files downloaded, {} skipped, {} blacklisted, {} failed",
+        result.downloaded, result.skipped, result.blacklisted, result.failed
+    )
+}
+
+/// Appends the outcome of a run to `<log dir>/<job name>.log`.
+fn write_run_log(
+    log_dir: &Path,
+    name: &str,
+    started: DateTime<Local>,
+    elapsed: Duration,
+    result: &Result<DownloadResult, CliError>,
+) -> io::Result<()> {
+    create_dir_all(log_dir)?;
+
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_dir.join(format!("{name}.log")))?;
+
+    let started = started.format("%Y-%m-%d %H:%M:%S");
+
+    match result {
+        Ok(result) => writeln!(
+            log,
+            "[{started}] Finished in {elapsed:.1?}: {}",
+            summary(result)
+        ),
+        Err(error) => writeln!(log, "[{started}] Failed after {elapsed:.1?}: {error}"),
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    metadata(path).and_then(|meta| meta.modified()).ok()
+}
@@ -1,3 +1,8 @@
+pub mod daemon;
 pub mod pool;
+pub mod popular;
 pub mod post;
+pub mod run;
 pub mod search;
+pub mod serve;
+pub mod sync;
@@ -1,38 +1,64 @@
+use std::{collections::HashMap, path::PathBuf};
+
 use clap::Args;
 use ibdl_common::{
+    log::warn,
     post::{rating::Rating, Post},
     reqwest::Client,
-    tokio::sync::mpsc::{Sender, UnboundedSender},
+    tokio::{
+        fs, spawn,
+        sync::mpsc::{unbounded_channel, Sender, UnboundedSender},
+    },
     ImageBoards,
 };
 use ibdl_extractors::{
-    imageboards::{danbooru::DanbooruExtractor, e621::E621Extractor},
+    error::ExtractorError,
+    imageboards::{danbooru::DanbooruExtractor, e621::E621Extractor, PoolInfo},
     prelude::*,
 };
+use owo_colors::OwoColorize;
 
 use crate::{
+    async_queue::{saved_pool_pages, PoolTarget},
     cli::{extra::auth_imgboard, Cli},
     error::CliError,
     RatingArg,
 };
 
+/// Extractor set up to fetch a single pool, with the id of the pool and the channel it's posts are
+/// sent through.
+type PoolUnit<E> = (u32, E, UnboundedSender<Post>);
+
 #[derive(Debug, Args)]
 pub struct Pool {
-    /// Pool ID to download.
+    /// IDs of the pools to download.
     ///
-    /// Will always ignore `--id` and cli tags
+    /// Will always ignore `--id` and cli tags. Without `-o`, each pool is saved into
+    /// `<imageboard>/<pool name>` inside the current dir. With `-o` and more than one pool, each
+    /// pool is saved into `<output>/<pool name>`
     #[clap(
         value_parser,
-        value_name = "ID",
+        value_name = "IDs",
         conflicts_with("save_file_as_id"),
-        requires("output")
+        required_unless_present_any(["pool_file", "search"])
     )]
-    pub pool_id: u32,
+    pub pool_ids: Vec<u32>,
+
+    /// Download a list of pools from a file (one pool id per line)
+    #[clap(long = "pool-file", value_name = "FILE PATH", value_parser)]
+    pub pool_file: Option<PathBuf>,
+
+    /// Download all pools matching a search, like `name_matches=*series*`.
+    ///
+    /// Uses the `pools.json` search of the imageboard. Multiple filters can be joined with `&`, like
+    /// `name_matches=*series*&category=series`
+    #[clap(long, value_parser, value_name = "QUERY")]
+    pub search: Option<String>,
 
     /// Download pool posts in reverse order
     ///
     /// Useful when using the download limiter
-    #[clap(long = "latest", value_parser, requires("pool_id"))]
+    #[clap(long = "latest", value_parser)]
     pub latest_first: bool,
 
     /// Set a max number of posts to download.
@@ -112,61 +138,183 @@ pub struct Pool {
 impl Pool {
     #[inline]
     fn selected_ratings(&self) -> Vec<Rating> {
-        let mut ratings: Vec<Rating> = Vec::with_capacity(4);
-        if self.rating.is_empty() {
-            ratings.push(Rating::Safe);
+        RatingArg::selected_ratings(&self.rating, self.safe_mode, self.ignore_unknown)
+    }
+
+    /// Ids given in the command line and in the pool file.
+    async fn pool_ids(&self) -> Result<Vec<u32>, CliError> {
+        let mut ids = self.pool_ids.clone();
+
+        if let Some(path) = &self.pool_file {
+            let pools = fs::read_to_string(&path).await?;
+            ids.extend(pools.lines().map(str::trim).filter_map(|line| {
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+
+                line.parse::<u32>().map_or_else(
+                    |_| {
+                        warn!(
+                            "Failed to parse line {} into a pool id",
+                            line.bright_blue().bold()
+                        );
+                        None
+                    },
+                    Some,
+                )
+            }));
+        }
+
+        Ok(ids)
+    }
+
+    /// Fetches the metadata of every pool selected by id or search, without duplicates.
+    async fn find_pools<E: PoolExtract>(&self, unit: &mut E) -> Result<Vec<PoolInfo>, CliError> {
+        let mut pools: Vec<PoolInfo> = Vec::new();
+
+        for id in self.pool_ids().await? {
+            if !pools.iter().any(|pool| pool.id == id) {
+                pools.push(unit.fetch_pool_info(id).await?);
+            }
+        }
 
-            if !self.safe_mode {
-                ratings.push(Rating::Questionable);
-                ratings.push(Rating::Explicit)
+        if let Some(query) = &self.search {
+            for pool in unit.search_pools(query).await? {
+                if !pools.iter().any(|p| p.id == pool.id) {
+                    pools.push(pool);
+                }
             }
-        } else {
-            self.rating.iter().for_each(|item| ratings.push(item.0));
-        };
+        }
+
+        if pools.is_empty() {
+            return Err(CliError::NoPoolsInInput);
+        }
+
+        Ok(pools)
+    }
+
+    /// Sets up one extractor for each pool, skipping the pages that were already saved by a
+    /// previous download.
+    fn setup_pools<E: PoolExtract + Clone>(
+        &self,
+        args: &Cli,
+        unit: &E,
+        pools: Vec<PoolInfo>,
+    ) -> Result<(Vec<PoolUnit<E>>, Vec<PoolTarget>), CliError> {
+        let batch = pools.len() > 1;
+
+        let mut units = Vec::with_capacity(pools.len());
+        let mut targets = Vec::with_capacity(pools.len());
+
+        // Pools with the same title would be saved into the same place, so their id tells them apart
+        let mut name_count = HashMap::new();
+        for info in &pools {
+            *name_count
+                .entry(info.file_name().to_lowercase())
+                .or_insert(0) += 1;
+        }
+
+        for info in pools {
+            let name = if name_count[&info.file_name().to_lowercase()] > 1 {
+                info.file_name_with_id()
+            } else {
+                info.file_name()
+            };
 
-        if !self.ignore_unknown {
-            ratings.push(Rating::Unknown);
+            let output = args.pool_save_path(&info, &name, batch)?;
+
+            let mut pool_unit = unit.clone();
+            pool_unit.setup_pool_download(Some(info.id), self.latest_first);
+            pool_unit.resume_pool(saved_pool_pages(
+                &output,
+                args.archive_format(),
+                args.cbz_split(),
+                &info,
+            ));
+
+            let (channel_tx, channel_rx) = unbounded_channel();
+
+            units.push((info.id, pool_unit, channel_tx));
+            targets.push(PoolTarget {
+                info,
+                output,
+                channel: channel_rx,
+            });
         }
-        ratings
+
+        Ok((units, targets))
+    }
+
+    /// Fetches the posts of each pool one after another, so the imageboard isn't hit by all of
+    /// them at once.
+    fn fetch_pools<E: AsyncFetch + Send + 'static>(
+        &self,
+        units: Vec<PoolUnit<E>>,
+        length_tx: Sender<u64>,
+    ) -> ExtractorThreadHandle {
+        let start_page = self.start_page;
+        let limit = self.limit;
+
+        spawn(async move {
+            let batch = units.len() > 1;
+            let mut removed = 0;
+
+            for (pool_id, unit, channel_tx) in units {
+                let fetch = unit
+                    .setup_fetch_thread(channel_tx, start_page, limit, Some(length_tx.clone()))
+                    .await
+                    .map_err(|_| ExtractorError::ImpossibleBehavior)?;
+
+                match fetch {
+                    Ok(total) => removed += total,
+                    // A single empty pool shouldn't stop the others from downloading
+                    Err(ExtractorError::ZeroPosts) if batch => {
+                        warn!("Pool {} has no posts to download. Skipping.", pool_id);
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+
+            Ok(removed)
+        })
     }
 
     pub async fn init_extractor(
         &self,
         args: &Cli,
-        channel_tx: UnboundedSender<Post>,
         length_tx: Sender<u64>,
-    ) -> Result<(ExtractorThreadHandle, Client), CliError> {
+    ) -> Result<(ExtractorThreadHandle, Client, Vec<PoolTarget>), CliError> {
         let ratings = self.selected_ratings();
 
-        match args.imageboard.server {
+        match args.imageboard().server {
             ImageBoards::Danbooru => {
                 let mut unit = DanbooruExtractor::new_with_config(
                     &[""],
                     &ratings,
                     self.disable_blacklist,
                     !self.no_animated,
-                    args.imageboard.clone(),
+                    args.imageboard().clone(),
                 );
                 auth_imgboard(args.auth, &mut unit).await?;
 
+                unit.recover_hidden_posts(args.recover_hidden);
+                unit.share_hidden_counter(args.hidden_posts.clone());
+                unit.fetch_ugoira_frames(args.ugoira.is_some());
+
                 unit.exclude_tags(&self.exclude);
 
                 if let Some(ext) = args.get_extension() {
                     unit.force_extension(ext);
                 }
 
-                unit.setup_pool_download(Some(self.pool_id), self.latest_first);
+                unit.select_variant(args.variant.0);
 
                 let client = unit.client();
 
-                let ext_thd = unit.setup_fetch_thread(
-                    channel_tx,
-                    self.start_page,
-                    self.limit,
-                    Some(length_tx),
-                );
+                let pools = self.find_pools(&mut unit).await?;
+                let (units, targets) = self.setup_pools(args, &unit, pools)?;
 
-                Ok((ext_thd, client))
+                Ok((self.fetch_pools(units, length_tx), client, targets))
             }
             ImageBoards::E621 => {
                 let mut unit = E621Extractor::new_with_config(
@@ -174,29 +322,28 @@ impl Pool {
                     &ratings,
                     self.disable_blacklist,
                     !self.no_animated,
-                    args.imageboard.clone(),
+                    args.imageboard().clone(),
                 );
 
                 auth_imgboard(args.auth, &mut unit).await?;
 
+                unit.recover_hidden_posts(args.recover_hidden);
+                unit.share_hidden_counter(args.hidden_posts.clone());
+
                 unit.exclude_tags(&self.exclude);
 
                 if let Some(ext) = args.get_extension() {
                     unit.force_extension(ext);
                 }
 
-                unit.setup_pool_download(Some(self.pool_id), self.latest_first);
+                unit.select_variant(args.variant.0);
 
                 let client = unit.client();
 
-                let ext_thd = unit.setup_fetch_thread(
-                    channel_tx,
-                    self.start_page,
-                    self.limit,
-                    Some(length_tx),
-                );
+                let pools = self.find_pools(&mut unit).await?;
+                let (units, targets) = self.setup_pools(args, &unit, pools)?;
 
-                Ok((ext_thd, client))
+                Ok((self.fetch_pools(units, length_tx), client, targets))
             }
             ImageBoards::GelbooruV0_2 | ImageBoards::Gelbooru | ImageBoards::Moebooru => {
                 Err(CliError::ExtractorUnsupportedMode)
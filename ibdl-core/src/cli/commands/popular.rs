@@ -0,0 +1,200 @@
+use chrono::NaiveDate;
+use clap::Args;
+use ibdl_common::{
+    post::{rating::Rating, Post},
+    reqwest::Client,
+    tokio::sync::mpsc::{Sender, UnboundedSender},
+    ImageBoards,
+};
+use ibdl_extractors::{
+    imageboards::{
+        danbooru::DanbooruExtractor, e621::E621Extractor, moebooru::MoebooruExtractor,
+        ExtractorFeatures, PopularFetch, PopularQuery,
+    },
+    prelude::*,
+};
+
+use crate::{
+    cli::{
+        extra::{auth_imgboard, parse_date},
+        Cli,
+    },
+    error::CliError,
+    RatingArg, ScaleArg,
+};
+
+#[derive(Debug, Args)]
+pub struct Popular {
+    /// Period covered by the list of popular posts
+    #[clap(long, value_enum, default_value = "day", help_heading = "GENERAL")]
+    pub scale: ScaleArg,
+
+    /// Any date inside the period, like `2024-01-31`. Defaults to the current one
+    #[clap(long, value_name = "YYYY-MM-DD", value_parser = parse_date, help_heading = "GENERAL")]
+    pub date: Option<NaiveDate>,
+
+    /// Set a max number of posts to download.
+    ///
+    /// [max: 65535]
+    #[clap(short, long, value_parser, help_heading = "DOWNLOAD")]
+    pub limit: Option<u16>,
+
+    /// Disable blacklist filtering
+    #[clap(long, value_parser, default_value_t = false, help_heading = "GENERAL")]
+    pub disable_blacklist: bool,
+
+    /// Exclude posts with these tags
+    #[clap(short, long, value_parser, help_heading = "GENERAL")]
+    pub exclude: Vec<String>,
+
+    /// Force the extractor to only fetch posts with the selected extension
+    #[clap(long, value_parser, help_heading = "DOWNLOAD")]
+    pub force_extension: Option<String>,
+
+    /// Do not download animated gifs or video files
+    #[clap(long, value_parser, default_value_t = false, help_heading = "SAVE")]
+    pub no_animated: bool,
+
+    /// Download images from the safe version of the selected Imageboard.
+    ///
+    /// Useful if you only want to download posts with "safe" rating.
+    #[clap(long, action, default_value_t = false, help_heading = "GENERAL")]
+    pub safe_mode: bool,
+
+    /// Download posts with the selected rating. Can be used multiple times to download posts with other ratings
+    #[clap(
+        short,
+        long,
+        value_parser,
+        help_heading = "GENERAL",
+        conflicts_with("safe_mode")
+    )]
+    pub rating: Vec<RatingArg>,
+
+    /// Do not download posts with an unknown rating
+    #[clap(long, value_parser, default_value_t = false, help_heading = "SAVE")]
+    pub ignore_unknown: bool,
+}
+
+impl Popular {
+    #[inline]
+    fn selected_ratings(&self) -> Vec<Rating> {
+        RatingArg::selected_ratings(&self.rating, self.safe_mode, self.ignore_unknown)
+    }
+
+    #[inline]
+    const fn query(&self) -> PopularQuery {
+        PopularQuery {
+            scale: self.scale.0,
+            date: self.date,
+        }
+    }
+
+    pub async fn init_extractor(
+        &self,
+        args: &Cli,
+        channel_tx: UnboundedSender<Post>,
+        length_tx: Sender<u64>,
+    ) -> Result<(ExtractorThreadHandle, Client), CliError> {
+        let server = args.imageboard();
+
+        if !server
+            .extractor_features()
+            .contains(ExtractorFeatures::Popular)
+        {
+            return Err(CliError::ExtractorUnsupportedMode);
+        }
+
+        let ratings = self.selected_ratings();
+
+        match server.server {
+            ImageBoards::Danbooru => {
+                let mut unit = DanbooruExtractor::new_with_config(
+                    &[""],
+                    &ratings,
+                    self.disable_blacklist,
+                    !self.no_animated,
+                    server.clone(),
+                );
+                auth_imgboard(args.auth, &mut unit).await?;
+
+                unit.recover_hidden_posts(args.recover_hidden);
+                unit.share_hidden_counter(args.hidden_posts.clone());
+                unit.fetch_ugoira_frames(args.ugoira.is_some());
+
+                unit.exclude_tags(&self.exclude);
+
+                if let Some(ext) = args.get_extension() {
+                    unit.force_extension(ext);
+                }
+
+                unit.select_variant(args.variant.0);
+                unit.setup_popular(self.query());
+
+                let client = unit.client();
+
+                let ext_thd =
+                    unit.setup_fetch_thread(channel_tx, None, self.limit, Some(length_tx));
+
+                Ok((ext_thd, client))
+            }
+            ImageBoards::E621 => {
+                let mut unit = E621Extractor::new_with_config(
+                    &[""],
+                    &ratings,
+                    self.disable_blacklist,
+                    !self.no_animated,
+                    server.clone(),
+                );
+                auth_imgboard(args.auth, &mut unit).await?;
+
+                unit.recover_hidden_posts(args.recover_hidden);
+                unit.share_hidden_counter(args.hidden_posts.clone());
+
+                unit.exclude_tags(&self.exclude);
+
+                if let Some(ext) = args.get_extension() {
+                    unit.force_extension(ext);
+                }
+
+                unit.select_variant(args.variant.0);
+                unit.setup_popular(self.query());
+
+                let client = unit.client();
+
+                let ext_thd =
+                    unit.setup_fetch_thread(channel_tx, None, self.limit, Some(length_tx));
+
+                Ok((ext_thd, client))
+            }
+            ImageBoards::Moebooru => {
+                let mut unit = MoebooruExtractor::new_with_config(
+                    &[""],
+                    &ratings,
+                    self.disable_blacklist,
+                    !self.no_animated,
+                    server.clone(),
+                );
+
+                unit.exclude_tags(&self.exclude);
+
+                if let Some(ext) = args.get_extension() {
+                    unit.force_extension(ext);
+                }
+
+                unit.select_variant(args.variant.0);
+                unit.setup_popular(self.query());
+
+                let client = unit.client();
+
+                let ext_thd =
+                    unit.setup_fetch_thread(channel_tx, None, self.limit, Some(length_tx));
+
+                Ok((ext_thd, client))
+            }
+            ImageBoards::Gelbooru | ImageBoards::GelbooruV0_2 => {
+                Err(CliError::ExtractorUnsupportedMode)
+            }
+        }
+    }
+}
@@ -50,17 +50,23 @@ impl Post {
         channel_tx: UnboundedSender<Pst>,
         length_tx: Sender<u64>,
     ) -> Result<(ExtractorThreadHandle, Client), CliError> {
-        match args.imageboard.server {
+        match args.imageboard().server {
             ImageBoards::Danbooru => {
                 let mut unit = DanbooruExtractor::new_with_config(
                     &[""],
                     &[],
                     true,
                     true,
-                    args.imageboard.clone(),
+                    args.imageboard().clone(),
                 );
                 auth_imgboard(args.auth, &mut unit).await?;
 
+                unit.recover_hidden_posts(args.recover_hidden);
+                unit.share_hidden_counter(args.hidden_posts.clone());
+                unit.fetch_ugoira_frames(args.ugoira.is_some());
+
+                unit.select_variant(args.variant.0);
+
                 let client = unit.client();
 
                 let ext_thd = {
@@ -102,10 +108,20 @@ impl Post {
                 Ok((ext_thd, client))
             }
             ImageBoards::E621 => {
-                let mut unit =
-                    E621Extractor::new_with_config(&[""], &[], true, true, args.imageboard.clone());
+                let mut unit = E621Extractor::new_with_config(
+                    &[""],
+                    &[],
+                    true,
+                    true,
+                    args.imageboard().clone(),
+                );
                 auth_imgboard(args.auth, &mut unit).await?;
 
+                unit.recover_hidden_posts(args.recover_hidden);
+                unit.share_hidden_counter(args.hidden_posts.clone());
+
+                unit.select_variant(args.variant.0);
+
                 let client = unit.client();
                 let ext_thd = {
                     if !self.posts.is_empty() {
@@ -146,14 +162,16 @@ impl Post {
                 Ok((ext_thd, client))
             }
             ImageBoards::GelbooruV0_2 | ImageBoards::Gelbooru => {
-                let unit = GelbooruExtractor::new_with_config(
+                let mut unit = GelbooruExtractor::new_with_config(
                     &[""],
                     &[],
                     true,
                     true,
-                    args.imageboard.clone(),
+                    args.imageboard().clone(),
                 );
 
+                unit.select_variant(args.variant.0);
+
                 let client = unit.client();
                 let ext_thd = {
                     if !self.posts.is_empty() {
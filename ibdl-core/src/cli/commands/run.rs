@@ -0,0 +1,115 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::Args;
+use futures::{stream, StreamExt};
+use ibdl_common::tokio::time::Instant;
+use ibdl_extractors::rate_limit::set_rate_limits;
+use owo_colors::OwoColorize;
+
+use crate::{
+    cli::{
+        download::DownloadResult,
+        job::{Job, JobFile},
+    },
+    error::CliError,
+};
+
+#[derive(Debug, Args)]
+pub struct Run {
+    /// Job file to run, in the same format as `jobs.toml`
+    #[clap(value_parser, value_name = "PATH")]
+    pub file: PathBuf,
+
+    /// Names of the jobs to run. All of them run when empty
+    #[clap(value_parser, value_name = "NAMES")]
+    pub names: Vec<String>,
+
+    /// Number of jobs that run at the same time
+    ///
+    /// Each job still downloads up to it's own number of simultaneous downloads
+    #[clap(
+        long,
+        value_name = "NUMBER",
+        value_parser(clap::value_parser!(u8).range(1..=20)),
+        default_value_t = 1
+    )]
+    pub concurrent: u8,
+}
+
+/// Outcome of a single job.
+#[derive(Debug)]
+pub struct RunReport {
+    pub name: String,
+    pub mode: &'static str,
+    pub elapsed: Duration,
+    pub result: Result<DownloadResult, CliError>,
+    /// Whether the job wrote it's archive to stdout, so nothing else should be printed there.
+    pub to_stdout: bool,
+}
+
+impl Run {
+    /// Runs every selected job once, ignoring their schedules.
+    ///
+    /// A failed job doesn't stop the others. Reports are returned in the same order as the jobs
+    /// in the file.
+    pub async fn run(&self) -> Result<Vec<RunReport>, CliError> {
+        let file = JobFile::read(&self.file)?;
+
+        set_rate_limits(file.rate_limits());
+
+        let selected = file
+            .jobs
+            .into_iter()
+            .filter(|job| self.names.is_empty() || self.names.contains(&job.name))
+            .collect::<Vec<_>>();
+
+        if selected.is_empty() {
+            return Err(CliError::NoJobs);
+        }
+
+        let stdout_jobs = selected
+            .iter()
+            .filter(|job| job.output_to_stdout())
+            .map(|job| job.name.as_str())
+            .collect::<Vec<_>>();
+
+        if stdout_jobs.len() > 1 {
+            return Err(CliError::ManyStdoutJobs {
+                names: stdout_jobs.join(", "),
+            });
+        }
+
+        // Keep the standard output clean for the job writing it's archive to it
+        let quiet = !stdout_jobs.is_empty();
+
+        let reports = stream::iter(selected)
+            .map(|job| Self::run_job(job, quiet))
+            .buffered(self.concurrent.into())
+            .collect()
+            .await;
+
+        Ok(reports)
+    }
+
+    async fn run_job(job: Job, quiet: bool) -> RunReport {
+        let message = format!("{} {}", "Running job".bold(), job.name.bold().blue());
+
+        if quiet {
+            eprintln!("{message}");
+        } else {
+            println!("{message}");
+        }
+
+        let to_stdout = job.output_to_stdout();
+        let timer = Instant::now();
+        let result = job.run().await;
+
+        RunReport {
+            mode: job.task.mode(),
+            name: job.name,
+            elapsed: timer.elapsed(),
+            result,
+            to_stdout,
+        }
+    }
+}
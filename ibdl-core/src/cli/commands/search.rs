@@ -1,18 +1,24 @@
+use std::num::NonZeroU32;
+
 use clap::Args;
 use ibdl_common::{
     post::{rating::Rating, Post},
     reqwest::Client,
-    tokio::sync::mpsc::{Sender, UnboundedSender},
+    tokio::sync::mpsc::{unbounded_channel, Sender, UnboundedSender},
     ImageBoards,
 };
 use ibdl_extractors::imageboards::{
     danbooru::DanbooruExtractor, e621::E621Extractor, gelbooru::GelbooruExtractor,
     moebooru::MoebooruExtractor,
 };
-use ibdl_extractors::prelude::*;
+use ibdl_extractors::{extractor_config::ServerConfig, prelude::*};
 
 use crate::{
-    cli::{extra::auth_imgboard, Cli},
+    cli::{
+        extra::auth_imgboard,
+        merge::{merge_sources, PostSource},
+        Cli,
+    },
     error::CliError,
     RatingArg,
 };
@@ -20,9 +26,21 @@ use crate::{
 #[derive(Debug, Args)]
 pub struct TagSearch {
     /// Tags to search
-    #[clap(value_parser, required = true)]
+    #[clap(value_parser, required_unless_present = "query")]
     pub tags: Vec<String>,
 
+    /// Search each query separately and download the posts found by any of them.
+    ///
+    /// Can be used multiple times, like `--query "a b" --query "c d"`. Posts found by more than one query are only downloaded once
+    #[clap(
+        long,
+        value_parser,
+        value_name = "TAGS",
+        conflicts_with = "tags",
+        help_heading = "GENERAL"
+    )]
+    pub query: Vec<String>,
+
     /// Set a max number of posts to download.
     ///
     /// [max: 1000]
@@ -45,6 +63,12 @@ pub struct TagSearch {
     )]
     pub start_page: Option<u16>,
 
+    /// Stop scanning after this many posts in a row were already downloaded.
+    ///
+    /// Useful to update a previous download of the same tags without going through every page.
+    #[clap(long, value_parser, help_heading = "DOWNLOAD", value_name = "N")]
+    pub abort_on_existing: Option<NonZeroU32>,
+
     /// Exclude posts with these tags
     #[clap(short, long, value_parser, help_heading = "GENERAL")]
     pub exclude: Vec<String>,
@@ -100,42 +124,120 @@ pub struct TagSearch {
 impl TagSearch {
     #[inline]
     fn selected_ratings(&self) -> Vec<Rating> {
-        let mut ratings: Vec<Rating> = Vec::with_capacity(4);
-        if self.rating.is_empty() {
-            ratings.push(Rating::Safe);
+        RatingArg::selected_ratings(&self.rating, self.safe_mode, self.ignore_unknown)
+    }
 
-            if !self.safe_mode {
-                ratings.push(Rating::Questionable);
-                ratings.push(Rating::Explicit)
-            }
+    pub async fn init_extractor(
+        &self,
+        args: &Cli,
+        channel_tx: UnboundedSender<Post>,
+        length_tx: Sender<u64>,
+        abort_signal: Option<AbortSignal>,
+    ) -> Result<(ExtractorThreadHandle, Client, Vec<(ServerConfig, Client)>), CliError> {
+        if self.query.is_empty() && !args.cross_server() {
+            let (ext, client) = self
+                .spawn_extractor(
+                    args,
+                    args.imageboard(),
+                    &self.tags,
+                    args.auth,
+                    channel_tx,
+                    Some(length_tx),
+                    abort_signal,
+                )
+                .await?;
+
+            return Ok((ext, client, Vec::new()));
+        }
+
+        let queries = if self.query.is_empty() {
+            vec![self.tags.clone()]
         } else {
-            self.rating.iter().for_each(|item| ratings.push(item.0));
+            self.query
+                .iter()
+                .map(|query| {
+                    let tags = query
+                        .split_whitespace()
+                        .map(String::from)
+                        .collect::<Vec<_>>();
+
+                    if tags.is_empty() {
+                        return Err(CliError::EmptyQuery);
+                    }
+
+                    Ok(tags)
+                })
+                .collect::<Result<Vec<_>, _>>()?
         };
 
-        if !self.ignore_unknown {
-            ratings.push(Rating::Unknown);
+        let mut sources = Vec::with_capacity(args.imageboards.len() * queries.len());
+        // Files are downloaded with the client (and user agent) of the server they were found in
+        let mut clients = Vec::with_capacity(args.imageboards.len());
+
+        for server in &args.imageboards {
+            for (idx, tags) in queries.iter().enumerate() {
+                let (query_tx, query_rx) = unbounded_channel();
+
+                // Only ask for the credentials of each server once
+                let (ext, query_client) = self
+                    .spawn_extractor(
+                        args,
+                        server,
+                        tags,
+                        args.auth && idx == 0,
+                        query_tx,
+                        None,
+                        abort_signal.clone(),
+                    )
+                    .await?;
+
+                if idx == 0 {
+                    clients.push((server.clone(), query_client));
+                }
+
+                sources.push(PostSource {
+                    thread: ext,
+                    channel: query_rx,
+                    server: server.name.clone(),
+                });
+            }
         }
-        ratings
+
+        let ext = merge_sources(sources, channel_tx, length_tx, self.limit);
+
+        let (_, client) = clients.remove(0);
+
+        Ok((ext, client, clients))
     }
 
-    pub async fn init_extractor(
+    /// Starts an extractor searching for `tags` in `server`.
+    #[allow(clippy::too_many_arguments)]
+    async fn spawn_extractor(
         &self,
         args: &Cli,
+        server: &ServerConfig,
+        tags: &[String],
+        ask_auth: bool,
         channel_tx: UnboundedSender<Post>,
-        length_tx: Sender<u64>,
+        length_tx: Option<Sender<u64>>,
+        abort_signal: Option<AbortSignal>,
     ) -> Result<(ExtractorThreadHandle, Client), CliError> {
         let ratings = self.selected_ratings();
 
-        match args.imageboard.server {
+        match server.server {
             ImageBoards::Danbooru => {
                 let mut unit = DanbooruExtractor::new_with_config(
-                    &self.tags,
+                    tags,
                     &ratings,
                     self.disable_blacklist,
                     !self.no_animated,
-                    args.imageboard.clone(),
+                    server.clone(),
                 );
-                auth_imgboard(args.auth, &mut unit).await?;
+                auth_imgboard(ask_auth, &mut unit).await?;
+
+                unit.recover_hidden_posts(args.recover_hidden);
+                unit.share_hidden_counter(args.hidden_posts.clone());
+                unit.fetch_ugoira_frames(args.ugoira.is_some());
 
                 unit.exclude_tags(&self.exclude);
 
@@ -143,26 +245,28 @@ impl TagSearch {
                     unit.force_extension(ext);
                 }
 
+                unit.select_variant(args.variant.0);
+                unit.abort_signal(abort_signal);
+
                 let client = unit.client();
 
-                let ext_thd = unit.setup_fetch_thread(
-                    channel_tx,
-                    self.start_page,
-                    self.limit,
-                    Some(length_tx),
-                );
+                let ext_thd =
+                    unit.setup_fetch_thread(channel_tx, self.start_page, self.limit, length_tx);
 
                 Ok((ext_thd, client))
             }
             ImageBoards::E621 => {
                 let mut unit = E621Extractor::new_with_config(
-                    &self.tags,
+                    tags,
                     &ratings,
                     self.disable_blacklist,
                     !self.no_animated,
-                    args.imageboard.clone(),
+                    server.clone(),
                 );
-                auth_imgboard(args.auth, &mut unit).await?;
+                auth_imgboard(ask_auth, &mut unit).await?;
+
+                unit.recover_hidden_posts(args.recover_hidden);
+                unit.share_hidden_counter(args.hidden_posts.clone());
 
                 unit.exclude_tags(&self.exclude);
 
@@ -170,24 +274,23 @@ impl TagSearch {
                     unit.force_extension(ext);
                 }
 
+                unit.select_variant(args.variant.0);
+                unit.abort_signal(abort_signal);
+
                 let client = unit.client();
 
-                let ext_thd = unit.setup_fetch_thread(
-                    channel_tx,
-                    self.start_page,
-                    self.limit,
-                    Some(length_tx),
-                );
+                let ext_thd =
+                    unit.setup_fetch_thread(channel_tx, self.start_page, self.limit, length_tx);
 
                 Ok((ext_thd, client))
             }
             ImageBoards::GelbooruV0_2 | ImageBoards::Gelbooru => {
                 let mut unit = GelbooruExtractor::new_with_config(
-                    &self.tags,
+                    tags,
                     &ratings,
                     self.disable_blacklist,
                     !self.no_animated,
-                    args.imageboard.clone(),
+                    server.clone(),
                 );
 
                 unit.exclude_tags(&self.exclude);
@@ -196,25 +299,27 @@ impl TagSearch {
                     unit.force_extension(ext);
                 }
 
+                unit.select_variant(args.variant.0);
+                unit.abort_signal(abort_signal);
+
                 let client = unit.client();
 
-                let ext_thd = unit.setup_fetch_thread(
-                    channel_tx,
-                    self.start_page,
-                    self.limit,
-                    Some(length_tx),
-                );
+                let ext_thd =
+                    unit.setup_fetch_thread(channel_tx, self.start_page, self.limit, length_tx);
 
                 Ok((ext_thd, client))
             }
             ImageBoards::Moebooru => {
                 let mut unit = MoebooruExtractor::new_with_config(
-                    &self.tags,
+                    tags,
                     &ratings,
                     self.disable_blacklist,
                     !self.no_animated,
-                    args.imageboard.clone(),
+                    server.clone(),
                 );
+                unit.select_variant(args.variant.0);
+                unit.abort_signal(abort_signal);
+
                 let client = unit.client();
 
                 unit.exclude_tags(&self.exclude);
@@ -223,12 +328,8 @@ impl TagSearch {
                     unit.force_extension(ext);
                 }
 
-                let ext_thd = unit.setup_fetch_thread(
-                    channel_tx,
-                    self.start_page,
-                    self.limit,
-                    Some(length_tx),
-                );
+                let ext_thd =
+                    unit.setup_fetch_thread(channel_tx, self.start_page, self.limit, length_tx);
 
                 Ok((ext_thd, client))
             }
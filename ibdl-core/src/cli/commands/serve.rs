@@ -0,0 +1,446 @@
+//! Small JSON API to queue downloads from other programs.
+//!
+//! | Route               | Description                                  |
+//! |---------------------|----------------------------------------------|
+//! | `GET /jobs`         | Lists every submitted job                    |
+//! | `POST /jobs`        | Submits a search, pool or post job           |
+//! | `GET /jobs/{id}`    | Status, progress and result of a job         |
+//! | `DELETE /jobs/{id}` | Cancels a queued or running job              |
+//!
+//! Jobs are submitted in the same format as the jobs of `jobs.toml`, like
+//! `{"mode": "search", "server": "danbooru", "tags": ["cat_ears"], "limit": 50}`, and run one at a
+//! time. Their `output` must be a relative path, which is kept inside the dir the API was started in.
+//!
+//! With a token, every request must send it as `Authorization: Bearer <token>`. Without one, the
+//! API only listens on loopback addresses.
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::Component,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Local};
+use clap::Args;
+use ibdl_common::{
+    serde::{self, Deserialize, Serialize},
+    serde_json::json,
+    tokio::{
+        spawn,
+        sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        task::{AbortHandle, JoinError, JoinHandle},
+    },
+};
+use owo_colors::OwoColorize;
+
+use crate::{
+    async_queue::{QueueStats, STDOUT_PATH},
+    cli::{
+        download::DownloadResult,
+        job::{Job, JobTask},
+    },
+    error::CliError,
+};
+
+#[derive(Debug, Args)]
+pub struct Serve {
+    /// Address the API listens on
+    #[clap(
+        long,
+        value_parser,
+        value_name = "ADDRESS",
+        default_value = "127.0.0.1:8420"
+    )]
+    pub listen: SocketAddr,
+
+    /// Token every request must send as `Authorization: Bearer <TOKEN>`.
+    ///
+    /// Required to listen on addresses other than loopback ones
+    #[clap(long, value_parser, value_name = "TOKEN", env = "IBDL_API_TOKEN")]
+    pub token: Option<String>,
+}
+
+type SharedState = Arc<ServerState>;
+
+#[derive(Debug)]
+struct ServerState {
+    jobs: Mutex<BTreeMap<u64, SubmittedJob>>,
+    next_id: AtomicU64,
+    queue: UnboundedSender<u64>,
+    token: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(crate = "self::serde", rename_all = "lowercase")]
+enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug)]
+struct SubmittedJob {
+    job: Job,
+    status: JobStatus,
+    submitted_at: DateTime<Local>,
+    started_at: Option<DateTime<Local>>,
+    finished_at: Option<DateTime<Local>>,
+    result: Option<DownloadResult>,
+    error: Option<String>,
+    task: Option<AbortHandle>,
+    /// Counts of the download of this job, only set once it started.
+    stats: Option<Arc<QueueStats>>,
+}
+
+/// Body of `POST /jobs`.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "self::serde")]
+struct JobRequest {
+    name: Option<String>,
+    #[serde(flatten)]
+    task: JobTask,
+}
+
+/// A job as returned by the API.
+#[derive(Debug, Serialize)]
+#[serde(crate = "self::serde")]
+struct JobInfo {
+    id: u64,
+    name: String,
+    mode: &'static str,
+    status: JobStatus,
+    submitted_at: DateTime<Local>,
+    started_at: Option<DateTime<Local>>,
+    finished_at: Option<DateTime<Local>>,
+    /// Only set while the job is running.
+    progress: Option<JobProgress>,
+    result: Option<DownloadResult>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "self::serde")]
+struct JobProgress {
+    /// Posts downloaded, skipped or failed so far.
+    processed: u64,
+    /// Posts found so far. Grows while the search is running.
+    total: u64,
+    downloaded: u64,
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl SubmittedJob {
+    fn info(&self, id: u64) -> JobInfo {
+        let progress = self
+            .stats
+            .as_ref()
+            .filter(|_| self.status == JobStatus::Running)
+            .map(|stats| {
+                let result = stats.result();
+
+                JobProgress {
+                    processed: result.downloaded + result.skipped + result.failed,
+                    total: stats.found(),
+                    downloaded: result.downloaded,
+                }
+            });
+
+        JobInfo {
+            id,
+            name: self.job.name.clone(),
+            mode: self.job.task.mode(),
+            status: self.status,
+            submitted_at: self.submitted_at,
+            started_at: self.started_at,
+            finished_at: self.finished_at,
+            progress,
+            result: self.result,
+            error: self.error.clone(),
+        }
+    }
+}
+
+impl ServerState {
+    /// Runs `action` on a job while holding the lock, or returns a 404 error if it doesn't exist.
+    fn with_job<T>(
+        &self,
+        id: u64,
+        action: impl FnOnce(&mut SubmittedJob) -> T,
+    ) -> Result<T, ApiError> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .map(action)
+            .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("Job {id} not found")))
+    }
+
+    fn info(&self, id: u64) -> Result<JobInfo, ApiError> {
+        self.with_job(id, |entry| entry.info(id))
+    }
+
+    /// Starts the job if it wasn't cancelled while queued.
+    fn start(&self, id: u64) -> Option<JoinHandle<Result<DownloadResult, CliError>>> {
+        self.with_job(id, |entry| {
+            if entry.status != JobStatus::Queued {
+                return None;
+            }
+
+            let job = entry.job.clone();
+            let stats = Arc::new(QueueStats::default());
+            entry.stats = Some(stats.clone());
+
+            let task = spawn(async move { job.run_with_stats(stats).await });
+
+            entry.status = JobStatus::Running;
+            entry.started_at = Some(Local::now());
+            entry.task = Some(task.abort_handle());
+
+            Some(task)
+        })
+        .ok()
+        .flatten()
+    }
+
+    fn finish(&self, id: u64, outcome: Result<Result<DownloadResult, CliError>, JoinError>) {
+        let _ = self.with_job(id, |entry| {
+            entry.finished_at = Some(Local::now());
+
+            match outcome {
+                Ok(Ok(result)) => {
+                    entry.status = JobStatus::Finished;
+                    entry.result = Some(result);
+                }
+                Ok(Err(error)) => {
+                    entry.status = JobStatus::Failed;
+                    entry.error = Some(error.to_string());
+                }
+                Err(error) if error.is_cancelled() => entry.status = JobStatus::Cancelled,
+                Err(error) => {
+                    entry.status = JobStatus::Failed;
+                    entry.error = Some(error.to_string());
+                }
+            }
+        });
+    }
+}
+
+impl Serve {
+    /// Serves the API until the process is stopped.
+    pub async fn run(&self) -> Result<(), CliError> {
+        if self.token.is_none() && !self.listen.ip().is_loopback() {
+            return Err(CliError::PublicServerWithoutToken {
+                address: self.listen,
+            });
+        }
+
+        let (queue_tx, queue_rx) = unbounded_channel();
+
+        let state = Arc::new(ServerState {
+            jobs: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            queue: queue_tx,
+            token: self.token.clone(),
+        });
+
+        spawn(run_queue(state.clone(), queue_rx));
+
+        let app = Router::new()
+            .route("/jobs", get(list_jobs).post(submit_job))
+            .route("/jobs/:id", get(get_job).delete(cancel_job))
+            .route_layer(middleware::from_fn_with_state(state.clone(), check_token))
+            .with_state(state);
+
+        let server =
+            axum::Server::try_bind(&self.listen).map_err(|error| CliError::ServerFail {
+                message: error.to_string(),
+            })?;
+
+        println!(
+            "{} {}",
+            "Listening on".bold(),
+            format!("http://{}", self.listen).bold().blue()
+        );
+
+        server
+            .serve(app.into_make_service())
+            .await
+            .map_err(|error| CliError::ServerFail {
+                message: error.to_string(),
+            })
+    }
+}
+
+/// Runs the submitted jobs one at a time, since they share the download queue and progress bars.
+async fn run_queue(state: SharedState, mut queue: UnboundedReceiver<u64>) {
+    while let Some(id) = queue.recv().await {
+        let Some(task) = state.start(id) else {
+            continue;
+        };
+
+        let outcome = task.await;
+        state.finish(id, outcome);
+    }
+}
+
+/// Refuses requests without the token of the server, if it has one.
+async fn check_token<B>(
+    State(state): State<SharedState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ApiError> {
+    if let Some(token) = &state.token {
+        let sent = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        if sent != Some(token.as_str()) {
+            return Err(ApiError(
+                StatusCode::UNAUTHORIZED,
+                String::from("Missing or wrong API token"),
+            ));
+        }
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Jobs always overwrite existing files, so they can only write inside the dir the API was started in.
+fn check_output(task: &JobTask) -> Result<(), ApiError> {
+    let save = match task {
+        JobTask::Search(job) => &job.save,
+        JobTask::Pool(job) => &job.save,
+        JobTask::Post(job) => &job.save,
+        JobTask::Sync(_) => return Ok(()),
+    };
+
+    let Some(output) = &save.output else {
+        return Ok(());
+    };
+
+    let inside = output
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    if !inside || output.as_os_str().is_empty() || output.as_os_str() == STDOUT_PATH {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Output {} must be a relative path inside the dir of the API",
+                output.display()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn list_jobs(State(state): State<SharedState>) -> Json<Vec<JobInfo>> {
+    let jobs = state.jobs.lock().unwrap();
+
+    Json(jobs.iter().map(|(id, job)| job.info(*id)).collect())
+}
+
+async fn submit_job(
+    State(state): State<SharedState>,
+    Json(request): Json<JobRequest>,
+) -> Result<(StatusCode, Json<JobInfo>), ApiError> {
+    if matches!(request.task, JobTask::Sync(_)) {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            String::from("Only search, pool and post jobs can be submitted"),
+        ));
+    }
+
+    check_output(&request.task)?;
+
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed);
+
+    let job = Job {
+        name: request.name.unwrap_or_else(|| format!("job-{id}")),
+        schedule: None,
+        task: request.task,
+    };
+
+    job.to_cli()
+        .map_err(|error| ApiError(StatusCode::BAD_REQUEST, error.to_string()))?;
+
+    state.jobs.lock().unwrap().insert(
+        id,
+        SubmittedJob {
+            job,
+            status: JobStatus::Queued,
+            submitted_at: Local::now(),
+            started_at: None,
+            finished_at: None,
+            result: None,
+            error: None,
+            task: None,
+            stats: None,
+        },
+    );
+
+    state
+        .queue
+        .send(id)
+        .map_err(|error| ApiError(StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(state.info(id)?)))
+}
+
+async fn get_job(
+    State(state): State<SharedState>,
+    Path(id): Path<u64>,
+) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.info(id)?))
+}
+
+/// Cancels a job. Running jobs are reported as cancelled once their downloads stop.
+async fn cancel_job(
+    State(state): State<SharedState>,
+    Path(id): Path<u64>,
+) -> Result<Json<JobInfo>, ApiError> {
+    state.with_job(id, |entry| {
+        match entry.status {
+            JobStatus::Queued => {
+                entry.status = JobStatus::Cancelled;
+                entry.finished_at = Some(Local::now());
+            }
+            JobStatus::Running => {
+                if let Some(task) = &entry.task {
+                    task.abort();
+                }
+            }
+            JobStatus::Finished | JobStatus::Failed | JobStatus::Cancelled => {
+                return Err(ApiError(
+                    StatusCode::CONFLICT,
+                    format!("Job {id} already ended"),
+                ));
+            }
+        }
+
+        Ok(Json(entry.info(id)))
+    })?
+}
@@ -0,0 +1,307 @@
+use std::{
+    path::PathBuf,
+    sync::{atomic::AtomicU64, Arc},
+};
+
+use clap::Args;
+use ibdl_common::{
+    log::warn,
+    post::Post,
+    reqwest::Client,
+    tokio::{
+        join,
+        sync::mpsc::{channel, unbounded_channel, Sender, UnboundedSender},
+    },
+    ImageBoards,
+};
+use ibdl_extractors::{
+    error::ExtractorError,
+    imageboards::{
+        danbooru::DanbooruExtractor, e621::E621Extractor, gelbooru::GelbooruExtractor,
+        moebooru::MoebooruExtractor, AbortSignal, HiddenPostCounter,
+    },
+    prelude::*,
+};
+
+use crate::{
+    async_queue::{NewPostLimit, Queue},
+    cli::{
+        extra::auth_imgboard,
+        subscription::{Subscription, SubscriptionFile, SyncState},
+        Cli,
+    },
+    error::CliError,
+};
+
+#[derive(Debug, Args)]
+pub struct Sync {
+    /// Names of the subscriptions to sync. All of them are synced when empty
+    #[clap(value_parser, value_name = "NAMES")]
+    pub names: Vec<String>,
+
+    /// Read the subscriptions from this file instead of `subscriptions.toml` in the config dir
+    #[clap(long, value_parser, value_name = "PATH")]
+    pub file: Option<PathBuf>,
+}
+
+/// Posts found by the sync of a single subscription.
+#[derive(Debug)]
+pub struct SyncResult {
+    pub downloaded: u64,
+    pub skipped: u64,
+    pub blacklisted: u64,
+    pub failed: u64,
+    /// Posts whose file url was hidden by the imageboard.
+    pub hidden: u64,
+    /// Hidden posts whose file url was rebuilt from their MD5 hash.
+    pub recovered: u64,
+    /// Highest post id saved so far, with no failed posts before it.
+    pub last_post_id: Option<u64>,
+}
+
+/// Outcome of syncing a single subscription.
+#[derive(Debug)]
+pub struct SyncReport {
+    pub name: String,
+    pub server: String,
+    pub result: Result<SyncResult, CliError>,
+}
+
+impl Sync {
+    /// Syncs every selected subscription, one after another.
+    ///
+    /// A failed subscription doesn't stop the others, and it's state is left untouched, so the
+    /// same posts are tried again in the next sync.
+    pub async fn run(&self, args: &Cli) -> Result<Vec<SyncReport>, CliError> {
+        let path = self
+            .file
+            .clone()
+            .unwrap_or_else(SubscriptionFile::default_path);
+
+        let file = SubscriptionFile::read(&path)?;
+
+        let state_path = SyncState::path(&path);
+        let mut state = SyncState::read(&state_path)?;
+
+        let selected = file
+            .subscriptions
+            .into_iter()
+            .filter(|sub| self.names.is_empty() || self.names.contains(&sub.name))
+            .collect::<Vec<_>>();
+
+        if selected.is_empty() {
+            return Err(CliError::NoSubscriptions);
+        }
+
+        let mut reports = Vec::with_capacity(selected.len());
+
+        for sub in selected {
+            let result = Self::sync(args, &sub, state.last_post_id(&sub.name)).await;
+
+            if let Ok(SyncResult {
+                last_post_id: Some(last_id),
+                ..
+            }) = &result
+            {
+                state.update(&sub.name, *last_id);
+                state.save(&state_path)?;
+            }
+
+            reports.push(SyncReport {
+                name: sub.name,
+                server: sub.server,
+                result,
+            });
+        }
+
+        Ok(reports)
+    }
+
+    /// Downloads the posts of a subscription that are newer than `last_post_id`.
+    async fn sync(
+        args: &Cli,
+        sub: &Subscription,
+        last_post_id: Option<u64>,
+    ) -> Result<SyncResult, CliError> {
+        let server = sub.server_config()?;
+        let ratings = sub.selected_ratings()?;
+
+        let (channel_tx, channel_rx) = unbounded_channel::<Post>();
+        let (length_tx, length_rx) = channel(args.simultaneous_downloads as usize);
+        let hidden_posts = Arc::new(HiddenPostCounter::default());
+
+        // Only the new posts count, so a later sync gets past the posts saved by this one
+        let (new_post_limit, limit_signal) = sub
+            .limit
+            .map(|limit| NewPostLimit::new(limit.into()))
+            .unzip();
+        let new_post_limit = new_post_limit.map(Arc::new);
+
+        let (ext, client) = match server.server {
+            ImageBoards::Danbooru => {
+                let mut unit = DanbooruExtractor::new_with_config(
+                    &sub.tags,
+                    &ratings,
+                    sub.disable_blacklist,
+                    !sub.no_animated,
+                    server.clone(),
+                );
+                auth_imgboard(false, &mut unit).await?;
+
+                unit.recover_hidden_posts(args.recover_hidden)
+                    .share_hidden_counter(hidden_posts.clone());
+                unit.fetch_ugoira_frames(args.ugoira.is_some());
+
+                Self::start(
+                    unit,
+                    args,
+                    sub,
+                    last_post_id,
+                    limit_signal,
+                    channel_tx,
+                    length_tx,
+                )
+            }
+            ImageBoards::E621 => {
+                let mut unit = E621Extractor::new_with_config(
+                    &sub.tags,
+                    &ratings,
+                    sub.disable_blacklist,
+                    !sub.no_animated,
+                    server.clone(),
+                );
+                auth_imgboard(false, &mut unit).await?;
+
+                unit.recover_hidden_posts(args.recover_hidden)
+                    .share_hidden_counter(hidden_posts.clone());
+
+                Self::start(
+                    unit,
+                    args,
+                    sub,
+                    last_post_id,
+                    limit_signal,
+                    channel_tx,
+                    length_tx,
+                )
+            }
+            ImageBoards::GelbooruV0_2 | ImageBoards::Gelbooru => {
+                let unit = GelbooruExtractor::new_with_config(
+                    &sub.tags,
+                    &ratings,
+                    sub.disable_blacklist,
+                    !sub.no_animated,
+                    server.clone(),
+                );
+
+                Self::start(
+                    unit,
+                    args,
+                    sub,
+                    last_post_id,
+                    limit_signal,
+                    channel_tx,
+                    length_tx,
+                )
+            }
+            ImageBoards::Moebooru => {
+                let unit = MoebooruExtractor::new_with_config(
+                    &sub.tags,
+                    &ratings,
+                    sub.disable_blacklist,
+                    !sub.no_animated,
+                    server.clone(),
+                );
+
+                Self::start(
+                    unit,
+                    args,
+                    sub,
+                    last_post_id,
+                    limit_signal,
+                    channel_tx,
+                    length_tx,
+                )
+            }
+        };
+
+        let mut qw = Queue::new(
+            server,
+            args.simultaneous_downloads,
+            Some(client),
+            sub.archive,
+            false,
+            args.name_type(),
+            args.annotate,
+        );
+
+        qw.convert_ugoira(args.ugoira_conversion())
+            .split_cbz(args.cbz_split())
+            .new_post_limit(new_post_limit.clone());
+
+        let asd = qw.setup_async_downloader(
+            sub.output_path(),
+            Arc::new(AtomicU64::new(0)),
+            channel_rx,
+            length_rx,
+        );
+
+        let (Ok(removed), Ok(downloaded)) = join!(ext, asd) else {
+            return Err(CliError::ImpossibleExecutionPath);
+        };
+
+        let blacklisted = match removed {
+            Ok(removed) => removed,
+            // Nothing matched the tags yet
+            Err(ExtractorError::ZeroPosts) => 0,
+            Err(error) => return Err(error.into()),
+        };
+
+        let downloaded = downloaded?;
+
+        // Older posts were left out by the limit, so they must be fetched again
+        let cut_short = new_post_limit.is_some_and(|limit| limit.reached());
+
+        let last_saved_id = if cut_short {
+            warn!(
+                "Subscription {} reached it's limit of {} new posts. The older ones are downloaded in the next sync.",
+                sub.name,
+                sub.limit.unwrap_or_default()
+            );
+            None
+        } else {
+            downloaded.last_saved_id
+        };
+
+        Ok(SyncResult {
+            downloaded: downloaded.downloaded,
+            skipped: downloaded.skipped,
+            blacklisted,
+            failed: downloaded.failed,
+            hidden: hidden_posts.total_hidden(),
+            recovered: hidden_posts.total_recovered(),
+            last_post_id: last_saved_id.max(last_post_id),
+        })
+    }
+
+    fn start<E: Extractor + AsyncFetch>(
+        mut unit: E,
+        args: &Cli,
+        sub: &Subscription,
+        last_post_id: Option<u64>,
+        limit_signal: Option<AbortSignal>,
+        channel_tx: UnboundedSender<Post>,
+        length_tx: Sender<u64>,
+    ) -> (ExtractorThreadHandle, Client) {
+        unit.exclude_tags(&sub.exclude);
+        unit.select_variant(args.variant.0);
+        unit.newer_than(last_post_id);
+        unit.abort_signal(limit_signal);
+
+        let client = unit.client();
+
+        let ext_thd = unit.setup_fetch_thread(channel_tx, None, None, Some(length_tx));
+
+        (ext_thd, client)
+    }
+}
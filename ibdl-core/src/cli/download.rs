@@ -0,0 +1,189 @@
+//! Runs the search, pool and post commands from start to finish.
+use std::{
+    path::PathBuf,
+    sync::{atomic::AtomicU64, Arc},
+};
+
+use ibdl_common::{
+    serde::{self, Serialize},
+    tokio::{
+        join,
+        sync::mpsc::{channel, unbounded_channel},
+        task::AbortHandle,
+    },
+};
+
+use crate::{
+    async_queue::{AbortOnExisting, ArchiveFormat, Queue},
+    error::CliError,
+};
+
+use super::{Cli, Commands};
+
+/// Posts found by a single run of the search, pool or post commands.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(crate = "self::serde")]
+pub struct DownloadResult {
+    pub downloaded: u64,
+    /// Posts that were already downloaded.
+    pub skipped: u64,
+    /// Posts removed by the blacklist.
+    pub blacklisted: u64,
+    pub failed: u64,
+    /// Posts whose file url was hidden by the imageboard.
+    pub hidden: u64,
+    /// Hidden posts whose file url was rebuilt from their MD5 hash.
+    pub recovered: u64,
+}
+
+/// Aborts a download thread when dropped, so cancelling a download also stops it's threads.
+pub(crate) struct AbortOnDrop(pub(crate) AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl Cli {
+    /// Downloads everything selected by the command.
+    ///
+    /// `confirm_overwrite` is called with the output paths that already exist, unless `-y` is set.
+    /// Returns `None` if it doesn't confirm.
+    pub async fn download(
+        &mut self,
+        confirm_overwrite: impl FnOnce(&[&PathBuf]) -> bool + Send,
+    ) -> Result<Option<DownloadResult>, CliError> {
+        self.apply_safe_mirror();
+        self.hidden_posts = Arc::default();
+
+        if self.output_to_stdout() && !self.archive_format().is_some_and(|f| f.is_streamable()) {
+            return Err(CliError::StdoutNotStreamable);
+        }
+
+        if self.archive_format() == Some(ArchiveFormat::Epub)
+            && !matches!(self.mode, Commands::Pool(_))
+        {
+            return Err(CliError::EpubWithoutPool);
+        }
+
+        if self.cbz_split().is_enabled() && self.archive_format() != Some(ArchiveFormat::Cbz) {
+            return Err(CliError::SplitWithoutCbz);
+        }
+
+        if self.cross_server() {
+            if !matches!(self.mode, Commands::Search(_)) {
+                return Err(CliError::CrossServerUnsupported);
+            }
+
+            if self.save_file_as_id {
+                return Err(CliError::CrossServerIdNames);
+            }
+        }
+
+        let (channel_tx, channel_rx) = unbounded_channel();
+
+        let (length_sender, length_channel) = channel(self.simultaneous_downloads as usize);
+        let mut pools = Vec::new();
+        let mut other_servers = Vec::new();
+
+        // Feedback channel used by the queue to stop the search once it finds enough downloaded posts
+        let (abort_on_existing, abort_signal) = match &self.mode {
+            Commands::Search(com) => com.abort_on_existing.map(AbortOnExisting::new).unzip(),
+            _ => (None, None),
+        };
+
+        let (ext, client) = match &self.mode {
+            Commands::Search(com) => {
+                let (ext, client, others) = com
+                    .init_extractor(self, channel_tx, length_sender, abort_signal)
+                    .await?;
+                other_servers = others;
+                (ext, client)
+            }
+            Commands::Pool(com) => {
+                let (ext, client, targets) = com.init_extractor(self, length_sender).await?;
+                pools = targets;
+                (ext, client)
+            }
+            Commands::Post(com) => com.init_extractor(self, channel_tx, length_sender).await?,
+            Commands::Popular(com) => com.init_extractor(self, channel_tx, length_sender).await?,
+            Commands::Sync(_) | Commands::Run(_) | Commands::Daemon(_) | Commands::Serve(_) => {
+                return Err(CliError::ImpossibleExecutionPath)
+            }
+        };
+
+        let is_pool = matches!(self.mode, Commands::Pool(_));
+
+        if self.output_to_stdout() && pools.len() > 1 {
+            return Err(CliError::MultiplePoolsToStdout);
+        }
+
+        // Pools are named after their title, which is only known once the extractor fetched it
+        let outputs = if is_pool {
+            pools.iter().map(|pool| pool.output.clone()).collect()
+        } else {
+            vec![self.generate_save_path()?]
+        };
+
+        let existing = outputs
+            .iter()
+            .filter(|path| {
+                !self.output_to_stdout()
+                    && path.exists()
+                    && (path.is_file() || path.read_dir().is_ok_and(|mut dir| dir.next().is_some()))
+            })
+            .collect::<Vec<_>>();
+
+        if !existing.is_empty() && !self.overwrite && !confirm_overwrite(&existing) {
+            ext.abort();
+            return Ok(None);
+        }
+
+        let mut qw = Queue::new(
+            self.imageboard().clone(),
+            self.simultaneous_downloads,
+            Some(client),
+            self.archive_format(),
+            is_pool,
+            self.name_type(),
+            self.annotate,
+        );
+
+        qw.convert_ugoira(self.ugoira_conversion())
+            .split_cbz(self.cbz_split())
+            .epub_max_size(self.epub_max_size)
+            .abort_on_existing(abort_on_existing)
+            .other_servers(other_servers)
+            .share_stats(self.queue_stats.clone());
+
+        let post_counter = Arc::new(AtomicU64::new(0));
+
+        let asd = if is_pool {
+            qw.setup_async_pool_downloader(pools, post_counter, length_channel)
+        } else {
+            let dirname = outputs.into_iter().next().unwrap();
+            qw.setup_async_downloader(dirname, post_counter, channel_rx, length_channel)
+        };
+
+        let _threads = [
+            AbortOnDrop(ext.abort_handle()),
+            AbortOnDrop(asd.abort_handle()),
+        ];
+
+        let (Ok(removed), Ok(results)) = join!(ext, asd) else {
+            return Err(CliError::ImpossibleExecutionPath);
+        };
+
+        let results = results?;
+
+        Ok(Some(DownloadResult {
+            downloaded: results.downloaded,
+            skipped: results.skipped,
+            blacklisted: removed?,
+            failed: results.failed,
+            hidden: self.hidden_posts.total_hidden(),
+            recovered: self.hidden_posts.total_recovered(),
+        }))
+    }
+}
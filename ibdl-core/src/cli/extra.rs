@@ -4,6 +4,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use chrono::NaiveDate;
 use dialoguer::{theme::ColorfulTheme, Input, Password};
 use ibdl_common::{
     bincode::deserialize,
@@ -46,7 +47,7 @@ pub async fn auth_prompt(
             .interact()?;
 
         let mut at = ImageboardConfig::new(
-            get_servers().get(&imageboard.name).unwrap().clone(),
+            imageboard.clone(),
             username.trim().to_string(),
             api_key.trim().to_string(),
         );
@@ -104,20 +105,27 @@ pub async fn read_config_from_fs(
     Ok(None)
 }
 
+/// Dir where `servers.toml` and `subscriptions.toml` are stored.
+///
+/// Can be changed with the `IBDL_SERVER_CFG` env var.
+pub fn config_dir() -> PathBuf {
+    let cfg_path = PathBuf::from(env::var("IBDL_SERVER_CFG").unwrap_or_else(|_| {
+        let cdir = ProjectDirs::from("com", "FerrahWolfeh", "imageboard-downloader").unwrap();
+        cdir.config_dir().to_string_lossy().to_string()
+    }));
+
+    if !cfg_path.exists() {
+        fs::create_dir_all(&cfg_path).unwrap();
+    }
+
+    cfg_path
+}
+
 pub fn get_servers<'a>() -> &'a HashMap<String, ServerConfig> {
     AVAILABLE_SERVERS.get_or_init(|| {
         let mut servers = DEFAULT_SERVERS.clone();
 
-        let cfg_path = PathBuf::from(env::var("IBDL_SERVER_CFG").unwrap_or({
-            let cdir = ProjectDirs::from("com", "FerrahWolfeh", "imageboard-downloader").unwrap();
-            cdir.config_dir().to_string_lossy().to_string()
-        }));
-
-        if !cfg_path.exists() {
-            fs::create_dir_all(&cfg_path).unwrap();
-        }
-
-        let cfg_path = cfg_path.join(Path::new("servers.toml"));
+        let cfg_path = config_dir().join(Path::new("servers.toml"));
 
         read_server_cfg_file(&cfg_path, &mut servers);
 
@@ -139,3 +147,121 @@ pub fn validate_imageboard(input: &str) -> Result<ServerConfig, String> {
         |server| Ok(server.clone()),
     )
 }
+
+/// Parses a human readable size like `500MB`, `2GB` or `1.5GiB` into bytes.
+///
+/// `KB`, `MB`, `GB` and `TB` are powers of 1000, while `KiB`, `MiB`, `GiB` and `TiB` are powers of
+/// 1024. Plain numbers are read as bytes.
+pub fn parse_byte_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+
+    let (number, unit) = input.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size: {input}"))?;
+
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000_u64.pow(2),
+        "g" | "gb" => 1000_u64.pow(3),
+        "t" | "tb" => 1000_u64.pow(4),
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => {
+            return Err(format!(
+                "Invalid size unit: {unit}. Allowed units are: B, KB, MB, GB, TB, KiB, MiB, GiB and TiB"
+            ))
+        }
+    };
+
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    let size = (number * multiplier as f64) as u64;
+
+    if size == 0 {
+        return Err(String::from("Size must be greater than zero"));
+    }
+
+    Ok(size)
+}
+
+/// Parses a resolution like `1072x1448` into it's width and height.
+pub fn parse_resolution(input: &str) -> Result<(u32, u32), String> {
+    let invalid =
+        || format!("Invalid resolution: {input}. Use the WIDTHxHEIGHT format, like 1072x1448");
+
+    let (width, height) = input.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+
+    let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+    let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+
+    Ok((width, height))
+}
+
+/// Parses a date in the `YYYY-MM-DD` format.
+pub fn parse_date(input: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(input.trim(), "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date: {input}. Use the YYYY-MM-DD format, like 2024-01-31"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_sizes_use_their_unit() {
+        assert_eq!(parse_byte_size("512"), Ok(512));
+        assert_eq!(parse_byte_size("512B"), Ok(512));
+        assert_eq!(parse_byte_size("2kb"), Ok(2000));
+        assert_eq!(parse_byte_size("1.5 MB"), Ok(1_500_000));
+        assert_eq!(parse_byte_size("2G"), Ok(2_000_000_000));
+        assert_eq!(parse_byte_size("1TB"), Ok(1_000_000_000_000));
+        assert_eq!(parse_byte_size("4KiB"), Ok(4096));
+        assert_eq!(parse_byte_size(" 1.5MiB "), Ok(1_572_864));
+        assert_eq!(parse_byte_size("1GiB"), Ok(1 << 30));
+        assert_eq!(parse_byte_size("1tib"), Ok(1 << 40));
+    }
+
+    #[test]
+    fn invalid_byte_sizes_fail() {
+        assert!(parse_byte_size("").is_err());
+        assert!(parse_byte_size("MB").is_err());
+        assert!(parse_byte_size("10 PB").is_err());
+        assert!(parse_byte_size("1.2.3MB").is_err());
+        assert!(parse_byte_size("-5MB").is_err());
+        assert!(parse_byte_size("0").is_err());
+        assert!(parse_byte_size("0.0001B").is_err());
+    }
+
+    #[test]
+    fn resolutions_have_width_and_height() {
+        assert_eq!(parse_resolution("1072x1448"), Ok((1072, 1448)));
+        assert_eq!(parse_resolution(" 800X600 "), Ok((800, 600)));
+        assert_eq!(parse_resolution("800 x 600"), Ok((800, 600)));
+    }
+
+    #[test]
+    fn invalid_resolutions_fail() {
+        assert!(parse_resolution("").is_err());
+        assert!(parse_resolution("1072").is_err());
+        assert!(parse_resolution("1072x").is_err());
+        assert!(parse_resolution("0x1448").is_err());
+        assert!(parse_resolution("1072x0").is_err());
+        assert!(parse_resolution("-1072x1448").is_err());
+        assert!(parse_resolution("1072x1448x3").is_err());
+    }
+}
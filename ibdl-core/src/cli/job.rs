@@ -0,0 +1,421 @@
+//! Downloads described in a `jobs.toml` file, run on their schedules by the `daemon` command.
+//!
+//! Each job is turned into the same arguments the command line would take, so jobs support the
+//! same options and are validated the same way.
+//!
+//! # Example
+//! ```toml
+//! [rate_limits]
+//! danbooru = 1.0
+//!
+//! [[job]]
+//! name = "skyfire"
+//! schedule = "0 */6 * * *"
+//! mode = "search"
+//! server = "danbooru"
+//! tags = ["skyfire_(arknights)"]
+//! output = "downloads/skyfire"
+//! abort_on_existing = 20
+//! ```
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local};
+use clap::{Parser, ValueEnum};
+use croner::Cron;
+use ibdl_common::serde::{self, Deserialize};
+
+use crate::{
+    async_queue::{ArchiveFormat, QueueStats},
+    error::CliError,
+};
+
+use super::{download::DownloadResult, extra::config_dir, Cli, Commands};
+
+/// Contents of a `jobs.toml` file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct JobFile {
+    /// Minimum number of seconds between two requests to each server, shared by all jobs.
+    #[serde(default)]
+    pub rate_limits: HashMap<String, f64>,
+    /// Dir where the run log of each job is written. Defaults to `logs`, next to the job file.
+    pub log_dir: Option<PathBuf>,
+    #[serde(default, rename = "job")]
+    pub jobs: Vec<Job>,
+}
+
+impl JobFile {
+    pub const FILE_NAME: &'static str = "jobs.toml";
+
+    /// Default location of the file, next to `servers.toml`.
+    pub fn default_path() -> PathBuf {
+        config_dir().join(Self::FILE_NAME)
+    }
+
+    /// Reads the file and checks that every job is valid.
+    pub fn read(path: &Path) -> Result<Self, CliError> {
+        let invalid = |message: String| CliError::JobFileError {
+            path: path.display().to_string(),
+            message,
+        };
+
+        let raw = read_to_string(path).map_err(|error| invalid(error.to_string()))?;
+        let file: Self = toml::from_str(&raw).map_err(|error| invalid(error.to_string()))?;
+
+        for (idx, job) in file.jobs.iter().enumerate() {
+            if file.jobs[..idx].iter().any(|j| j.name == job.name) {
+                return Err(invalid(format!("Job {} is declared twice", job.name)));
+            }
+
+            job.validate()?;
+        }
+
+        for (server, seconds) in &file.rate_limits {
+            if !seconds.is_finite() || *seconds < 0.0 {
+                return Err(invalid(format!(
+                    "Invalid rate limit for {server}: {seconds}"
+                )));
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Rate limits in the format used by [`set_rate_limits`](ibdl_extractors::rate_limit::set_rate_limits).
+    pub fn rate_limits(&self) -> impl Iterator<Item = (String, Duration)> + '_ {
+        self.rate_limits
+            .iter()
+            .map(|(server, seconds)| (server.clone(), Duration::from_secs_f64(*seconds)))
+    }
+
+    /// Dir of the run logs, relative to the job file at `path`.
+    pub fn log_dir(&self, path: &Path) -> PathBuf {
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+
+        self.log_dir
+            .as_ref()
+            .map_or_else(|| base.join("logs"), |dir| base.join(dir))
+    }
+}
+
+/// A single download of the job file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct Job {
+    /// Unique name of the job, also used as the name of it's run log.
+    pub name: String,
+    /// When the daemon runs the job, as a cron pattern like `0 */6 * * *` or `@daily`.
+    pub schedule: Option<String>,
+    #[serde(flatten)]
+    pub task: JobTask,
+}
+
+/// What a job downloads, selected with the `mode` key.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "self::serde", tag = "mode", rename_all = "lowercase")]
+pub enum JobTask {
+    Search(SearchJob),
+    Pool(PoolJob),
+    Post(PostJob),
+    Sync(SyncJob),
+}
+
+impl JobTask {
+    /// Name of the mode, as used in the `mode` key.
+    pub const fn mode(&self) -> &'static str {
+        match self {
+            Self::Search(_) => "search",
+            Self::Pool(_) => "pool",
+            Self::Post(_) => "post",
+            Self::Sync(_) => "sync",
+        }
+    }
+}
+
+/// Options shared by the search, pool and post jobs, same as the global options of the command
+/// line.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct SaveOptions {
+    /// Name of the server, as listed by `--servers`. Search jobs can use many, separated by commas.
+    #[serde(default = "SaveOptions::default_server")]
+    pub server: String,
+    /// Where to save files, same as `-o`.
+    pub output: Option<PathBuf>,
+    pub archive: Option<ArchiveFormat>,
+    /// Save files with their ID as filename instead of it's MD5.
+    #[serde(default)]
+    pub id: bool,
+    #[serde(default)]
+    pub annotate: bool,
+    pub simultaneous_downloads: Option<u8>,
+}
+
+impl SaveOptions {
+    fn default_server() -> String {
+        String::from("danbooru")
+    }
+
+    fn push_args(&self, args: &mut Vec<String>) {
+        args.extend([String::from("-i"), self.server.clone()]);
+
+        if let Some(output) = &self.output {
+            args.extend([String::from("-o"), output.display().to_string()]);
+        }
+
+        if let Some(format) = self.archive.and_then(|format| format.to_possible_value()) {
+            args.extend([String::from("--archive"), format.get_name().to_string()]);
+        }
+
+        if self.id {
+            args.push(String::from("--id"));
+        }
+
+        if self.annotate {
+            args.push(String::from("--annotate"));
+        }
+
+        if let Some(number) = self.simultaneous_downloads {
+            args.extend([String::from("-d"), number.to_string()]);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct SearchJob {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Queries searched separately instead of `tags`, same as `--query`.
+    #[serde(default)]
+    pub queries: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Ratings to download, like `general` or `explicit`. All of them are downloaded when empty.
+    #[serde(default)]
+    pub ratings: Vec<String>,
+    pub limit: Option<u16>,
+    #[serde(default)]
+    pub disable_blacklist: bool,
+    #[serde(default)]
+    pub no_animated: bool,
+    /// Stop scanning after this many posts in a row were already downloaded.
+    pub abort_on_existing: Option<u32>,
+    #[serde(flatten)]
+    pub save: SaveOptions,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct PoolJob {
+    #[serde(default)]
+    pub pools: Vec<u32>,
+    /// Download every pool found by this search, same as `--search`.
+    pub search: Option<String>,
+    #[serde(flatten)]
+    pub save: SaveOptions,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct PostJob {
+    pub posts: Vec<u32>,
+    #[serde(flatten)]
+    pub save: SaveOptions,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct SyncJob {
+    /// Names of the subscriptions to sync. All of them are synced when empty.
+    #[serde(default)]
+    pub subscriptions: Vec<String>,
+    /// Subscription file to read, instead of `subscriptions.toml` in the config dir.
+    pub file: Option<PathBuf>,
+}
+
+impl Job {
+    fn invalid(&self, message: String) -> CliError {
+        CliError::InvalidJob {
+            name: self.name.clone(),
+            message,
+        }
+    }
+
+    fn validate(&self) -> Result<(), CliError> {
+        if self.name.is_empty() || self.name.contains(['/', '\\']) {
+            return Err(self.invalid(String::from("Job names can't be empty or contain slashes")));
+        }
+
+        self.cron()?;
+        self.to_cli()?;
+
+        Ok(())
+    }
+
+    /// Parsed schedule of the job, if it has one.
+    pub fn cron(&self) -> Result<Option<Cron>, CliError> {
+        self.schedule
+            .as_deref()
+            .map(|pattern| {
+                Cron::new(pattern)
+                    .parse()
+                    .map_err(|error| self.invalid(format!("Invalid schedule {pattern}: {error}")))
+            })
+            .transpose()
+    }
+
+    /// Next time the job should run after `time`, if it has a schedule.
+    pub fn next_run(&self, time: &DateTime<Local>) -> Option<DateTime<Local>> {
+        self.cron()
+            .ok()
+            .flatten()
+            .and_then(|cron| cron.find_next_occurrence(time, false).ok())
+    }
+
+    /// Command line arguments equivalent to the job.
+    pub fn to_args(&self) -> Vec<String> {
+        // Jobs never ask before writing into existing paths
+        let mut args = vec![String::from("imageboard_downloader"), String::from("-y")];
+
+        match &self.task {
+            JobTask::Search(job) => {
+                job.save.push_args(&mut args);
+                args.push(String::from("search"));
+
+                for tag in &job.exclude {
+                    args.extend([String::from("--exclude"), tag.clone()]);
+                }
+
+                for rating in &job.ratings {
+                    args.extend([String::from("--rating"), rating.clone()]);
+                }
+
+                if let Some(limit) = job.limit {
+                    args.extend([String::from("--limit"), limit.to_string()]);
+                }
+
+                if job.disable_blacklist {
+                    args.push(String::from("--disable-blacklist"));
+                }
+
+                if job.no_animated {
+                    args.push(String::from("--no-animated"));
+                }
+
+                if let Some(count) = job.abort_on_existing {
+                    args.extend([String::from("--abort-on-existing"), count.to_string()]);
+                }
+
+                for query in &job.queries {
+                    args.extend([String::from("--query"), query.clone()]);
+                }
+
+                // Tags can start with a dash to exclude them
+                args.push(String::from("--"));
+                args.extend(job.tags.iter().cloned());
+            }
+            JobTask::Pool(job) => {
+                job.save.push_args(&mut args);
+                args.push(String::from("pool"));
+
+                if let Some(query) = &job.search {
+                    args.extend([String::from("--search"), query.clone()]);
+                }
+
+                args.extend(job.pools.iter().map(ToString::to_string));
+            }
+            JobTask::Post(job) => {
+                job.save.push_args(&mut args);
+                args.push(String::from("post"));
+                args.extend(job.posts.iter().map(ToString::to_string));
+            }
+            JobTask::Sync(job) => {
+                args.push(String::from("sync"));
+
+                if let Some(file) = &job.file {
+                    args.extend([String::from("--file"), file.display().to_string()]);
+                }
+
+                args.extend(job.subscriptions.iter().cloned());
+            }
+        }
+
+        args
+    }
+
+    /// Whether the job writes it's archive to the standard output.
+    pub fn output_to_stdout(&self) -> bool {
+        let save = match &self.task {
+            JobTask::Search(job) => &job.save,
+            JobTask::Pool(job) => &job.save,
+            JobTask::Post(job) => &job.save,
+            JobTask::Sync(_) => return false,
+        };
+
+        save.output.as_deref().is_some_and(ArchiveFormat::is_stdout)
+    }
+
+    /// Parses the job the same way as the command line.
+    pub fn to_cli(&self) -> Result<Cli, CliError> {
+        Cli::try_parse_from(self.to_args()).map_err(|error| {
+            // Only keep the reason, without the usage hints meant for the command line
+            let message = error.to_string();
+            let reason = message.lines().next().unwrap_or_default();
+
+            self.invalid(reason.trim_start_matches("error: ").to_string())
+        })
+    }
+
+    /// Runs the job from start to finish.
+    ///
+    /// Subscriptions that fail to sync don't stop the others, but make the whole job fail.
+    pub async fn run(&self) -> Result<DownloadResult, CliError> {
+        self.run_with_stats(Arc::default()).await
+    }
+
+    /// Same as [`run`](Self::run), counting the posts of the download into `stats` while it runs.
+    ///
+    /// Sync jobs run a queue for each subscription, so they leave `stats` untouched.
+    pub async fn run_with_stats(&self, stats: Arc<QueueStats>) -> Result<DownloadResult, CliError> {
+        let mut args = self.to_cli()?;
+        args.queue_stats = stats;
+
+        if let Commands::Sync(com) = &args.mode {
+            let reports = com.run(&args).await?;
+
+            let mut total = DownloadResult::default();
+            let mut failed = Vec::new();
+
+            for report in reports {
+                match report.result {
+                    Ok(result) => {
+                        total.downloaded += result.downloaded;
+                        total.skipped += result.skipped;
+                        total.blacklisted += result.blacklisted;
+                        total.failed += result.failed;
+                        total.hidden += result.hidden;
+                        total.recovered += result.recovered;
+                    }
+                    Err(error) => failed.push(format!("{} ({error})", report.name)),
+                }
+            }
+
+            if !failed.is_empty() {
+                return Err(CliError::SyncFailed {
+                    names: failed.join(", "),
+                });
+            }
+
+            return Ok(total);
+        }
+
+        Ok(args.download(|_| true).await?.unwrap_or_default())
+    }
+}
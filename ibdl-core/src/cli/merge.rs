@@ -0,0 +1,109 @@
+//! Merges the posts found by many extractors into a single download queue.
+use std::collections::HashSet;
+
+use futures::{stream::select_all, StreamExt};
+use ibdl_common::{
+    log::debug,
+    post::Post,
+    tokio::{
+        spawn,
+        sync::mpsc::{Sender, UnboundedReceiver, UnboundedSender},
+    },
+};
+use ibdl_extractors::{error::ExtractorError, imageboards::ExtractorThreadHandle};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use super::download::AbortOnDrop;
+
+/// An extractor thread along with the channel it sends it's posts through.
+pub struct PostSource {
+    pub thread: ExtractorThreadHandle,
+    pub channel: UnboundedReceiver<Post>,
+    /// Name of the server the extractor searches.
+    pub server: String,
+}
+
+/// Forwards the posts of every source to `channel_tx`, dropping the ones that were already
+/// forwarded, until `limit` posts are sent.
+///
+/// Posts are compared by their id and MD5 when every source searches the same server. Otherwise,
+/// ids can be the same for different posts, so only the MD5 is compared and each post keeps the
+/// name of the server it was found in.
+///
+/// The returned thread ends once every source ends, returning the total of posts removed by the
+/// blacklists. Sources that found no posts are only an error if all of them found nothing.
+pub fn merge_sources(
+    sources: Vec<PostSource>,
+    channel_tx: UnboundedSender<Post>,
+    length_tx: Sender<u64>,
+    limit: Option<u16>,
+) -> ExtractorThreadHandle {
+    spawn(async move {
+        let cross_server = sources
+            .iter()
+            .any(|source| source.server != sources[0].server);
+
+        // Stop the extractors if the download is cancelled
+        let mut threads = Vec::with_capacity(sources.len());
+        let mut channels = Vec::with_capacity(sources.len());
+
+        for source in sources {
+            let guard = AbortOnDrop(source.thread.abort_handle());
+            threads.push((source.thread, guard));
+
+            let server = cross_server.then_some(source.server);
+            channels.push(
+                UnboundedReceiverStream::new(source.channel).map(move |mut post| {
+                    post.server.clone_from(&server);
+                    post
+                }),
+            );
+        }
+
+        let mut posts = select_all(channels);
+
+        let mut seen_ids = HashSet::new();
+        let mut seen_md5 = HashSet::new();
+        let mut sent: u16 = 0;
+
+        // Keep draining the channels after the limit, so the extractors can finish on their own
+        while let Some(post) = posts.next().await {
+            if limit.is_some_and(|limit| sent >= limit) {
+                continue;
+            }
+
+            let new_id = cross_server || seen_ids.insert(post.id);
+            let new_md5 = post.md5.is_empty() || seen_md5.insert(post.md5.clone());
+
+            if !new_id || !new_md5 {
+                debug!("Post {} was already found by another search", post.id);
+                continue;
+            }
+
+            channel_tx.send(post)?;
+            length_tx.send(1).await?;
+            sent += 1;
+        }
+
+        let mut removed = 0;
+        let mut found_posts = false;
+
+        for (thread, _guard) in threads {
+            match thread.await {
+                Ok(Ok(count)) => {
+                    removed += count;
+                    found_posts = true;
+                }
+                Ok(Err(ExtractorError::ZeroPosts)) => {}
+                Ok(Err(error)) => return Err(error),
+                Err(_) => return Err(ExtractorError::ImpossibleBehavior),
+            }
+        }
+
+        if !found_posts {
+            return Err(ExtractorError::ZeroPosts);
+        }
+
+        Ok(removed)
+    })
+}
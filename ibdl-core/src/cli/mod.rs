@@ -1,20 +1,38 @@
 // 20002709
-use ibdl_common::post::{extension::Extension, NameType};
-use ibdl_extractors::extractor_config::ServerConfig;
+use ibdl_common::{
+    log::{debug, warn},
+    post::{extension::Extension, NameType},
+};
+use ibdl_extractors::{
+    extractor_config::ServerConfig,
+    imageboards::{HiddenPostCounter, PoolInfo},
+};
 use once_cell::sync::OnceCell;
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 use clap::{Parser, Subcommand};
 
-use crate::generate_output_path_precise;
+use crate::{
+    async_queue::{
+        ArchiveFormat, CbzSplit, QueueStats, UgoiraConversion, UgoiraFormat, STDOUT_PATH,
+    },
+    generate_output_path_precise, VariantArg,
+};
 
 use self::{
-    commands::{pool::Pool, post::Post, search::TagSearch},
-    extra::validate_imageboard,
+    commands::{
+        daemon::Daemon, pool::Pool, popular::Popular, post::Post, run::Run, search::TagSearch,
+        serve::Serve, sync::Sync,
+    },
+    extra::{parse_byte_size, parse_resolution, validate_imageboard},
 };
 
 pub mod commands;
+pub mod download;
 pub(crate) mod extra;
+pub mod job;
+pub(crate) mod merge;
+pub mod subscription;
 
 pub static AVAILABLE_SERVERS: OnceCell<HashMap<String, ServerConfig>> = OnceCell::new();
 
@@ -26,6 +44,16 @@ pub enum Commands {
     Pool(Pool),
     /// Download a single or multiple specific posts
     Post(Post),
+    /// Download the popular posts of a day, week or month
+    Popular(Popular),
+    /// Download the new posts of every saved search in `subscriptions.toml`
+    Sync(Sync),
+    /// Run every job of a job file once, ignoring their schedules
+    Run(Run),
+    /// Run the jobs of `jobs.toml` on their schedules
+    Daemon(Daemon),
+    /// Serve a local HTTP API to queue downloads from other programs
+    Serve(Serve),
 }
 
 #[derive(Parser, Debug)]
@@ -37,8 +65,19 @@ pub struct Cli {
     /// Specify which website to download from
     ///
     /// Default websites include: ["danbooru", "e621", "gelbooru", "rule34", "realbooru", "konachan"]
-    #[clap(short, long, ignore_case = true, default_value_t = ServerConfig::default(), global = true, value_parser = validate_imageboard)]
-    pub imageboard: ServerConfig,
+    ///
+    /// Tag searches can go through many websites at once, like `-i danbooru,e621,gelbooru`
+    #[clap(
+        short,
+        long = "imageboard",
+        value_name = "IMAGEBOARD",
+        ignore_case = true,
+        value_delimiter = ',',
+        default_values_t = [ServerConfig::default()],
+        global = true,
+        value_parser = validate_imageboard
+    )]
+    pub imageboards: Vec<ServerConfig>,
 
     /// Print all available servers and exit
     #[clap(long, global = true)]
@@ -94,6 +133,56 @@ pub struct Cli {
     )]
     pub cbz: bool,
 
+    /// Save posts inside a single archive of the selected format
+    ///
+    /// Tar archives can be written to the standard output with `-o -`
+    #[clap(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        conflicts_with = "cbz",
+        help_heading = "SAVE",
+        global = true
+    )]
+    pub archive: Option<ArchiveFormat>,
+
+    /// Downscale the pages of EPUB files to fit in this resolution, like `1072x1448`
+    ///
+    /// Only JPEG and PNG pages are downscaled
+    #[clap(
+        long,
+        value_name = "WIDTHxHEIGHT",
+        value_parser = parse_resolution,
+        requires = "archive",
+        help_heading = "SAVE",
+        global = true
+    )]
+    pub epub_max_size: Option<(u32, u32)>,
+
+    /// Split the cbz file into volumes of at most this size, like `500MB` or `2GB`
+    ///
+    /// Volumes are saved as `name.001.cbz`, `name.002.cbz` and so on
+    #[clap(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_byte_size,
+        help_heading = "SAVE",
+        global = true
+    )]
+    pub cbz_split_size: Option<u64>,
+
+    /// Split the cbz file into volumes of at most this number of posts
+    ///
+    /// Volumes are saved as `name.001.cbz`, `name.002.cbz` and so on
+    #[clap(
+        long,
+        value_name = "NUMBER",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help_heading = "SAVE",
+        global = true
+    )]
+    pub cbz_split_count: Option<u32>,
+
     /// Write tags in a txt file next to the downloaded image (for Stable Diffusion training)
     #[clap(
         long,
@@ -104,6 +193,48 @@ pub struct Cli {
     )]
     pub annotate: bool,
 
+    /// Rebuild the file url of posts hidden by the imageboard using their MD5 hash
+    ///
+    /// Only works on servers with a `hidden_url_template` set (Danbooru and e621 by default)
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = false,
+        help_heading = "DOWNLOAD",
+        global = true
+    )]
+    pub recover_hidden: bool,
+
+    /// Download a smaller version of each file instead of the original
+    ///
+    /// Falls back to the original file when the imageboard doesn't provide the selected variant.
+    /// Samples and previews can't be checked against the post's MD5 hash
+    #[clap(
+        long,
+        value_enum,
+        default_value = "original",
+        help_heading = "DOWNLOAD",
+        global = true
+    )]
+    pub variant: VariantArg,
+
+    /// Convert Pixiv Ugoira zips into animated images after download
+    ///
+    /// Needs the frame delays of the post, currently only provided by Danbooru
+    #[clap(long, value_enum, help_heading = "SAVE", global = true)]
+    pub ugoira: Option<UgoiraFormat>,
+
+    /// Keep the original Ugoira zip along with the converted file
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = false,
+        requires = "ugoira",
+        help_heading = "SAVE",
+        global = true
+    )]
+    pub keep_ugoira_zip: bool,
+
     /// Always overwrite output
     #[clap(
         short = 'y',
@@ -113,9 +244,28 @@ pub struct Cli {
         global = true
     )]
     pub overwrite: bool,
+
+    /// Counter of hidden and recovered posts shared by every extractor capable of [`HiddenPostRecovery`](ibdl_extractors::imageboards::HiddenPostRecovery)
+    #[clap(skip)]
+    pub hidden_posts: Arc<HiddenPostCounter>,
+
+    /// Counts of the posts found and downloaded so far, readable while the download runs
+    #[clap(skip)]
+    pub queue_stats: Arc<QueueStats>,
 }
 
 impl Cli {
+    /// First server selected with `-i`.
+    pub fn imageboard(&self) -> &ServerConfig {
+        // Clap always fills at least the default server
+        &self.imageboards[0]
+    }
+
+    /// Whether `-i` selected more than one server.
+    pub const fn cross_server(&self) -> bool {
+        self.imageboards.len() > 1
+    }
+
     pub const fn name_type(&self) -> NameType {
         if self.save_file_as_id {
             NameType::ID
@@ -124,6 +274,65 @@ impl Cli {
         }
     }
 
+    pub fn ugoira_conversion(&self) -> Option<UgoiraConversion> {
+        self.ugoira
+            .map(|format| UgoiraConversion::new(format, self.keep_ugoira_zip))
+    }
+
+    /// Format of the archive selected with `--archive` or `--cbz`, if any.
+    pub fn archive_format(&self) -> Option<ArchiveFormat> {
+        self.archive
+            .or_else(|| self.cbz.then_some(ArchiveFormat::Cbz))
+    }
+
+    /// Whether the archive should be written to the standard output (`-o -`).
+    pub fn output_to_stdout(&self) -> bool {
+        self.output.as_deref().is_some_and(ArchiveFormat::is_stdout)
+    }
+
+    pub fn cbz_split(&self) -> CbzSplit {
+        CbzSplit::new(
+            self.cbz_split_size,
+            self.cbz_split_count.map(|count| count as usize),
+        )
+    }
+
+    /// Whether `--safe-mode` was set in the selected subcommand.
+    pub const fn safe_mode(&self) -> bool {
+        match &self.mode {
+            Commands::Search(args) => args.safe_mode,
+            Commands::Pool(args) => args.safe_mode,
+            Commands::Popular(args) => args.safe_mode,
+            Commands::Post(_)
+            | Commands::Sync(_)
+            | Commands::Run(_)
+            | Commands::Daemon(_)
+            | Commands::Serve(_) => false,
+        }
+    }
+
+    /// Replaces the selected servers with their safe mirror when running in safe mode, so the NSFW
+    /// endpoint is never contacted.
+    ///
+    /// If the server has no safe mirror, ratings are still filtered after fetching the posts.
+    pub fn apply_safe_mirror(&mut self) {
+        if !self.safe_mode() {
+            return;
+        }
+
+        for imageboard in &mut self.imageboards {
+            if let Some(mirror) = imageboard.safe_mirror() {
+                debug!("Using safe mirror {}", mirror.base_url);
+                *imageboard = mirror;
+            } else {
+                warn!(
+                    "{} has no safe mirror. Posts will be filtered by rating after fetching them.",
+                    imageboard.pretty_name
+                );
+            }
+        }
+    }
+
     pub fn get_extension(&self) -> Option<Extension> {
         match &self.mode {
             Commands::Search(args) => {
@@ -136,12 +345,26 @@ impl Cli {
                     return Some(Extension::guess_format(ext));
                 }
             }
-            Commands::Post(_) => {}
+            Commands::Popular(args) => {
+                if let Some(ext) = &args.force_extension {
+                    return Some(Extension::guess_format(ext));
+                }
+            }
+            Commands::Post(_)
+            | Commands::Sync(_)
+            | Commands::Run(_)
+            | Commands::Daemon(_)
+            | Commands::Serve(_) => {}
         }
         None
     }
 
+    /// Path of the output dir or archive.
     pub fn generate_save_path(&self) -> Result<PathBuf, std::io::Error> {
+        if self.output_to_stdout() {
+            return Ok(PathBuf::from(STDOUT_PATH));
+        }
+
         let raw_save_path = if let Some(precise_path) = &self.output {
             precise_path.to_owned()
         } else {
@@ -149,11 +372,51 @@ impl Cli {
         };
 
         let dirname = if self.output.is_some() {
-            generate_output_path_precise(&raw_save_path, self.cbz)
+            generate_output_path_precise(&raw_save_path, self.archive_format())
         } else {
             raw_save_path
         };
 
         Ok(dirname)
     }
+
+    /// Path of the output dir or archive of a pool, saved as `name`.
+    ///
+    /// Without `-o`, pools are saved into `<imageboard>/<name>` inside the current dir. When
+    /// downloading more than one pool, `-o` is the dir all of them are saved into, each one named
+    /// after it's pool.
+    ///
+    /// Pools used to be saved under their id, so if only `<id>` exists, it's updated instead.
+    pub fn pool_save_path(
+        &self,
+        pool: &PoolInfo,
+        name: &str,
+        batch: bool,
+    ) -> Result<PathBuf, std::io::Error> {
+        if self.output_to_stdout() {
+            return Ok(PathBuf::from(STDOUT_PATH));
+        }
+
+        let path_for = |name: &str| -> Result<PathBuf, std::io::Error> {
+            let path = match &self.output {
+                Some(output) if batch => output.join(name),
+                Some(output) => output.clone(),
+                None => std::env::current_dir()?
+                    .join(self.imageboard().server.to_string())
+                    .join(name),
+            };
+
+            Ok(generate_output_path_precise(&path, self.archive_format()))
+        };
+
+        let dirname = path_for(name)?;
+        let legacy = path_for(&pool.id.to_string())?;
+
+        if !dirname.exists() && legacy.exists() {
+            debug!("Updating pool {} saved as {}", pool.id, legacy.display());
+            return Ok(legacy);
+        }
+
+        Ok(dirname)
+    }
 }
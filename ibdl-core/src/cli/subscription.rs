@@ -0,0 +1,224 @@
+//! Saved searches read from `subscriptions.toml`, downloaded incrementally by the `sync` command.
+//!
+//! # Example
+//! ```toml
+//! [[subscription]]
+//! name = "skyfire"
+//! server = "danbooru"
+//! tags = ["skyfire_(arknights)"]
+//! exclude = ["comic"]
+//! ratings = ["general", "sensitive"]
+//! output = "downloads/{server}/{name}"
+//! archive = "cbz"
+//! ```
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, write},
+    io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use ibdl_common::{
+    post::rating::Rating,
+    serde::{self, Deserialize, Serialize},
+    serde_json,
+};
+use ibdl_extractors::extractor_config::ServerConfig;
+
+use crate::{async_queue::ArchiveFormat, error::CliError, generate_output_path_precise, RatingArg};
+
+use super::extra::{config_dir, validate_imageboard};
+
+/// Contents of a `subscriptions.toml` file.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct SubscriptionFile {
+    #[serde(default, rename = "subscription")]
+    pub subscriptions: Vec<Subscription>,
+}
+
+impl SubscriptionFile {
+    pub const FILE_NAME: &'static str = "subscriptions.toml";
+
+    /// Default location of the file, next to `servers.toml`.
+    pub fn default_path() -> PathBuf {
+        config_dir().join(Self::FILE_NAME)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, CliError> {
+        let invalid = |message: String| CliError::SubscriptionFileError {
+            path: path.display().to_string(),
+            message,
+        };
+
+        let raw = read_to_string(path).map_err(|error| invalid(error.to_string()))?;
+        let file: Self = toml::from_str(&raw).map_err(|error| invalid(error.to_string()))?;
+
+        for (idx, sub) in file.subscriptions.iter().enumerate() {
+            if file.subscriptions[..idx].iter().any(|s| s.name == sub.name) {
+                return Err(invalid(format!(
+                    "Subscription {} is declared twice",
+                    sub.name
+                )));
+            }
+
+            sub.check_archive()?;
+        }
+
+        Ok(file)
+    }
+}
+
+/// A saved search.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct Subscription {
+    /// Unique name of the subscription, used to remember which posts were already downloaded.
+    pub name: String,
+    /// Name of the server, as listed by `--servers`.
+    #[serde(default = "Subscription::default_server")]
+    pub server: String,
+    pub tags: Vec<String>,
+    /// Exclude posts with these tags.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Ratings to download, like `general` or `explicit`. All of them are downloaded when empty.
+    #[serde(default)]
+    pub ratings: Vec<String>,
+    #[serde(default)]
+    pub disable_blacklist: bool,
+    /// Do not download animated gifs or video files.
+    #[serde(default)]
+    pub no_animated: bool,
+    /// Max number of new posts to download in each sync. Posts saved in an earlier sync don't count toward it, and the state isn't moved when it's reached.
+    pub limit: Option<u16>,
+    /// Where posts are saved. `{name}`, `{server}` and `{tags}` are replaced by the values of the
+    /// subscription.
+    #[serde(default = "Subscription::default_output")]
+    pub output: String,
+    /// Save posts into a cbz file instead of a folder. Other archives can't be appended to, so
+    /// they aren't accepted.
+    pub archive: Option<ArchiveFormat>,
+}
+
+impl Subscription {
+    fn default_server() -> String {
+        String::from("danbooru")
+    }
+
+    fn default_output() -> String {
+        String::from("{server}/{name}")
+    }
+
+    fn invalid(&self, message: String) -> CliError {
+        CliError::InvalidSubscription {
+            name: self.name.clone(),
+            message,
+        }
+    }
+
+    pub fn server_config(&self) -> Result<ServerConfig, CliError> {
+        validate_imageboard(&self.server).map_err(|message| self.invalid(message))
+    }
+
+    /// Each sync appends the new posts to the output, which only cbz files and folders allow.
+    fn check_archive(&self) -> Result<(), CliError> {
+        match self.archive {
+            None | Some(ArchiveFormat::Cbz) => Ok(()),
+            Some(format) => Err(self.invalid(format!(
+                "Posts can't be appended to {} files, use cbz or a folder instead",
+                format.extension()
+            ))),
+        }
+    }
+
+    pub fn selected_ratings(&self) -> Result<Vec<Rating>, CliError> {
+        let ratings = self
+            .ratings
+            .iter()
+            .map(|rating| {
+                RatingArg::from_str(rating, true)
+                    .map_err(|_| self.invalid(format!("Invalid rating: {rating}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(RatingArg::selected_ratings(&ratings, false, false))
+    }
+
+    /// Path of the dir or archive the posts are saved into.
+    #[allow(clippy::literal_string_with_formatting_args)]
+    pub fn output_path(&self) -> PathBuf {
+        let tags = self.tags.join(" ");
+        let tags = if cfg!(windows) {
+            tags.replace(':', "_")
+        } else {
+            tags
+        };
+
+        let path = self
+            .output
+            .replace("{name}", &self.name)
+            .replace("{server}", &self.server)
+            .replace("{tags}", &tags);
+
+        generate_output_path_precise(Path::new(&path), self.archive)
+    }
+}
+
+/// What was downloaded by the last sync of each subscription.
+///
+/// Saved next to the subscription file, as `<file name>.state.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct SyncState {
+    #[serde(flatten)]
+    pub subscriptions: BTreeMap<String, SubscriptionState>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct SubscriptionState {
+    /// Highest post id downloaded so far. Only newer posts are fetched in the next sync.
+    pub last_post_id: u64,
+    pub last_sync: DateTime<Utc>,
+}
+
+impl SyncState {
+    pub fn path(subscription_file: &Path) -> PathBuf {
+        subscription_file.with_extension("state.json")
+    }
+
+    /// Reads the state file, or starts from scratch if it doesn't exist yet.
+    pub fn read(path: &Path) -> Result<Self, CliError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let raw = read_to_string(path)?;
+
+        serde_json::from_str(&raw).map_err(|error| CliError::SubscriptionFileError {
+            path: path.display().to_string(),
+            message: error.to_string(),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn last_post_id(&self, name: &str) -> Option<u64> {
+        self.subscriptions.get(name).map(|state| state.last_post_id)
+    }
+
+    pub fn update(&mut self, name: &str, last_post_id: u64) {
+        self.subscriptions.insert(
+            name.to_string(),
+            SubscriptionState {
+                last_post_id,
+                last_sync: Utc::now(),
+            },
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{io, num::TryFromIntError};
+use std::{io, net::SocketAddr, num::TryFromIntError};
 
 use ibdl_common::post::error::PostError;
 use ibdl_extractors::error::ExtractorError;
@@ -31,6 +31,9 @@ pub enum QueueError {
     #[error("Summary file in {file} not found or corrupted")]
     ZipSummaryReadError { file: String },
 
+    #[error("Failed to add image to epub file: {message}")]
+    EpubImageFail { message: String },
+
     #[error("No posts to download!")]
     NoPostsInQueue,
 
@@ -85,4 +88,68 @@ pub enum CliError {
 
     #[error("No posts given")]
     NoPostsInInput,
+
+    #[error("Search queries can't be empty")]
+    EmptyQuery,
+
+    #[error("Only tag searches can use more than one imageboard")]
+    CrossServerUnsupported,
+
+    #[error(
+        "Posts of different imageboards can have the same ID. Remove `--id` to save them by MD5."
+    )]
+    CrossServerIdNames,
+
+    #[error("No pools given or found")]
+    NoPoolsInInput,
+
+    #[error("No subscriptions to sync")]
+    NoSubscriptions,
+
+    #[error("Failed to read subscriptions from {path}: {message}")]
+    SubscriptionFileError { path: String, message: String },
+
+    #[error("Invalid subscription {name}: {message}")]
+    InvalidSubscription { name: String, message: String },
+
+    #[error("Failed to sync subscriptions: {names}")]
+    SyncFailed { names: String },
+
+    #[error("Failed to read jobs from {path}: {message}")]
+    JobFileError { path: String, message: String },
+
+    #[error("Invalid job {name}: {message}")]
+    InvalidJob { name: String, message: String },
+
+    #[error("No jobs to run")]
+    NoJobs,
+
+    #[error("Only one job of each run can write to stdout: {names}")]
+    ManyStdoutJobs { names: String },
+
+    #[error(
+        "Only tar archives can be written to stdout. Use `--archive tar` or `--archive tar.zst`."
+    )]
+    StdoutNotStreamable,
+
+    #[error("EPUB files can only be generated from pools.")]
+    EpubWithoutPool,
+
+    #[error("Only cbz files can be split into volumes. Use `--cbz` or `--archive cbz`.")]
+    SplitWithoutCbz,
+
+    #[error("Only a single pool can be written to stdout.")]
+    MultiplePoolsToStdout,
+
+    #[error("HTTP server failed: {message}")]
+    ServerFail { message: String },
+
+    #[error("The API can only listen on {address} with a token. Set one with --token")]
+    PublicServerWithoutToken { address: SocketAddr },
+
+    #[error("Failed to download posts: {source}")]
+    QueueFail {
+        #[from]
+        source: QueueError,
+    },
 }
@@ -3,9 +3,14 @@
 #![allow(clippy::missing_panics_doc)]
 #![allow(clippy::missing_errors_doc)]
 #![allow(clippy::struct_field_names)]
+use async_queue::ArchiveFormat;
 pub use clap;
 use clap::ValueEnum;
-use ibdl_common::{post::rating::Rating, ImageBoards};
+use ibdl_common::{
+    post::{rating::Rating, variant::FileVariant},
+    ImageBoards,
+};
+use ibdl_extractors::imageboards::{PoolInfo, PopularScale};
 pub use owo_colors;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
@@ -27,6 +32,8 @@ impl ValueEnum for RatingArg {
     fn value_variants<'a>() -> &'a [Self] {
         &[
             Self(Rating::Safe),
+            Self(Rating::General),
+            Self(Rating::Sensitive),
             Self(Rating::Questionable),
             Self(Rating::Explicit),
         ]
@@ -35,20 +42,108 @@ impl ValueEnum for RatingArg {
         match self.0 {
             Rating::Safe => {
                 Some(clap::builder::PossibleValue::new("safe").help(
-                    "Represents posts that are don't involve nothing suggestive or sensitive",
+                    "Represents posts that are don't involve nothing suggestive or sensitive. Also selects `general`",
                 ))
             }
+            Rating::General => Some(clap::builder::PossibleValue::new("general").help(
+                "Danbooru and Gelbooru's equivalent of `safe`",
+            )),
+            Rating::Sensitive => Some(clap::builder::PossibleValue::new("sensitive").help(
+                "Represents mildly suggestive posts (Danbooru and Gelbooru only)",
+            )),
             Rating::Questionable => Some(clap::builder::PossibleValue::new("questionable").help(
                 "Represents posts that have some degree of nudity or sexually suggestive elements",
             )),
             Rating::Explicit => Some(clap::builder::PossibleValue::new("explicit").help(
                 "Represents posts that have explicit elements of pornography, gore, death, etc",
             )),
-            _ => None,
+            Rating::Unknown => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(transparent)]
+pub struct VariantArg(pub FileVariant);
+
+impl ValueEnum for VariantArg {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self(FileVariant::Original),
+            Self(FileVariant::Sample),
+            Self(FileVariant::Preview),
+        ]
+    }
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        match self.0 {
+            FileVariant::Original => Some(
+                clap::builder::PossibleValue::new("original")
+                    .help("The original file uploaded to the imageboard"),
+            ),
+            FileVariant::Sample => Some(
+                clap::builder::PossibleValue::new("sample")
+                    .help("A resized version of the file, when the imageboard provides one"),
+            ),
+            FileVariant::Preview => Some(
+                clap::builder::PossibleValue::new("preview").help("A small thumbnail of the file"),
+            ),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(transparent)]
+pub struct ScaleArg(pub PopularScale);
+
+impl ValueEnum for ScaleArg {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self(PopularScale::Day),
+            Self(PopularScale::Week),
+            Self(PopularScale::Month),
+        ]
+    }
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(self.0.as_str()))
+    }
+}
+
+impl RatingArg {
+    /// Expands the selected ratings into the list used by the extractors.
+    ///
+    /// `safe` also selects `general`, and when nothing is selected, all ratings are downloaded,
+    /// except for `sensitive`, `questionable` and `explicit` in safe mode.
+    pub fn selected_ratings(args: &[Self], safe_mode: bool, ignore_unknown: bool) -> Vec<Rating> {
+        let mut ratings: Vec<Rating> = Vec::with_capacity(6);
+        if args.is_empty() {
+            ratings.push(Rating::Safe);
+            ratings.push(Rating::General);
+
+            if !safe_mode {
+                ratings.push(Rating::Sensitive);
+                ratings.push(Rating::Questionable);
+                ratings.push(Rating::Explicit);
+            }
+        } else {
+            for item in args {
+                ratings.push(item.0);
+
+                if item.0 == Rating::Safe {
+                    ratings.push(Rating::General);
+                }
+            }
+        };
+
+        if !ignore_unknown {
+            ratings.push(Rating::Unknown);
+        }
+
+        ratings.sort();
+        ratings.dedup();
+        ratings
+    }
+}
+
 impl Deref for RatingArg {
     type Target = Rating;
 
@@ -62,24 +157,31 @@ pub fn generate_output_path(
     main_path: &Path,
     imageboard: ImageBoards,
     tags: &[String],
-    cbz_mode: bool,
-    pool_id: Option<u32>,
+    archive: Option<ArchiveFormat>,
+    pool: Option<&PoolInfo>,
 ) -> PathBuf {
     let tag_string = tags.join(" ");
-    let tag_path_string = if tag_string.contains("fav:") {
-        String::from("Favorites")
-    } else if cfg!(windows) {
-        tag_string.replace(':', "_")
-    } else if let Some(id) = pool_id {
-        id.to_string()
-    } else {
-        tag_string
-    };
+    let tag_path_string = pool.map_or_else(
+        || {
+            if tag_string.contains("fav:") {
+                String::from("Favorites")
+            } else if cfg!(windows) {
+                tag_string.replace(':', "_")
+            } else {
+                tag_string
+            }
+        },
+        PoolInfo::file_name,
+    );
 
     let pbuf = main_path.join(Path::new(&imageboard.to_string()));
 
-    if cbz_mode {
-        return pbuf.join(Path::new(&format!("{}.cbz", tag_path_string)));
+    if let Some(format) = archive {
+        return pbuf.join(Path::new(&format!(
+            "{}.{}",
+            tag_path_string,
+            format.extension()
+        )));
     }
     pbuf.join(Path::new(&tag_path_string))
 }
@@ -87,9 +189,9 @@ pub fn generate_output_path(
 /// This function creates the destination directory without creating additional ones related to
 /// the selected imageboard or tags used.
 #[inline]
-pub fn generate_output_path_precise(main_path: &Path, cbz_mode: bool) -> PathBuf {
-    if cbz_mode {
-        return PathBuf::from(&format!("{}.cbz", main_path.display()));
+pub fn generate_output_path_precise(main_path: &Path, archive: Option<ArchiveFormat>) -> PathBuf {
+    if let Some(format) = archive {
+        return PathBuf::from(&format!("{}.{}", main_path.display(), format.extension()));
     }
     main_path.to_path_buf()
 }
@@ -142,6 +142,15 @@ impl ProgressCounter {
         });
     }
 
+    /// Resets the main progress bar and the download counter, so they can be used by another
+    /// download in the same run.
+    pub fn restart(&self, len: u64) {
+        self.main.reset();
+        self.main.set_length(len);
+        self.main.enable_steady_tick(Duration::from_millis(100));
+        self.downloaded_mtx.store(0, Ordering::SeqCst);
+    }
+
     pub fn increment_counters(&self, delta: u64) {
         self.main.inc(delta);
         self.total_mtx.fetch_add(delta as usize, Ordering::SeqCst);
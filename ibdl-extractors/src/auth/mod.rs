@@ -98,7 +98,7 @@ impl Default for ImageboardConfig {
 
 impl ImageboardConfig {
     #[must_use]
-    pub fn new(imageboard: ServerConfig, username: String, api_key: String) -> Self {
+    pub const fn new(imageboard: ServerConfig, username: String, api_key: String) -> Self {
         Self {
             imageboard,
             username,
@@ -151,10 +151,10 @@ impl ImageboardConfig {
             return Err(Error::InvalidLogin);
         }
 
-        if req.id.is_some() {
+        if let Some(id) = req.id {
             let tag_list = req.blacklisted_tags.unwrap();
 
-            self.user_data.id = req.id.unwrap();
+            self.user_data.id = id;
             self.user_data.name = req.name.unwrap();
 
             for i in tag_list.lines() {
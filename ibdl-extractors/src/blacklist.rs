@@ -29,6 +29,10 @@
 //!
 //! With this, the user can input all tags that they do not want to download. In case a post has
 //! any of the tags set in the blacklist, it will be removed from the download queue.
+//!
+//! Rating metatags such as `rating:s` or `rating:questionable` are also accepted and are parsed
+//! with the rating table of the selected imageboard, so `rating:s` blocks *sensitive* posts on
+//! Danbooru and *safe* posts on e621.
 use ahash::AHashSet;
 use ibdl_common::directories::ProjectDirs;
 use ibdl_common::log::{debug, warn};
@@ -104,6 +108,7 @@ impl GlobalBlacklist {
 
 pub struct BlacklistFilter {
     gbl_tags: AHashSet<String>,
+    blocked_ratings: AHashSet<Rating>,
     selected_ratings: Vec<Rating>,
     disabled: bool,
     ignore_animated: bool,
@@ -144,11 +149,24 @@ impl BlacklistFilter {
             }
         }
 
+        let mut blocked_ratings: AHashSet<Rating> = AHashSet::new();
+        gbl_tags.retain(|tag| {
+            tag.strip_prefix("rating:").map_or(true, |rt| {
+                blocked_ratings.insert(Rating::from_board_str(rt, imageboard.server));
+                false
+            })
+        });
+
+        if !blocked_ratings.is_empty() {
+            debug!("Blacklisted ratings: {:?}", blocked_ratings);
+        }
+
         let mut sorted_list = selected_ratings.to_vec();
         sorted_list.sort();
 
         Ok(Self {
             gbl_tags,
+            blocked_ratings,
             selected_ratings: sorted_list,
             disabled,
             ignore_animated,
@@ -167,7 +185,7 @@ impl BlacklistFilter {
         let start = Instant::now();
         if let Some(ext) = self.extension {
             debug!("Selecting only posts with extension {:?}", ext.to_string());
-            original_list.retain(|post| ext == post.extension);
+            original_list.retain(|post| ext == post.source_extension());
         }
 
         if !self.selected_ratings.is_empty() {
@@ -183,18 +201,21 @@ impl BlacklistFilter {
         if !self.disabled {
             let fsize = original_list.len();
 
-            let bp = if !self.gbl_tags.is_empty() {
+            if !self.blocked_ratings.is_empty() {
+                original_list.retain(|c| !self.blocked_ratings.contains(&c.rating));
+            }
+
+            if !self.gbl_tags.is_empty() {
                 debug!("Removing posts with tags {:?}", self.gbl_tags);
                 original_list.retain(|c| !c.tags.iter().any(|s| self.gbl_tags.contains(&s.tag())));
-                fsize - original_list.len()
-            } else {
-                0
-            };
+            }
+
+            let bp = fsize - original_list.len();
 
             if self.ignore_animated {
                 original_list.retain(|post| {
                     !(post.tags.contains(&Tag::new("animated", TagType::Meta))
-                        || post.extension.is_video())
+                        || post.source_extension().is_video())
                 });
             }
 
@@ -8,11 +8,12 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ExtractorError {
+    // Posts are boxed to keep every `Result` with this error small
     #[error("Failed to send post through channel")]
-    SyncChannelSendFail(#[from] std::sync::mpsc::SendError<Post>),
+    SyncChannelSendFail(#[source] Box<std::sync::mpsc::SendError<Post>>),
 
     #[error("Failed to send post through channel")]
-    ChannelSendFail(#[from] SendError<Post>),
+    ChannelSendFail(#[source] Box<SendError<Post>>),
 
     #[error("Too many tags, got: {current} while this imageboard only supports a max of {max}")]
     TooManyTags { current: usize, max: u64 },
@@ -56,9 +57,24 @@ pub enum ExtractorError {
     #[error("Unsupported operation for this server")]
     UnsupportedOperation,
 
+    #[error("Invalid pool search: {query}. Use key=value pairs separated by '&'")]
+    InvalidPoolSearch { query: String },
+
     #[error("Error sending length data to progress counter: {source}")]
     SendLengthFail {
         #[from]
         source: SendError<u64>,
     },
 }
+
+impl From<std::sync::mpsc::SendError<Post>> for ExtractorError {
+    fn from(error: std::sync::mpsc::SendError<Post>) -> Self {
+        Self::SyncChannelSendFail(Box::new(error))
+    }
+}
+
+impl From<SendError<Post>> for ExtractorError {
+    fn from(error: SendError<Post>) -> Self {
+        Self::ChannelSendFail(Box::new(error))
+    }
+}
@@ -1,6 +1,6 @@
 #[macro_export]
 macro_rules! server_config {
-    ($name:expr, $pretty_name:expr, $server:expr, $client:expr, $ext:expr, $base_url:expr, $post_url:expr, $post_list_url:expr, $pool_idx_url:expr, $max_post_limit:expr, $auth_url:expr, $image_url: expr) => {
+    ($name:expr, $pretty_name:expr, $server:expr, $client:expr, $ext:expr, $base_url:expr, $post_url:expr, $post_list_url:expr, $pool_idx_url:expr, $max_post_limit:expr, $auth_url:expr, $image_url: expr, $hidden_url_template: expr, $safe_base_url: expr, $safe_post_list_url: expr, $max_tags: expr, $popular_url: expr) => {
         ServerConfig {
             name: String::from($name),
             pretty_name: String::from($pretty_name),
@@ -14,6 +14,12 @@ macro_rules! server_config {
             max_post_limit: $max_post_limit,
             auth_url: $auth_url,
             image_url: $image_url,
+            hidden_url_template: $hidden_url_template,
+            safe_base_url: $safe_base_url,
+            safe_post_list_url: $safe_post_list_url,
+            max_tags: $max_tags,
+            popular_url: $popular_url,
+            mirrored_from: None,
         }
     };
 }
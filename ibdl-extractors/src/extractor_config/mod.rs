@@ -1,5 +1,6 @@
 use ibdl_common::serde;
 use ibdl_common::{
+    reqwest::Url,
     serde::{Deserialize, Serialize},
     ImageBoards,
 };
@@ -59,7 +60,16 @@ pub static DEFAULT_SERVERS: Lazy<HashMap<String, ServerConfig>> = Lazy::new(|| {
             Some(String::from("https://danbooru.donmai.us/pools")),
             200,
             Some(String::from("https://danbooru.donmai.us/profile.json")),
-            None
+            None,
+            Some(String::from(
+                "https://cdn.donmai.us/original/{md5_0_2}/{md5_2_4}/{md5}.{ext}"
+            )),
+            Some(String::from("https://safebooru.donmai.us")),
+            Some(String::from("https://safebooru.donmai.us/posts.json")),
+            Some(2),
+            Some(String::from(
+                "https://danbooru.donmai.us/explore/posts/popular.json"
+            ))
         ),
     );
     hmap.insert(
@@ -76,7 +86,14 @@ pub static DEFAULT_SERVERS: Lazy<HashMap<String, ServerConfig>> = Lazy::new(|| {
             Some(String::from("https://e621.net/pools")),
             320,
             Some(String::from("https://e621.net/users/")),
-            None
+            None,
+            Some(String::from(
+                "https://static1.e621.net/data/{md5_0_2}/{md5_2_4}/{md5}.{ext}"
+            )),
+            Some(String::from("https://e926.net")),
+            Some(String::from("https://e926.net/posts.json")),
+            Some(40),
+            Some(String::from("https://e621.net/popular.json"))
         ),
     );
     hmap.insert(
@@ -95,6 +112,11 @@ pub static DEFAULT_SERVERS: Lazy<HashMap<String, ServerConfig>> = Lazy::new(|| {
             None,
             100,
             None,
+            None,
+            None,
+            None,
+            None,
+            None,
             None
         ),
     );
@@ -114,6 +136,11 @@ pub static DEFAULT_SERVERS: Lazy<HashMap<String, ServerConfig>> = Lazy::new(|| {
             None,
             1000,
             None,
+            None,
+            None,
+            None,
+            None,
+            None,
             None
         ),
     );
@@ -133,6 +160,11 @@ pub static DEFAULT_SERVERS: Lazy<HashMap<String, ServerConfig>> = Lazy::new(|| {
             None,
             1000,
             None,
+            None,
+            None,
+            None,
+            None,
+            None,
             None
         ),
     );
@@ -150,7 +182,14 @@ pub static DEFAULT_SERVERS: Lazy<HashMap<String, ServerConfig>> = Lazy::new(|| {
             None,
             100,
             None,
-            None
+            None,
+            None,
+            Some(String::from("https://konachan.net")),
+            Some(String::from("https://konachan.net/post.json")),
+            Some(6),
+            Some(String::from(
+                "https://konachan.com/post/popular_by_{scale}.json"
+            ))
         ),
     );
     hmap
@@ -171,21 +210,158 @@ pub struct ServerConfig {
     pub max_post_limit: usize,
     pub auth_url: Option<String>,
     pub image_url: Option<String>,
+    /// Template used to rebuild the file url of posts hidden by the imageboard.
+    ///
+    /// Accepts the placeholders `{md5}`, `{md5_0_2}`, `{md5_2_4}` and `{ext}`.
+    ///
+    /// Skipped when serializing, so auth caches written by older versions can still be decoded.
+    #[serde(skip)]
+    pub hidden_url_template: Option<String>,
+    /// Base url of the safe (SFW) mirror of this imageboard, used in safe mode.
+    #[serde(skip)]
+    pub safe_base_url: Option<String>,
+    /// Post list url of the safe mirror. If not set, it's derived from `post_list_url`.
+    #[serde(skip)]
+    pub safe_post_list_url: Option<String>,
+    /// Max number of tags the imageboard accepts in a single search. Unlimited when not set.
+    ///
+    /// Danbooru raises this limit for Gold and Platinum users.
+    #[serde(skip)]
+    pub max_tags: Option<usize>,
+    /// Url of the list of popular posts of a day, week or month.
+    ///
+    /// `{scale}` is replaced by `day`, `week` or `month`, for imageboards that have an endpoint for each.
+    #[serde(skip)]
+    pub popular_url: Option<String>,
+    /// Original base url of a config created by [`ServerConfig::safe_mirror`].
+    #[serde(skip)]
+    mirrored_from: Option<String>,
 }
 
 impl ServerConfig {
     #[inline]
     #[must_use]
     pub fn extractor_features(&self) -> ExtractorFeatures {
-        match self.server {
+        let mut features = match self.server {
             ImageBoards::Danbooru => DanbooruExtractor::features(),
             ImageBoards::E621 => E621Extractor::features(),
             ImageBoards::Moebooru => MoebooruExtractor::features(),
             ImageBoards::Gelbooru | ImageBoards::GelbooruV0_2 => GelbooruExtractor::features(),
+        };
+
+        // Popular posts can only be fetched from servers that have the endpoint set
+        if self.popular_url.is_none() {
+            features.remove(ExtractorFeatures::Popular);
+        }
+
+        features
+    }
+
+    /// Rebuilds the file url of a post hidden by the imageboard from it's MD5 hash and extension.
+    ///
+    /// Returns `None` if this server has no `hidden_url_template` or the hash is malformed.
+    #[must_use]
+    #[allow(clippy::literal_string_with_formatting_args)]
+    pub fn hidden_file_url(&self, md5: &str, ext: &str) -> Option<String> {
+        let template = self.hidden_url_template.as_ref()?;
+
+        if md5.len() < 4 || !md5.is_ascii() {
+            return None;
+        }
+
+        Some(
+            template
+                .replace("{md5_0_2}", &md5[0..2])
+                .replace("{md5_2_4}", &md5[2..4])
+                .replace("{md5}", md5)
+                .replace("{ext}", ext),
+        )
+    }
+
+    /// Returns a copy of this config with every API url pointing to the safe mirror of the imageboard.
+    ///
+    /// Returns `None` if this server has no `safe_base_url`.
+    #[must_use]
+    pub fn safe_mirror(&self) -> Option<Self> {
+        let safe_base_url = self.safe_base_url.as_ref()?;
+
+        let from = Url::parse(&self.base_url).ok()?.host_str()?.to_string();
+        let to = Url::parse(safe_base_url).ok()?.host_str()?.to_string();
+
+        let mirror = |url: &Option<String>| url.as_ref().map(|u| mirror_host(u, &from, &to));
+
+        Some(Self {
+            base_url: safe_base_url.clone(),
+            post_url: mirror(&self.post_url),
+            post_list_url: self
+                .safe_post_list_url
+                .clone()
+                .or_else(|| mirror(&self.post_list_url)),
+            pool_idx_url: mirror(&self.pool_idx_url),
+            popular_url: mirror(&self.popular_url),
+            auth_url: mirror(&self.auth_url),
+            image_url: mirror(&self.image_url),
+            hidden_url_template: mirror(&self.hidden_url_template),
+            mirrored_from: Some(self.base_url.clone()),
+            ..self.clone()
+        })
+    }
+
+    /// Whether this config points to the safe mirror of the imageboard.
+    #[inline]
+    #[must_use]
+    pub const fn is_safe_mirror(&self) -> bool {
+        self.mirrored_from.is_some()
+    }
+
+    /// Routes a file url returned by the API through the safe mirror when this config was created
+    /// by [`ServerConfig::safe_mirror`]. Otherwise, returns the url unchanged.
+    #[must_use]
+    pub fn route_file_url(&self, url: &str) -> String {
+        let Some(original) = &self.mirrored_from else {
+            return url.to_string();
+        };
+
+        let hosts = Url::parse(original)
+            .ok()
+            .zip(Url::parse(&self.base_url).ok());
+
+        match hosts {
+            Some((from, to)) => match (from.host_str(), to.host_str()) {
+                (Some(from), Some(to)) => mirror_host(url, from, to),
+                _ => url.to_string(),
+            },
+            None => url.to_string(),
         }
     }
 }
 
+/// Replaces the host of `url` if it's `from` or one of it's subdomains (e.g. `static1.e621.net`).
+fn mirror_host(url: &str, from: &str, to: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url) else {
+        // Url templates with placeholders are not always valid urls, so fall back to a plain replace.
+        return url.replacen(from, to, 1);
+    };
+
+    let Some(host) = parsed.host_str() else {
+        return url.to_string();
+    };
+
+    let new_host = if host == from {
+        to.to_string()
+    } else if let Some(sub) = host.strip_suffix(&format!(".{from}")) {
+        format!("{sub}.{to}")
+    } else {
+        return url.to_string();
+    };
+
+    if parsed.set_host(Some(&new_host)).is_err() {
+        return url.to_string();
+    }
+
+    parsed.to_string()
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
@@ -201,6 +377,16 @@ impl Default for ServerConfig {
             max_post_limit: 200,
             auth_url: Some(String::from("https://danbooru.donmai.us/profile.json")),
             image_url: None,
+            hidden_url_template: Some(String::from(
+                "https://cdn.donmai.us/original/{md5_0_2}/{md5_2_4}/{md5}.{ext}",
+            )),
+            safe_base_url: Some(String::from("https://safebooru.donmai.us")),
+            safe_post_list_url: Some(String::from("https://safebooru.donmai.us/posts.json")),
+            max_tags: Some(2),
+            popular_url: Some(String::from(
+                "https://danbooru.donmai.us/explore/posts/popular.json",
+            )),
+            mirrored_from: None,
         }
     }
 }
@@ -31,6 +31,11 @@ struct Server {
     max_post_limit: usize,
     auth_url: Option<String>,
     image_url: Option<String>,
+    hidden_url_template: Option<String>,
+    safe_base_url: Option<String>,
+    safe_post_list_url: Option<String>,
+    max_tags: Option<usize>,
+    popular_url: Option<String>,
 }
 
 pub fn read_server_cfg_file<S: std::hash::BuildHasher>(
@@ -62,6 +67,12 @@ pub fn read_server_cfg_file<S: std::hash::BuildHasher>(
             max_post_limit: data.max_post_limit,
             auth_url: data.auth_url,
             image_url: data.image_url,
+            hidden_url_template: data.hidden_url_template,
+            safe_base_url: data.safe_base_url,
+            safe_post_list_url: data.safe_post_list_url,
+            max_tags: data.max_tags,
+            popular_url: data.popular_url,
+            mirrored_from: None,
         };
         smap.insert(id, config);
     }
@@ -4,24 +4,33 @@
 //! - Authentication
 //! - Native blacklist (defined in user profile page)
 //!
-use self::models::DanbooruPost;
+use self::models::{DanbooruMediaMetadataResponse, DanbooruPost};
 
-use super::{Auth, Extractor, ExtractorFeatures, ServerConfig, SinglePostFetch};
+use super::{
+    AbortSignal, Auth, Extractor, ExtractorFeatures, HiddenPostCounter, HiddenPostRecovery,
+    PoolInfo, PopularFetch, PopularQuery, ServerConfig, SinglePostFetch,
+};
 use crate::auth::{AuthState, ImageboardConfig};
 use crate::extractor_config::DEFAULT_SERVERS;
+use crate::query::{fetch_post_counts, QueryPlan};
+use crate::rate_limit::wait_for_slot;
 use crate::{blacklist::BlacklistFilter, error::ExtractorError};
+use ahash::HashMap;
 use ibdl_common::post::extension::Extension;
+use ibdl_common::post::variant::FileVariant;
 use ibdl_common::reqwest::Method;
+use ibdl_common::serde::{self, Deserialize};
 use ibdl_common::serde_json;
 use ibdl_common::tokio::time::{sleep, Instant};
 use ibdl_common::{
     client, join_tags,
-    log::debug,
+    log::{debug, warn},
     post::{rating::Rating, Post, PostQueue},
     reqwest::Client,
     ImageBoards,
 };
 use std::fmt::Display;
+use std::sync::Arc;
 use std::time::Duration;
 
 mod models;
@@ -42,9 +51,17 @@ pub struct DanbooruExtractor {
     map_videos: bool,
     excluded_tags: Vec<String>,
     selected_extension: Option<Extension>,
-    extra_tags: Vec<String>,
+    variant: FileVariant,
+    newer_than: Option<u64>,
+    abort_signal: Option<AbortSignal>,
     pool_id: Option<u32>,
     pool_last_items_first: bool,
+    pool_info: Option<PoolInfo>,
+    pool_saved_pages: HashMap<u64, u64>,
+    popular: Option<PopularQuery>,
+    recover_hidden: bool,
+    hidden_posts: Arc<HiddenPostCounter>,
+    fetch_ugoira_frames: bool,
     server_cfg: ServerConfig,
 }
 
@@ -63,7 +80,7 @@ impl Extractor for DanbooruExtractor {
         // Use common client for all connections with a set User-Agent
         let client = client!(config);
 
-        let mut strvec: Vec<String> = tags
+        let strvec: Vec<String> = tags
             .iter()
             .map(|t| {
                 let st: String = t.to_string();
@@ -71,16 +88,7 @@ impl Extractor for DanbooruExtractor {
             })
             .collect();
 
-        let extra_tags = if strvec.len() > 2 {
-            strvec.split_off(1)
-        } else {
-            Vec::with_capacity(strvec.len().saturating_sub(2))
-        };
-
         debug!("Tag List: {:?}", strvec);
-        if !extra_tags.is_empty() {
-            debug!("Extra tags: {:?}", extra_tags);
-        }
 
         // Merge all tags in the URL format
         let tag_string = join_tags!(strvec);
@@ -97,9 +105,17 @@ impl Extractor for DanbooruExtractor {
             map_videos,
             excluded_tags: vec![],
             selected_extension: None,
-            extra_tags,
+            variant: FileVariant::Original,
+            newer_than: None,
+            abort_signal: None,
             pool_id: None,
             pool_last_items_first: false,
+            pool_info: None,
+            pool_saved_pages: HashMap::default(),
+            popular: None,
+            recover_hidden: false,
+            hidden_posts: Arc::default(),
+            fetch_ugoira_frames: false,
             server_cfg: config,
         }
     }
@@ -117,7 +133,7 @@ impl Extractor for DanbooruExtractor {
         // Use common client for all connections with a set User-Agent
         let client = client!(config);
 
-        let mut strvec: Vec<String> = tags
+        let strvec: Vec<String> = tags
             .iter()
             .map(|t| {
                 let st: String = t.to_string();
@@ -125,16 +141,7 @@ impl Extractor for DanbooruExtractor {
             })
             .collect();
 
-        let extra_tags = if strvec.len() > 2 {
-            strvec.split_off(1)
-        } else {
-            Vec::with_capacity(strvec.len().saturating_sub(2))
-        };
-
         debug!("Tag List: {:?}", strvec);
-        if !extra_tags.is_empty() {
-            debug!("Extra tags: {:?}", extra_tags);
-        }
 
         // Merge all tags in the URL format
         let tag_string = join_tags!(strvec);
@@ -151,15 +158,23 @@ impl Extractor for DanbooruExtractor {
             map_videos,
             excluded_tags: vec![],
             selected_extension: None,
-            extra_tags,
+            variant: FileVariant::Original,
+            newer_than: None,
+            abort_signal: None,
             pool_id: None,
             pool_last_items_first: false,
+            pool_info: None,
+            pool_saved_pages: HashMap::default(),
+            popular: None,
+            recover_hidden: false,
+            hidden_posts: Arc::default(),
+            fetch_ugoira_frames: false,
             server_cfg: config,
         }
     }
 
     fn features() -> ExtractorFeatures {
-        ExtractorFeatures::from_bits_truncate(0b0001_1111) // AsyncFetch + TagSearch + SinglePostDownload + PoolDownload + Auth (Everything)
+        ExtractorFeatures::from_bits_truncate(0b0011_1111) // AsyncFetch + TagSearch + SinglePostDownload + PoolDownload + Auth + Popular (Everything)
     }
 
     fn config(&self) -> ServerConfig {
@@ -191,6 +206,21 @@ impl Extractor for DanbooruExtractor {
         self
     }
 
+    fn select_variant(&mut self, variant: FileVariant) -> &mut Self {
+        self.variant = variant;
+        self
+    }
+
+    fn newer_than(&mut self, post_id: Option<u64>) -> &mut Self {
+        self.newer_than = post_id;
+        self
+    }
+
+    fn abort_signal(&mut self, signal: Option<AbortSignal>) -> &mut Self {
+        self.abort_signal = signal;
+        self
+    }
+
     async fn full_search(
         &mut self,
         start_page: Option<u16>,
@@ -265,13 +295,21 @@ impl Extractor for DanbooruExtractor {
     }
 
     async fn get_post_list(&self, page: u16) -> Result<Vec<Post>, ExtractorError> {
-        if self.server_cfg.post_list_url.is_none() {
-            return Err(ExtractorError::UnsupportedOperation);
+        let mut request = if let Some(popular) = &self.popular {
+            self.client
+                .request(Method::GET, popular.url(&self.server_cfg)?)
+                .query(&popular.params())
+        } else {
+            let Some(url) = &self.server_cfg.post_list_url else {
+                return Err(ExtractorError::UnsupportedOperation);
+            };
+
+            self.client
+                .request(Method::GET, url)
+                .query(&[("tags", &self.tag_string)])
         };
 
-        let mut request = self
-            .client
-            .request(Method::GET, self.server_cfg.post_list_url.as_ref().unwrap());
+        wait_for_slot(&self.server_cfg.name).await;
 
         // Fetch item list from page
         if self.auth_state.is_auth() {
@@ -284,17 +322,22 @@ impl Extractor for DanbooruExtractor {
         let req = request.query(&[
             ("page", &page.to_string()),
             ("limit", &self.server_cfg.max_post_limit.to_string()),
-            ("tags", &self.tag_string),
         ]);
 
         let post_array = req.send().await?.text().await?;
 
         let start_point = Instant::now();
 
-        let mtx = self.map_posts(post_array)?;
+        let mut mtx = self.map_posts(post_array)?;
 
         let end_iter = start_point.elapsed();
 
+        if self.fetch_ugoira_frames {
+            for post in mtx.iter_mut() {
+                self.try_fill_frame_delays(post).await;
+            }
+        }
+
         debug!("List size: {}", mtx.len());
         debug!("Post mapping took {:?}", end_iter);
         Ok(mtx)
@@ -304,28 +347,9 @@ impl Extractor for DanbooruExtractor {
         let parsed_json: Vec<DanbooruPost> =
             serde_json::from_str::<Vec<DanbooruPost>>(raw_json.as_str())?;
 
-        let batch = parsed_json.into_iter().filter(|c| c.file_url.is_some());
-
-        let mapper_iter = batch.map(|c| {
-            let tag_list = c.map_tags();
-
-            let rt = c.rating.unwrap();
-            let rating = if rt == "s" {
-                Rating::Questionable
-            } else {
-                Rating::from_rating_str(&rt)
-            };
-
-            Post {
-                id: c.id.unwrap(),
-                website: ImageBoards::Danbooru,
-                md5: c.md5.unwrap(),
-                url: c.file_url.unwrap(),
-                extension: Extension::guess_format(&c.file_ext.unwrap()),
-                tags: tag_list,
-                rating,
-            }
-        });
+        let mapper_iter = parsed_json
+            .into_iter()
+            .filter_map(|c| self.map_danbooru_post(c));
 
         Ok(mapper_iter.collect::<Vec<Post>>())
     }
@@ -348,6 +372,209 @@ impl Extractor for DanbooruExtractor {
     }
 }
 
+impl DanbooruExtractor {
+    /// Splits the tags between the search and the local filter, so the search fits in the tag
+    /// limit of the user.
+    async fn plan_query(&mut self) -> Result<QueryPlan, ExtractorError> {
+        let mut max_tags = self.server_cfg.max_tags;
+
+        let counts = if QueryPlan::fits(&self.tags, max_tags) {
+            HashMap::default()
+        } else {
+            max_tags = self.tag_limit().await.or(max_tags);
+            self.post_counts().await
+        };
+
+        let plan = QueryPlan::new(&self.tags, &self.excluded_tags, max_tags, &counts)?;
+        self.tag_string = plan.tag_string();
+
+        Ok(plan)
+    }
+
+    /// Tag limit of the authenticated user, based on it's account level.
+    async fn tag_limit(&self) -> Option<usize> {
+        #[derive(Debug, Deserialize)]
+        #[serde(crate = "self::serde")]
+        struct Profile {
+            level: u32,
+        }
+
+        if !self.auth_state.is_auth() {
+            return None;
+        }
+
+        wait_for_slot(&self.server_cfg.name).await;
+
+        let profile = self
+            .client
+            .get(self.server_cfg.auth_url.as_ref()?)
+            .basic_auth(&self.auth.username, Some(&self.auth.api_key))
+            .send()
+            .await
+            .ok()?
+            .json::<Profile>()
+            .await
+            .ok()?;
+
+        debug!("User level: {}", profile.level);
+
+        // Gold users can search 6 tags, Platinum and above 12
+        match profile.level {
+            31.. => Some(12),
+            30 => Some(6),
+            _ => None,
+        }
+    }
+
+    async fn post_counts(&self) -> HashMap<String, u64> {
+        let names = QueryPlan::countable(&self.tags)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(",");
+
+        wait_for_slot(&self.server_cfg.name).await;
+
+        let request = self
+            .client
+            .get(format!("{}/tags.json", self.server_cfg.base_url))
+            .query(&[
+                ("search[name_comma]", names.as_str()),
+                ("only", "name,post_count"),
+            ]);
+
+        fetch_post_counts(request).await.into_iter().collect()
+    }
+
+    /// Also fetch the frame delays of Ugoira posts from their media metadata, so they can be
+    /// converted into animated images after download.
+    ///
+    /// This costs one extra API call for each Ugoira post found.
+    pub fn fetch_ugoira_frames(&mut self, fetch: bool) -> &mut Self {
+        self.fetch_ugoira_frames = fetch;
+        self
+    }
+
+    /// Same as [`fill_frame_delays`](Self::fill_frame_delays), but a failure only leaves the post
+    /// without frame delays, so the Ugoira is saved as the original zip.
+    async fn try_fill_frame_delays(&self, post: &mut Post) {
+        if let Err(error) = self.fill_frame_delays(post).await {
+            warn!(
+                "Failed to fetch the frame delays of post {}: {error}",
+                post.id
+            );
+        }
+    }
+
+    async fn fill_frame_delays(&self, post: &mut Post) -> Result<(), ExtractorError> {
+        if post.extension != Extension::Ugoira || post.frame_delays.is_some() {
+            return Ok(());
+        }
+
+        let Some(post_url) = &self.server_cfg.post_url else {
+            return Ok(());
+        };
+
+        let url = format!("{}/{}.json", post_url.trim_end_matches('/'), post.id);
+
+        let mut request = self.client.get(url).query(&[("only", "media_metadata")]);
+
+        if self.auth_state.is_auth() {
+            request = request.basic_auth(&self.auth.username, Some(&self.auth.api_key));
+        }
+
+        debug!("Fetching Ugoira frame delays of post {}", post.id);
+
+        wait_for_slot(&self.server_cfg.name).await;
+
+        let raw_json = request.send().await?.text().await?;
+        let parsed: DanbooruMediaMetadataResponse = serde_json::from_str(&raw_json)?;
+
+        post.frame_delays = parsed
+            .media_metadata
+            .and_then(|m| m.metadata)
+            .and_then(|m| m.ugoira_frame_delays);
+
+        Ok(())
+    }
+
+    /// Maps a raw danbooru post, rebuilding it's file url from the MD5 hash if it was hidden and
+    /// hidden post recovery is enabled.
+    fn map_danbooru_post(&self, c: DanbooruPost) -> Option<Post> {
+        let tag_list = c.map_tags();
+        let sample = c
+            .media_variant(&["sample"])
+            .or_else(|| c.large_file_url.clone());
+        let preview = c
+            .media_variant(&["360x360", "180x180"])
+            .or_else(|| c.preview_file_url.clone());
+
+        // Posts missing any of these are dropped, so they must not count as hidden or recovered
+        let id = c.id?;
+        let md5 = c.md5?;
+        let file_ext = c.file_ext?;
+        let rating = Rating::from_board_str(&c.rating?, ImageBoards::Danbooru);
+
+        let url = match c.file_url {
+            Some(url) => url,
+            None => {
+                self.hidden_posts.add_hidden();
+
+                if !self.recover_hidden {
+                    return None;
+                }
+
+                let url = self.server_cfg.hidden_file_url(&md5, &file_ext)?;
+
+                debug!("Recovered hidden post {id}");
+                self.hidden_posts.add_recovered();
+                url
+            }
+        };
+
+        let mut post = Post {
+            id,
+            website: ImageBoards::Danbooru,
+            md5,
+            url,
+            extension: Extension::guess_format(&file_ext),
+            tags: tag_list,
+            rating,
+            frame_delays: None,
+            variant: FileVariant::Original,
+            original_extension: None,
+            pool_post_id: None,
+            server: None,
+        };
+
+        post.set_variant(self.variant, self.variant.pick(sample, preview));
+
+        Some(post)
+    }
+}
+
+impl PopularFetch for DanbooruExtractor {
+    fn setup_popular(&mut self, query: PopularQuery) -> &mut Self {
+        self.popular = Some(query);
+        self
+    }
+}
+
+impl HiddenPostRecovery for DanbooruExtractor {
+    fn recover_hidden_posts(&mut self, recover: bool) -> &mut Self {
+        self.recover_hidden = recover;
+        self
+    }
+
+    fn hidden_post_counter(&self) -> Arc<HiddenPostCounter> {
+        self.hidden_posts.clone()
+    }
+
+    fn share_hidden_counter(&mut self, counter: Arc<HiddenPostCounter>) -> &mut Self {
+        self.hidden_posts = counter;
+        self
+    }
+}
+
 impl Auth for DanbooruExtractor {
     async fn auth(&mut self, config: ImageboardConfig) -> Result<(), ExtractorError> {
         let mut cfg = config;
@@ -365,26 +592,8 @@ impl SinglePostFetch for DanbooruExtractor {
     fn map_post(&self, raw_json: String) -> Result<Post, ExtractorError> {
         let parsed_json: DanbooruPost = serde_json::from_str::<DanbooruPost>(raw_json.as_str())?;
 
-        let tag_list = parsed_json.map_tags();
-
-        let rt = parsed_json.rating.unwrap();
-        let rating = if rt == "s" {
-            Rating::Questionable
-        } else {
-            Rating::from_rating_str(&rt)
-        };
-
-        let post = Post {
-            id: parsed_json.id.unwrap(),
-            website: ImageBoards::Danbooru,
-            md5: parsed_json.md5.unwrap(),
-            url: parsed_json.file_url.unwrap(),
-            extension: Extension::guess_format(&parsed_json.file_ext.unwrap()),
-            tags: tag_list,
-            rating,
-        };
-
-        Ok(post)
+        self.map_danbooru_post(parsed_json)
+            .ok_or(ExtractorError::ZeroPosts)
     }
 
     async fn get_post(&mut self, post_id: u32) -> Result<Post, ExtractorError> {
@@ -392,6 +601,8 @@ impl SinglePostFetch for DanbooruExtractor {
             return Err(ExtractorError::UnsupportedOperation);
         };
 
+        wait_for_slot(&self.server_cfg.name).await;
+
         let url = format!(
             "{}/{}.json",
             self.server_cfg.post_url.as_ref().unwrap(),
@@ -413,10 +624,14 @@ impl SinglePostFetch for DanbooruExtractor {
 
         let start_point = Instant::now();
 
-        let mtx = self.map_post(post_array)?;
+        let mut mtx = self.map_post(post_array)?;
 
         let end_iter = start_point.elapsed();
 
+        if self.fetch_ugoira_frames {
+            self.try_fill_frame_delays(&mut mtx).await;
+        }
+
         debug!("Post mapping took {:?}", end_iter);
         Ok(mtx)
     }
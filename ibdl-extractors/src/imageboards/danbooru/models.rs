@@ -6,6 +6,10 @@ use ibdl_common::{
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(crate = "self::serde")]
 pub struct DanbooruPoolList {
+    pub id: Option<u32>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub category: Option<String>,
     pub post_ids: Vec<u64>,
 }
 
@@ -15,6 +19,9 @@ pub struct DanbooruPost {
     pub id: Option<u64>,
     pub md5: Option<String>,
     pub file_url: Option<String>,
+    pub large_file_url: Option<String>,
+    pub preview_file_url: Option<String>,
+    pub media_asset: Option<DanbooruMediaAsset>,
     pub tag_string: Option<String>,
     pub tag_string_general: Option<String>,
     pub tag_string_character: Option<String>,
@@ -25,7 +32,33 @@ pub struct DanbooruPost {
     pub rating: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "self::serde")]
+pub struct DanbooruMediaAsset {
+    pub variants: Option<Vec<DanbooruMediaVariant>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "self::serde")]
+pub struct DanbooruMediaVariant {
+    #[serde(rename = "type")]
+    pub variant_type: String,
+    pub url: String,
+}
+
 impl DanbooruPost {
+    /// Returns the url of the first media asset variant found with one of the given types.
+    pub fn media_variant(&self, types: &[&str]) -> Option<String> {
+        let variants = self.media_asset.as_ref()?.variants.as_ref()?;
+
+        types.iter().find_map(|t| {
+            variants
+                .iter()
+                .find(|v| v.variant_type == *t)
+                .map(|v| v.url.clone())
+        })
+    }
+
     pub fn map_tags(&self) -> Vec<Tag> {
         let mut tags = Vec::with_capacity(64);
         if let Some(tagstr) = &self.tag_string_artist {
@@ -55,3 +88,23 @@ impl DanbooruPost {
         tags
     }
 }
+
+/// Response of `posts/{id}.json?only=media_metadata`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct DanbooruMediaMetadataResponse {
+    pub media_metadata: Option<DanbooruMediaMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct DanbooruMediaMetadata {
+    pub metadata: Option<DanbooruFileMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "self::serde")]
+pub struct DanbooruFileMetadata {
+    #[serde(rename = "Ugoira:FrameDelays")]
+    pub ugoira_frame_delays: Option<Vec<u32>>,
+}
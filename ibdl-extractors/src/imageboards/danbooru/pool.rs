@@ -4,16 +4,20 @@ use ibdl_common::{
     serde_json,
 };
 
-use crate::{error::ExtractorError, imageboards::PoolExtract};
+use crate::{
+    error::ExtractorError,
+    imageboards::{pool_search_params, PoolExtract, PoolInfo, POOL_SEARCH_LIMIT},
+    rate_limit::wait_for_slot,
+};
 
 use super::{models::DanbooruPoolList, DanbooruExtractor};
 
 impl PoolExtract for DanbooruExtractor {
-    async fn fetch_pool_idxs(
-        &mut self,
-        pool_id: u32,
-        limit: Option<u16>,
-    ) -> Result<HashMap<u64, usize>, ExtractorError> {
+    async fn fetch_pool_info(&mut self, pool_id: u32) -> Result<PoolInfo, ExtractorError> {
+        if let Some(info) = self.pool_info.as_ref().filter(|info| info.id == pool_id) {
+            return Ok(info.clone());
+        }
+
         if self.server_cfg.pool_idx_url.is_none() {
             return Err(ExtractorError::UnsupportedOperation);
         };
@@ -35,9 +39,23 @@ impl PoolExtract for DanbooruExtractor {
             self.client.get(url)
         };
 
+        wait_for_slot(&self.server_cfg.name).await;
+
         let post_array = req.send().await?.text().await?;
 
-        let mut mtx = self.parse_pool_ids(post_array)?;
+        let mut info = self.parse_pool_info(post_array)?;
+        info.id = pool_id;
+
+        self.pool_info = Some(info.clone());
+        Ok(info)
+    }
+
+    async fn fetch_pool_idxs(
+        &mut self,
+        pool_id: u32,
+        limit: Option<u16>,
+    ) -> Result<HashMap<u64, usize>, ExtractorError> {
+        let mut mtx = self.fetch_pool_info(pool_id).await?.post_ids;
 
         if self.pool_last_items_first {
             mtx.reverse();
@@ -47,10 +65,23 @@ impl PoolExtract for DanbooruExtractor {
             mtx.truncate(limit_post as usize);
         }
 
+        // Posts that were already downloaded keep their page, while new ones go after the last page
+        let mut next_page = self
+            .pool_saved_pages
+            .values()
+            .max()
+            .map_or(0, |last| last + 1);
+
         let position_map = mtx
             .iter()
-            .enumerate()
-            .map(|(position, id)| (*id, position))
+            .map(|id| {
+                let position = self.pool_saved_pages.get(id).copied().unwrap_or_else(|| {
+                    next_page += 1;
+                    next_page - 1
+                });
+
+                (*id, position as usize)
+            })
             .collect::<HashMap<u64, usize>>();
 
         trace!("Pool post positions: {:#?}", position_map);
@@ -59,14 +90,93 @@ impl PoolExtract for DanbooruExtractor {
     }
 
     fn parse_pool_ids(&self, raw_json: String) -> Result<Vec<u64>, ExtractorError> {
+        Ok(self.parse_pool_info(raw_json)?.post_ids)
+    }
+
+    fn parse_pool_info(&self, raw_json: String) -> Result<PoolInfo, ExtractorError> {
         let parsed_json: DanbooruPoolList =
             serde_json::from_str::<DanbooruPoolList>(raw_json.as_str())?;
 
-        Ok(parsed_json.post_ids)
+        Ok(self.map_pool(parsed_json))
+    }
+
+    fn parse_pool_list(&self, raw_json: String) -> Result<Vec<PoolInfo>, ExtractorError> {
+        let parsed_json: Vec<DanbooruPoolList> = serde_json::from_str(raw_json.as_str())?;
+
+        Ok(parsed_json
+            .into_iter()
+            .map(|pool| self.map_pool(pool))
+            .collect())
+    }
+
+    async fn search_pools(&mut self, query: &str) -> Result<Vec<PoolInfo>, ExtractorError> {
+        let Some(pool_url) = &self.server_cfg.pool_idx_url else {
+            return Err(ExtractorError::UnsupportedOperation);
+        };
+
+        let url = format!("{pool_url}.json");
+        let params = pool_search_params(query)?;
+
+        let mut pools = Vec::new();
+
+        for page in 1..=100 {
+            let req = self
+                .client
+                .get(&url)
+                .query(&params)
+                .query(&[("limit", POOL_SEARCH_LIMIT), ("page", page)]);
+
+            let req = if self.auth_state.is_auth() {
+                debug!("[AUTH] Searching pools, page {}", page);
+                req.basic_auth(&self.auth.username, Some(&self.auth.api_key))
+            } else {
+                debug!("Searching pools, page {}", page);
+                req
+            };
+
+            wait_for_slot(&self.server_cfg.name).await;
+
+            let found = self.parse_pool_list(req.send().await?.text().await?)?;
+            let size = found.len();
+
+            pools.extend(found);
+
+            if size < POOL_SEARCH_LIMIT {
+                break;
+            }
+        }
+
+        debug!("Found {} pools", pools.len());
+        Ok(pools)
     }
 
     fn setup_pool_download(&mut self, pool_id: Option<u32>, last_first: bool) {
         self.pool_id = pool_id;
         self.pool_last_items_first = last_first;
     }
+
+    fn resume_pool(&mut self, saved_pages: impl IntoIterator<Item = (u64, u64)>) {
+        self.pool_saved_pages = saved_pages.into_iter().collect();
+        debug!("Pages already downloaded: {}", self.pool_saved_pages.len());
+    }
+}
+
+impl DanbooruExtractor {
+    fn map_pool(&self, pool: DanbooruPoolList) -> PoolInfo {
+        let id = pool.id.unwrap_or_default();
+
+        PoolInfo {
+            id,
+            name: pool.name.unwrap_or_default(),
+            description: pool.description.unwrap_or_default(),
+            category: pool.category,
+            creator: None,
+            post_ids: pool.post_ids,
+            url: self
+                .server_cfg
+                .pool_idx_url
+                .as_ref()
+                .map(|url| format!("{url}/{id}")),
+        }
+    }
 }
@@ -6,7 +6,11 @@
 //!
 use crate::auth::{AuthState, ImageboardConfig};
 use crate::extractor_config::DEFAULT_SERVERS;
+use crate::query::{fetch_post_counts, QueryPlan};
+use crate::rate_limit::wait_for_slot;
+use ahash::HashMap;
 use ibdl_common::post::extension::Extension;
+use ibdl_common::post::variant::FileVariant;
 use ibdl_common::reqwest::{Client, Method};
 use ibdl_common::serde_json;
 use ibdl_common::{
@@ -16,6 +20,7 @@ use ibdl_common::{
     tokio, ImageBoards,
 };
 use std::fmt::Display;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::{sleep, Instant};
 
@@ -25,7 +30,10 @@ use crate::{
 
 use self::models::E621Post;
 
-use super::{Auth, Extractor, ExtractorFeatures, ServerConfig, SinglePostFetch};
+use super::{
+    AbortSignal, Auth, Extractor, ExtractorFeatures, HiddenPostCounter, HiddenPostRecovery,
+    PoolInfo, PopularFetch, PopularQuery, ServerConfig, SinglePostFetch,
+};
 
 mod models;
 mod pool;
@@ -47,8 +55,16 @@ pub struct E621Extractor {
     map_videos: bool,
     excluded_tags: Vec<String>,
     selected_extension: Option<Extension>,
+    variant: FileVariant,
+    newer_than: Option<u64>,
+    abort_signal: Option<AbortSignal>,
     pool_id: Option<u32>,
     pool_last_items_first: bool,
+    pool_info: Option<PoolInfo>,
+    pool_saved_pages: HashMap<u64, u64>,
+    popular: Option<PopularQuery>,
+    recover_hidden: bool,
+    hidden_posts: Arc<HiddenPostCounter>,
     server_cfg: ServerConfig,
 }
 
@@ -91,8 +107,16 @@ impl Extractor for E621Extractor {
             map_videos,
             excluded_tags: vec![],
             selected_extension: None,
+            variant: FileVariant::Original,
+            newer_than: None,
+            abort_signal: None,
             pool_id: None,
             pool_last_items_first: false,
+            pool_info: None,
+            pool_saved_pages: HashMap::default(),
+            popular: None,
+            recover_hidden: false,
+            hidden_posts: Arc::default(),
             server_cfg: config,
         }
     }
@@ -134,14 +158,22 @@ impl Extractor for E621Extractor {
             map_videos,
             excluded_tags: vec![],
             selected_extension: None,
+            variant: FileVariant::Original,
+            newer_than: None,
+            abort_signal: None,
             pool_id: None,
             pool_last_items_first: false,
+            pool_info: None,
+            pool_saved_pages: HashMap::default(),
+            popular: None,
+            recover_hidden: false,
+            hidden_posts: Arc::default(),
             server_cfg: config,
         }
     }
 
     fn features() -> ExtractorFeatures {
-        ExtractorFeatures::from_bits_truncate(0b0001_1111) // AsyncFetch + TagSearch + SinglePostDownload + PoolDownload + Auth (Everything)
+        ExtractorFeatures::from_bits_truncate(0b0011_1111) // AsyncFetch + TagSearch + SinglePostDownload + PoolDownload + Auth + Popular (Everything)
     }
 
     fn config(&self) -> ServerConfig {
@@ -173,6 +205,21 @@ impl Extractor for E621Extractor {
         self
     }
 
+    fn select_variant(&mut self, variant: FileVariant) -> &mut Self {
+        self.variant = variant;
+        self
+    }
+
+    fn newer_than(&mut self, post_id: Option<u64>) -> &mut Self {
+        self.newer_than = post_id;
+        self
+    }
+
+    fn abort_signal(&mut self, signal: Option<AbortSignal>) -> &mut Self {
+        self.abort_signal = signal;
+        self
+    }
+
     async fn full_search(
         &mut self,
         start_page: Option<u16>,
@@ -250,13 +297,28 @@ impl Extractor for E621Extractor {
     }
 
     async fn get_post_list(&self, page: u16) -> Result<Vec<Post>, ExtractorError> {
-        if self.server_cfg.post_list_url.is_none() {
-            return Err(ExtractorError::UnsupportedOperation);
+        let mut request = if let Some(popular) = &self.popular {
+            // The whole list fits in a single page
+            if page > 1 {
+                return Ok(Vec::new());
+            }
+
+            self.client
+                .request(Method::GET, popular.url(&self.server_cfg)?)
+                .query(&popular.params())
+        } else {
+            let Some(url) = &self.server_cfg.post_list_url else {
+                return Err(ExtractorError::UnsupportedOperation);
+            };
+
+            self.client.request(Method::GET, url).query(&[
+                ("page", &page.to_string()),
+                ("limit", &self.server_cfg.max_post_limit.to_string()),
+                ("tags", &self.tag_string),
+            ])
         };
 
-        let mut request = self
-            .client
-            .request(Method::GET, self.server_cfg.post_list_url.as_ref().unwrap());
+        wait_for_slot(&self.server_cfg.name).await;
 
         // Fetch item list from page
         if self.auth_state.is_auth() {
@@ -266,13 +328,7 @@ impl Extractor for E621Extractor {
             debug!("Fetching posts from page {}", page);
         };
 
-        let req = request.query(&[
-            ("page", &page.to_string()),
-            ("limit", &self.server_cfg.max_post_limit.to_string()),
-            ("tags", &self.tag_string),
-        ]);
-
-        let items = req.send().await?.text().await?;
+        let items = request.send().await?.text().await?;
 
         let start_point = Instant::now();
 
@@ -286,27 +342,13 @@ impl Extractor for E621Extractor {
     }
 
     fn map_posts(&self, raw_json: String) -> Result<Vec<Post>, ExtractorError> {
-        let mut items: E621TopLevel = serde_json::from_str(raw_json.as_str()).unwrap();
-
-        let post_iter = items.posts.iter_mut().filter(|c| c.file.url.is_some());
+        let items: E621TopLevel = serde_json::from_str(raw_json.as_str()).unwrap();
 
-        let mut post_list: Vec<Post> = Vec::with_capacity(post_iter.size_hint().0);
-
-        post_iter.for_each(|c| {
-            let tag_list = c.tags.map_tags();
-
-            let unit = Post {
-                id: c.id.unwrap(),
-                website: ImageBoards::E621,
-                url: c.file.url.clone().unwrap(),
-                md5: c.file.md5.clone().unwrap(),
-                extension: Extension::guess_format(&c.file.ext.clone().unwrap()),
-                tags: tag_list,
-                rating: Rating::from_rating_str(&c.rating),
-            };
-
-            post_list.push(unit);
-        });
+        let post_list = items
+            .posts
+            .into_iter()
+            .filter_map(|c| self.map_e621_post(c))
+            .collect::<Vec<Post>>();
 
         Ok(post_list)
     }
@@ -329,6 +371,124 @@ impl Extractor for E621Extractor {
     }
 }
 
+impl E621Extractor {
+    /// Splits the tags between the search and the local filter, so the search fits in the tag
+    /// limit of the server.
+    async fn plan_query(&mut self) -> Result<QueryPlan, ExtractorError> {
+        let counts = if QueryPlan::fits(&self.tags, self.server_cfg.max_tags) {
+            HashMap::default()
+        } else {
+            self.post_counts().await
+        };
+
+        let plan = QueryPlan::new(
+            &self.tags,
+            &self.excluded_tags,
+            self.server_cfg.max_tags,
+            &counts,
+        )?;
+        self.tag_string = plan.tag_string();
+
+        Ok(plan)
+    }
+
+    async fn post_counts(&self) -> HashMap<String, u64> {
+        let tags = QueryPlan::countable(&self.tags).collect::<Vec<_>>();
+
+        let names = tags
+            .iter()
+            .map(|tag| tag.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        wait_for_slot(&self.server_cfg.name).await;
+
+        let request = self
+            .client
+            .get(format!("{}/tags.json", self.server_cfg.base_url))
+            .query(&[
+                ("search[name]", names.as_str()),
+                ("limit", &tags.len().to_string()),
+            ]);
+
+        fetch_post_counts(request).await.into_iter().collect()
+    }
+
+    /// Maps a raw e621 post, rebuilding it's file url from the MD5 hash if it was hidden and
+    /// hidden post recovery is enabled.
+    fn map_e621_post(&self, c: E621Post) -> Option<Post> {
+        // Posts missing any of these are dropped, so they must not count as hidden or recovered
+        let id = c.id?;
+        let md5 = c.file.md5?;
+        let file_ext = c.file.ext?;
+
+        let url = match c.file.url {
+            Some(url) => url,
+            None => {
+                self.hidden_posts.add_hidden();
+
+                if !self.recover_hidden {
+                    return None;
+                }
+
+                let url = self.server_cfg.hidden_file_url(&md5, &file_ext)?;
+
+                debug!("Recovered hidden post {id}");
+                self.hidden_posts.add_recovered();
+                url
+            }
+        };
+
+        let sample = c
+            .sample
+            .filter(|s| s.has.unwrap_or_default())
+            .and_then(|s| s.url);
+        let preview = c.preview.and_then(|p| p.url);
+
+        let mut post = Post {
+            id,
+            website: ImageBoards::E621,
+            url,
+            md5,
+            extension: Extension::guess_format(&file_ext),
+            tags: c.tags.map_tags(),
+            rating: Rating::from_board_str(&c.rating, ImageBoards::E621),
+            frame_delays: None,
+            variant: FileVariant::Original,
+            original_extension: None,
+            pool_post_id: None,
+            server: None,
+        };
+
+        post.set_variant(self.variant, self.variant.pick(sample, preview));
+
+        Some(post)
+    }
+}
+
+impl PopularFetch for E621Extractor {
+    fn setup_popular(&mut self, query: PopularQuery) -> &mut Self {
+        self.popular = Some(query);
+        self
+    }
+}
+
+impl HiddenPostRecovery for E621Extractor {
+    fn recover_hidden_posts(&mut self, recover: bool) -> &mut Self {
+        self.recover_hidden = recover;
+        self
+    }
+
+    fn hidden_post_counter(&self) -> Arc<HiddenPostCounter> {
+        self.hidden_posts.clone()
+    }
+
+    fn share_hidden_counter(&mut self, counter: Arc<HiddenPostCounter>) -> &mut Self {
+        self.hidden_posts = counter;
+        self
+    }
+}
+
 impl Auth for E621Extractor {
     async fn auth(&mut self, config: ImageboardConfig) -> Result<(), ExtractorError> {
         let mut cfg = config;
@@ -347,22 +507,7 @@ impl SinglePostFetch for E621Extractor {
     fn map_post(&self, raw_json: String) -> Result<Post, ExtractorError> {
         let c: E621Post = serde_json::from_str::<E621Post>(raw_json.as_str())?;
 
-        if c.file.url.is_some() {
-            let tag_list = c.tags.map_tags();
-
-            let unit = Post {
-                id: c.id.unwrap(),
-                website: ImageBoards::E621,
-                url: c.file.url.clone().unwrap(),
-                md5: c.file.md5.clone().unwrap(),
-                extension: Extension::guess_format(&c.file.ext.clone().unwrap()),
-                tags: tag_list,
-                rating: Rating::from_rating_str(&c.rating),
-            };
-            Ok(unit)
-        } else {
-            Err(ExtractorError::ZeroPosts)
-        }
+        self.map_e621_post(c).ok_or(ExtractorError::ZeroPosts)
     }
 
     async fn get_post(&mut self, post_id: u32) -> Result<Post, ExtractorError> {
@@ -370,6 +515,8 @@ impl SinglePostFetch for E621Extractor {
             return Err(ExtractorError::UnsupportedOperation);
         };
 
+        wait_for_slot(&self.server_cfg.name).await;
+
         let url = format!(
             "{}/{}.json",
             self.server_cfg.post_url.as_ref().unwrap(),
@@ -14,6 +14,8 @@ pub struct E621TopLevel {
 pub struct E621Post {
     pub id: Option<u64>,
     pub file: E621File,
+    pub sample: Option<E621Sample>,
+    pub preview: Option<E621Preview>,
     pub tags: Tags,
     pub rating: String,
 }
@@ -28,6 +30,20 @@ pub struct E621File {
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(crate = "self::serde")]
+pub struct E621Sample {
+    pub has: Option<bool>,
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "self::serde")]
+pub struct E621Preview {
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "self::serde")]
+#[allow(dead_code)]
 pub struct E621AuthUser {
     pub success: Option<bool>,
     pub message: Option<String>,
@@ -74,5 +90,10 @@ impl Tags {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(crate = "self::serde")]
 pub struct E621PoolList {
+    pub id: Option<u32>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub category: Option<String>,
+    pub creator_name: Option<String>,
     pub post_ids: Vec<u64>,
 }
@@ -1,6 +1,6 @@
-use std::time::Duration;
+use std::{num::NonZeroU64, time::Duration};
 
-use ahash::{HashMap, HashMapExt};
+use ahash::{HashMap, HashMapExt, HashSet};
 use ibdl_common::{
     log::debug,
     post::Post,
@@ -15,9 +15,11 @@ use ibdl_common::{
 use crate::{
     blacklist::BlacklistFilter,
     error::ExtractorError,
+    imageboards::abort_requested,
     imageboards::{
         AsyncFetch, Extractor, PoolExtract, PostFetchAsync, PostFetchMethod, SinglePostFetch,
     },
+    query::QueryPlan,
 };
 
 use super::E621Extractor;
@@ -58,11 +60,28 @@ impl AsyncFetch for ExtractorUnit {
         )
         .await?;
 
-        let mut pool_idxs = HashMap::with_capacity(512);
+        let plan = if self.pool_id.is_some() {
+            QueryPlan::default()
+        } else {
+            self.plan_query().await?
+        };
 
-        if let Some(p_id) = self.pool_id {
+        let pool_idxs = if let Some(p_id) = self.pool_id {
             self.tag_string = format!("pool:{p_id}");
-            pool_idxs = self.fetch_pool_idxs(p_id, limit).await?;
+            self.fetch_pool_idxs(p_id, limit).await?
+        } else {
+            HashMap::with_capacity(512)
+        };
+
+        let mut missing_pages = pool_idxs
+            .keys()
+            .filter(|id| !self.pool_saved_pages.contains_key(id))
+            .copied()
+            .collect::<HashSet<_>>();
+
+        if self.pool_id.is_some() && missing_pages.is_empty() {
+            debug!("All pages of the pool were already downloaded.");
+            return Ok(self.total_removed);
         }
 
         let mut has_posts: bool = false;
@@ -75,7 +94,7 @@ impl AsyncFetch for ExtractorUnit {
         loop {
             let position = start_page.map_or(page, |n| page + n);
 
-            let posts = self.get_post_list(position).await?;
+            let mut posts = self.get_post_list(position).await?;
             let size = posts.len();
 
             if size == 0 {
@@ -86,6 +105,16 @@ impl AsyncFetch for ExtractorUnit {
                 break;
             }
 
+            // Posts come newest first, so the ones after a known post were all seen before
+            let reached_known = self.newer_than.is_some_and(|last_id| {
+                posts.retain(|post| post.id > last_id);
+                posts.len() < size
+            });
+
+            if plan.filters_locally() {
+                posts.retain(|post| plan.matches(post));
+            }
+
             let mut list = if !self.disable_blacklist || !self.download_ratings.is_empty() {
                 let (removed, posts) = blacklist.filter(posts);
                 self.total_removed += removed;
@@ -99,6 +128,10 @@ impl AsyncFetch for ExtractorUnit {
             }
 
             for i in &mut list {
+                if abort_requested(self.abort_signal.as_ref()) {
+                    break;
+                }
+
                 if let Some(num) = limit {
                     if total_posts_sent >= num {
                         break;
@@ -106,11 +139,13 @@ impl AsyncFetch for ExtractorUnit {
                 }
 
                 if self.pool_id.is_some() {
-                    if let Some(page_num) = pool_idxs.get(&i.id) {
-                        i.id = *page_num as u64;
-                    } else {
+                    // Saved pages, posts outside the pool and posts repeated between pages are skipped
+                    if !missing_pages.remove(&i.id) {
                         continue;
                     }
+
+                    i.pool_post_id = NonZeroU64::new(i.id);
+                    i.id = pool_idxs[&i.id] as u64;
                 }
 
                 sender_channel.send(i.clone())?;
@@ -120,6 +155,11 @@ impl AsyncFetch for ExtractorUnit {
                 }
             }
 
+            if self.pool_id.is_some() && missing_pages.is_empty() {
+                debug!("All missing pages of the pool were found.");
+                break;
+            }
+
             if let Some(num) = limit {
                 if total_posts_sent >= num {
                     debug!("Target post count of {} reached.", num);
@@ -127,6 +167,16 @@ impl AsyncFetch for ExtractorUnit {
                 }
             }
 
+            if abort_requested(self.abort_signal.as_ref()) {
+                debug!("Stopped by the download queue.");
+                break;
+            }
+
+            if reached_known {
+                debug!("Reached posts that were already fetched.");
+                break;
+            }
+
             if page == 100 {
                 break;
             }
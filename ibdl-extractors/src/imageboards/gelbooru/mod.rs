@@ -13,6 +13,7 @@
 
 use ibdl_common::post::extension::Extension;
 use ibdl_common::post::tags::{Tag, TagType};
+use ibdl_common::post::variant::FileVariant;
 use ibdl_common::reqwest::Client;
 use ibdl_common::serde_json::{self, Value};
 use ibdl_common::tokio::time::{sleep, Instant};
@@ -22,13 +23,16 @@ use ibdl_common::{
     post::{rating::Rating, Post, PostQueue},
     ImageBoards,
 };
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::time::Duration;
 
 use crate::extractor_config::DEFAULT_SERVERS;
+use crate::query::QueryPlan;
+use crate::rate_limit::wait_for_slot;
 use crate::{blacklist::BlacklistFilter, error::ExtractorError};
 
-use super::{Extractor, ExtractorFeatures, ServerConfig, SinglePostFetch};
+use super::{AbortSignal, Extractor, ExtractorFeatures, ServerConfig, SinglePostFetch};
 
 mod unsync;
 
@@ -43,6 +47,9 @@ pub struct GelbooruExtractor {
     map_videos: bool,
     excluded_tags: Vec<String>,
     selected_extension: Option<Extension>,
+    variant: FileVariant,
+    newer_than: Option<u64>,
+    abort_signal: Option<AbortSignal>,
     server_cfg: ServerConfig,
 }
 
@@ -87,6 +94,9 @@ impl Extractor for GelbooruExtractor {
             map_videos,
             excluded_tags: vec![],
             selected_extension: None,
+            variant: FileVariant::Original,
+            newer_than: None,
+            abort_signal: None,
             server_cfg: config,
         }
     }
@@ -130,6 +140,9 @@ impl Extractor for GelbooruExtractor {
             map_videos,
             excluded_tags: vec![],
             selected_extension: None,
+            variant: FileVariant::Original,
+            newer_than: None,
+            abort_signal: None,
             server_cfg: config,
         }
     }
@@ -244,11 +257,28 @@ impl Extractor for GelbooruExtractor {
         self
     }
 
+    fn select_variant(&mut self, variant: FileVariant) -> &mut Self {
+        self.variant = variant;
+        self
+    }
+
+    fn newer_than(&mut self, post_id: Option<u64>) -> &mut Self {
+        self.newer_than = post_id;
+        self
+    }
+
+    fn abort_signal(&mut self, signal: Option<AbortSignal>) -> &mut Self {
+        self.abort_signal = signal;
+        self
+    }
+
     async fn get_post_list(&self, page: u16) -> Result<Vec<Post>, ExtractorError> {
         if self.server_cfg.post_list_url.is_none() {
             return Err(ExtractorError::UnsupportedOperation);
         };
 
+        wait_for_slot(&self.server_cfg.name).await;
+
         let items = self
             .client
             .get(self.server_cfg.post_list_url.as_ref().unwrap())
@@ -304,6 +334,22 @@ impl Extractor for GelbooruExtractor {
 }
 
 impl GelbooruExtractor {
+    /// Splits the tags between the search and the local filter when the server has a tag limit.
+    ///
+    /// Tag counts are not fetched from Gelbooru servers, so the tags that are sent are the first
+    /// ones given.
+    fn plan_query(&mut self) -> Result<QueryPlan, ExtractorError> {
+        let plan = QueryPlan::new(
+            &self.tags,
+            &self.excluded_tags,
+            self.server_cfg.max_tags,
+            &HashMap::new(),
+        )?;
+        self.tag_string = plan.tag_string();
+
+        Ok(plan)
+    }
+
     fn gelbooru_old_path(&self, list: &[Value]) -> Vec<Post> {
         let start = Instant::now();
         let post_iter = list.iter().filter(|f| f["hash"].as_str().is_some());
@@ -357,15 +403,35 @@ impl GelbooruExtractor {
 
         let extension = extract_ext_from_url!(url);
 
-        Post {
+        let mut unit = Post {
             id: post["id"].as_u64().unwrap(),
             website: self.active_imageboard,
             md5: post["md5"].as_str().unwrap().to_string(),
             url,
             extension: Extension::guess_format(&extension),
             tags,
-            rating: Rating::from_rating_str(post["rating"].as_str().unwrap()),
-        }
+            rating: Rating::from_board_str(
+                post["rating"].as_str().unwrap(),
+                self.active_imageboard,
+            ),
+            frame_delays: None,
+            variant: FileVariant::Original,
+            original_extension: None,
+            pool_post_id: None,
+            server: None,
+        };
+
+        unit.set_variant(self.variant, self.pick_variant_url(post));
+
+        unit
+    }
+
+    /// Gelbooru's `sample_url` and `preview_url` fields, which are empty when the post has no sample.
+    #[inline]
+    fn pick_variant_url(&self, post: &Value) -> Option<String> {
+        let field = |name: &str| post[name].as_str().map(ToString::to_string);
+
+        self.variant.pick(field("sample_url"), field("preview_url"))
     }
 
     #[inline]
@@ -378,7 +444,8 @@ impl GelbooruExtractor {
             tags.push(Tag::new(f, TagType::Any));
         });
 
-        let rating = Rating::from_rating_str(post["rating"].as_str().unwrap());
+        let rating =
+            Rating::from_board_str(post["rating"].as_str().unwrap(), self.active_imageboard);
 
         let file = post["image"].as_str().unwrap();
 
@@ -404,7 +471,7 @@ impl GelbooruExtractor {
             post["file_url"].as_str().unwrap().to_string()
         };
 
-        Post {
+        let mut unit = Post {
             id: post["id"].as_u64().unwrap(),
             website: self.active_imageboard,
             url: drop_url,
@@ -412,7 +479,19 @@ impl GelbooruExtractor {
             extension: Extension::guess_format(&ext),
             rating,
             tags,
+            frame_delays: None,
+            variant: FileVariant::Original,
+            original_extension: None,
+            pool_post_id: None,
+            server: None,
+        };
+
+        // Gelbooru 0.2 servers don't provide any variant urls, so only the original file can be downloaded
+        if self.active_imageboard != ImageBoards::GelbooruV0_2 {
+            unit.set_variant(self.variant, self.pick_variant_url(post));
         }
+
+        unit
     }
 }
 
@@ -426,6 +505,8 @@ impl SinglePostFetch for GelbooruExtractor {
             return Err(ExtractorError::UnsupportedOperation);
         };
 
+        wait_for_slot(&self.server_cfg.name).await;
+
         let url = format!(
             "{}/{}.json",
             self.server_cfg.post_url.as_ref().unwrap(),
@@ -7,21 +7,20 @@
 //! Most extractors have a common set of public methods that should be the default way of implementing and interacting with them.
 //!
 //! ### Example with the `Danbooru` extractor
-//! ```rust
-//! use imageboard_downloader::*;
+//! ```no_run
+//! use ibdl_common::post::rating::Rating;
+//! use ibdl_extractors::{imageboards::prelude::*, prelude::*};
 //!
 //! async fn test() {
 //!     let tags = ["umbreon", "espeon"]; // The tags to search
-//!     
-//!     let safe_mode = false; // Setting this to true, will ignore searching NSFW posts
 //!
-//!     let disable_blacklist = false; // Will filter all items according to what's set in GBL
+//!     let ratings = [Rating::General, Rating::Sensitive]; // Only download posts with these ratings
 //!
-//!     let mut unit = DanbooruExtractor::new(&tags, safe_mode, disable_blacklist); // Initialize
+//!     let disable_blacklist = false; // Will filter all items according to what's set in GBL
 //!
-//!     let prompt = true; // If true, will ask the user to input thei username and API key.
+//!     let map_videos = true; // Also download animated gifs and videos
 //!
-//!     unit.auth(prompt).await.unwrap(); // Try to authenticate
+//!     let mut unit = DanbooruExtractor::new(&tags, &ratings, disable_blacklist, map_videos); // Initialize
 //!
 //!     let start_page = Some(1); // Start searching from the first page
 //!
@@ -35,23 +34,23 @@
 //!
 //! ### Example with the `Gelbooru` extractor
 //!
-//! The Gelbooru extractor supports multiple websites, so to use it correctly, an additional option needs to be set.
+//! The Gelbooru extractor supports multiple websites, so to use it correctly, the config of the
+//! selected server needs to be given.
 //!
-//! ```rust
-//! use imageboard_downloader::*;
+//! ```no_run
+//! use ibdl_common::post::rating::Rating;
+//! use ibdl_extractors::{extractor_config::DEFAULT_SERVERS, imageboards::prelude::*, prelude::*};
 //!
 //! async fn test() {
 //!     let tags = ["umbreon", "espeon"]; // The tags to search
-//!     
-//!     let safe_mode = false; // Setting this to true, will ignore searching NSFW posts
 //!
-//!     let disable_blacklist = false; // Will filter all items according to what's set in GBL
+//!     let ratings = [Rating::Safe, Rating::General]; // Only download posts with these ratings
 //!
-//!     let mut unit = GelbooruExtractor::new(&tags, safe_mode, disable_blacklist)
-//!         .set_imageboard(ImageBoards::Rule34).expect("Invalid imageboard"); // Here the imageboard was set to Rule34
+//!     let disable_blacklist = false; // Will filter all items according to what's set in GBL
 //!
+//!     let config = DEFAULT_SERVERS.get("rule34").unwrap().clone(); // Here the imageboard was set to Rule34
 //!
-//!     let prompt = true; // If true, will ask the user to input thei username and API key.
+//!     let mut unit = GelbooruExtractor::new_with_config(&tags, &ratings, disable_blacklist, true, config);
 //!
 //!     let start_page = Some(1); // Start searching from the first page
 //!
@@ -64,16 +63,27 @@
 //!```
 //!
 //!
-use std::{fmt::Display, future::Future};
+use std::{
+    fmt::Display,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use crate::{auth::ImageboardConfig, extractor_config::ServerConfig};
 use ahash::HashMap;
 use bitflags::bitflags;
+use chrono::{Datelike, NaiveDate};
 use ibdl_common::{
-    post::{extension::Extension, rating::Rating, Post, PostQueue},
+    post::{extension::Extension, rating::Rating, variant::FileVariant, Post, PostQueue},
     reqwest::Client,
     tokio::{
-        sync::mpsc::{Sender, UnboundedSender},
+        sync::{
+            mpsc::{Sender, UnboundedSender},
+            watch,
+        },
         task::JoinHandle,
     },
     ImageBoards,
@@ -93,6 +103,15 @@ pub mod prelude;
 
 pub type ExtractorThreadHandle = JoinHandle<Result<u64, ExtractorError>>;
 
+/// Receiving end of the channel the download queue uses to tell the extractor to stop early.
+pub type AbortSignal = watch::Receiver<bool>;
+
+/// Whether the download queue asked the extractor to stop fetching posts.
+#[inline]
+pub(crate) fn abort_requested(signal: Option<&AbortSignal>) -> bool {
+    signal.is_some_and(|signal| *signal.borrow())
+}
+
 bitflags! {
     pub struct ExtractorFeatures: u8 {
         const AsyncFetch = 0b0000_0001;
@@ -100,6 +119,7 @@ bitflags! {
         const SinglePostFetch = 0b0000_0100;
         const PoolDownload = 0b0000_1000;
         const Auth = 0b0001_0000;
+        const Popular = 0b0010_0000;
     }
 }
 
@@ -150,6 +170,18 @@ pub trait Extractor {
     /// Forces the extractor to only map posts that have the specified extension
     fn force_extension(&mut self, extension: Extension) -> &mut Self;
 
+    /// Selects which version of the file each post will point to. Falls back to the original
+    /// file when the imageboard doesn't provide the selected variant.
+    fn select_variant(&mut self, variant: FileVariant) -> &mut Self;
+
+    /// Only fetch posts with an id higher than `post_id`. Since posts are listed from newest to
+    /// oldest, the search stops once it reaches an older post.
+    fn newer_than(&mut self, post_id: Option<u64>) -> &mut Self;
+
+    /// Stops fetching new pages once `true` is sent through `signal`. Used by the download queue
+    /// to report that it only finds posts that were already downloaded.
+    fn abort_signal(&mut self, signal: Option<AbortSignal>) -> &mut Self;
+
     /// Pretty similar to `search`, but instead returns the raw post list instead of a [`PostQueue`](ibdl_common::post::PostQueue)
     fn get_post_list(
         &self,
@@ -214,7 +246,141 @@ pub trait AsyncFetch {
     ) -> JoinHandle<Result<u64, ExtractorError>>;
 }
 
+/// Capability for the extractor to rebuild the file url of posts the imageboard hides from the user
+/// (e.g. anonymous users on a default-blacklisted tag) through the server's `hidden_url_template`.
+pub trait HiddenPostRecovery {
+    /// Enables or disables the url reconstruction of hidden posts. Hidden posts are always counted.
+    fn recover_hidden_posts(&mut self, recover: bool) -> &mut Self;
+
+    /// Returns the shared counter of hidden and recovered posts for reporting at the end of the run.
+    fn hidden_post_counter(&self) -> Arc<HiddenPostCounter>;
+
+    /// Replaces the counter of hidden and recovered posts, so many extractors can report to the same one.
+    fn share_hidden_counter(&mut self, counter: Arc<HiddenPostCounter>) -> &mut Self;
+}
+
+/// Thread-safe counter of posts whose file url was hidden by the imageboard.
+#[derive(Debug, Default)]
+pub struct HiddenPostCounter {
+    hidden: AtomicU64,
+    recovered: AtomicU64,
+}
+
+impl HiddenPostCounter {
+    /// Total number of posts found without a file url.
+    pub fn total_hidden(&self) -> u64 {
+        self.hidden.load(Ordering::Relaxed)
+    }
+
+    /// Total number of hidden posts whose file url was rebuilt.
+    pub fn total_recovered(&self) -> u64 {
+        self.recovered.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_hidden(&self) {
+        self.hidden.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_recovered(&self) {
+        self.recovered.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Metadata of a pool of posts.
+#[derive(Debug, Clone, Default)]
+pub struct PoolInfo {
+    pub id: u32,
+    pub name: String,
+    pub description: String,
+    /// Kind of pool, like `series` or `collection`.
+    pub category: Option<String>,
+    /// Name of the user that created the pool, when provided by the imageboard.
+    pub creator: Option<String>,
+    /// Ids of all posts in the pool, in page order.
+    pub post_ids: Vec<u64>,
+    /// Url of the pool page in the imageboard.
+    pub url: Option<String>,
+}
+
+impl PoolInfo {
+    /// Pool name with underscores replaced by spaces.
+    #[inline]
+    pub fn pretty_name(&self) -> String {
+        self.name.replace('_', " ")
+    }
+
+    /// Name used for the directory or archive the pool is saved into.
+    ///
+    /// Characters not allowed in file names are replaced, and the pool id is used instead if
+    /// nothing is left of the name.
+    pub fn file_name(&self) -> String {
+        let sanitized: String = self
+            .pretty_name()
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect();
+
+        // Windows doesn't allow names ending with dots or spaces
+        let mut name = sanitized.trim().trim_end_matches('.').to_string();
+
+        // Keep some room for the extension in file systems limited to 255 bytes
+        while name.len() > 200 {
+            name.pop();
+        }
+
+        if name.trim_matches(['_', '.', ' ']).is_empty() {
+            return self.id.to_string();
+        }
+
+        name
+    }
+
+    /// Same as [`file_name`](Self::file_name), with the pool id appended, like `Name (1234)`.
+    ///
+    /// Used to tell apart pools that would be saved under the same name.
+    pub fn file_name_with_id(&self) -> String {
+        let name = self.file_name();
+
+        if name == self.id.to_string() {
+            return name;
+        }
+
+        format!("{} ({})", name, self.id)
+    }
+}
+
+/// Number of pools requested for each page of a pool search.
+pub(crate) const POOL_SEARCH_LIMIT: usize = 100;
+
+/// Turns a pool search query like `name_matches=*series*&category=series` into the
+/// `search[key]=value` parameters used by Danbooru and e621.
+pub(crate) fn pool_search_params(query: &str) -> Result<Vec<(String, String)>, ExtractorError> {
+    query
+        .split('&')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            pair.split_once('=')
+                .map(|(key, value)| (format!("search[{}]", key.trim()), value.trim().to_string()))
+                .ok_or_else(|| ExtractorError::InvalidPoolSearch {
+                    query: query.to_string(),
+                })
+        })
+        .collect()
+}
+
 pub trait PoolExtract {
+    /// Fetch the metadata and post list of a pool.
+    ///
+    /// The result is cached, so [`PoolExtract::fetch_pool_idxs`] doesn't need to request the pool again.
+    fn fetch_pool_info(
+        &mut self,
+        pool_id: u32,
+    ) -> impl Future<Output = Result<PoolInfo, ExtractorError>> + Send;
+
     fn fetch_pool_idxs(
         &mut self,
         pool_id: u32,
@@ -223,7 +389,107 @@ pub trait PoolExtract {
 
     fn parse_pool_ids(&self, raw_json: String) -> Result<Vec<u64>, ExtractorError>;
 
+    fn parse_pool_info(&self, raw_json: String) -> Result<PoolInfo, ExtractorError>;
+
+    /// Search pools using the imageboard's `pools.json` endpoint.
+    ///
+    /// The query is made of `key=value` pairs separated by `&`, each sent as `search[key]=value`.
+    /// For example, `name_matches=*series*&category=series`.
+    fn search_pools(
+        &mut self,
+        query: &str,
+    ) -> impl Future<Output = Result<Vec<PoolInfo>, ExtractorError>> + Send;
+
+    /// Parses a list of pools returned by the search endpoint.
+    fn parse_pool_list(&self, raw_json: String) -> Result<Vec<PoolInfo>, ExtractorError>;
+
     fn setup_pool_download(&mut self, pool_id: Option<u32>, last_first: bool);
+
+    /// Sets the posts of the pool that were already downloaded, mapped to the page they were
+    /// saved as.
+    ///
+    /// These posts keep their page and are not fetched again, while new posts are numbered after
+    /// the last saved page.
+    fn resume_pool(&mut self, saved_pages: impl IntoIterator<Item = (u64, u64)>);
+}
+
+/// Period covered by a list of popular posts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PopularScale {
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+impl PopularScale {
+    #[inline]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+}
+
+impl Display for PopularScale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Popular posts of the day, week or month that has `date`.
+#[derive(Debug, Clone, Copy)]
+pub struct PopularQuery {
+    pub scale: PopularScale,
+    /// Any date inside the period. The current one is used when not set.
+    pub date: Option<NaiveDate>,
+}
+
+impl PopularQuery {
+    /// Parameters of the `date` and `scale` kind, used by Danbooru and e621.
+    pub(crate) fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![("scale", self.scale.to_string())];
+
+        if let Some(date) = self.date {
+            params.push(("date", date.format("%Y-%m-%d").to_string()));
+        }
+
+        params
+    }
+
+    /// Parameters of the `day`, `month` and `year` kind, used by Moebooru.
+    pub(crate) fn split_date_params(&self) -> Vec<(&'static str, String)> {
+        let Some(date) = self.date else {
+            return Vec::new();
+        };
+
+        vec![
+            ("day", date.day().to_string()),
+            ("month", date.month().to_string()),
+            ("year", date.year().to_string()),
+        ]
+    }
+
+    /// Url of the popular posts list of `server`, with `{scale}` replaced.
+    pub(crate) fn url(&self, server: &ServerConfig) -> Result<String, ExtractorError> {
+        server
+            .popular_url
+            .as_ref()
+            .map(|url| url.replace("{scale}", self.scale.as_str()))
+            .ok_or(ExtractorError::UnsupportedOperation)
+    }
+}
+
+/// Capability for the extractor to fetch the curated lists of popular posts instead of searching
+/// tags.
+pub trait PopularFetch {
+    /// Makes the extractor fetch the popular posts selected by `query` instead of searching it's
+    /// tags.
+    ///
+    /// Posts still go through the same blacklist, rating and extension filters of a tag search.
+    fn setup_popular(&mut self, query: PopularQuery) -> &mut Self;
 }
 
 #[derive(Debug, Clone)]
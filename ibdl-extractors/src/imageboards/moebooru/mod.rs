@@ -1,6 +1,7 @@
 //! Post extractor for `https://konachan.com` and other Moebooru imageboards
 use ibdl_common::post::extension::Extension;
 use ibdl_common::post::tags::{Tag, TagType};
+use ibdl_common::post::variant::FileVariant;
 use ibdl_common::reqwest::Client;
 use ibdl_common::{
     client, extract_ext_from_url, join_tags,
@@ -10,14 +11,17 @@ use ibdl_common::{
     tokio::time::Instant,
     ImageBoards,
 };
+use std::collections::HashMap;
 use std::fmt::Display;
 
 use crate::extractor_config::DEFAULT_SERVERS;
+use crate::query::{fetch_post_counts, QueryPlan};
+use crate::rate_limit::wait_for_slot;
 use crate::{
     blacklist::BlacklistFilter, error::ExtractorError, imageboards::moebooru::models::KonachanPost,
 };
 
-use super::{Extractor, ExtractorFeatures, ServerConfig};
+use super::{AbortSignal, Extractor, ExtractorFeatures, PopularFetch, PopularQuery, ServerConfig};
 
 mod models;
 mod unsync;
@@ -32,6 +36,10 @@ pub struct MoebooruExtractor {
     map_videos: bool,
     excluded_tags: Vec<String>,
     selected_extension: Option<Extension>,
+    variant: FileVariant,
+    newer_than: Option<u64>,
+    abort_signal: Option<AbortSignal>,
+    popular: Option<PopularQuery>,
     server_cfg: ServerConfig,
 }
 
@@ -72,6 +80,10 @@ impl Extractor for MoebooruExtractor {
             map_videos,
             excluded_tags: vec![],
             selected_extension: None,
+            variant: FileVariant::Original,
+            newer_than: None,
+            abort_signal: None,
+            popular: None,
             server_cfg: config,
         }
     }
@@ -111,12 +123,16 @@ impl Extractor for MoebooruExtractor {
             map_videos,
             excluded_tags: vec![],
             selected_extension: None,
+            variant: FileVariant::Original,
+            newer_than: None,
+            abort_signal: None,
+            popular: None,
             server_cfg: config,
         }
     }
 
     fn features() -> ExtractorFeatures {
-        ExtractorFeatures::from_bits_truncate(0b0000_0011) // AsyncFetch + TagSearch
+        ExtractorFeatures::from_bits_truncate(0b0010_0011) // AsyncFetch + TagSearch + Popular
     }
 
     fn config(&self) -> ServerConfig {
@@ -220,23 +236,46 @@ impl Extractor for MoebooruExtractor {
         self
     }
 
+    fn select_variant(&mut self, variant: FileVariant) -> &mut Self {
+        self.variant = variant;
+        self
+    }
+
+    fn newer_than(&mut self, post_id: Option<u64>) -> &mut Self {
+        self.newer_than = post_id;
+        self
+    }
+
+    fn abort_signal(&mut self, signal: Option<AbortSignal>) -> &mut Self {
+        self.abort_signal = signal;
+        self
+    }
+
     async fn get_post_list(&self, page: u16) -> Result<Vec<Post>, ExtractorError> {
-        if self.server_cfg.post_list_url.is_none() {
-            return Err(ExtractorError::UnsupportedOperation);
-        };
+        let request = if let Some(popular) = &self.popular {
+            // The whole list fits in a single page
+            if page > 1 {
+                return Ok(Vec::new());
+            }
+
+            self.client
+                .get(popular.url(&self.server_cfg)?)
+                .query(&popular.split_date_params())
+        } else {
+            let Some(url) = &self.server_cfg.post_list_url else {
+                return Err(ExtractorError::UnsupportedOperation);
+            };
 
-        let items = self
-            .client
-            .get(self.server_cfg.post_list_url.as_ref().unwrap())
-            .query(&[
+            self.client.get(url).query(&[
                 ("page", &page.to_string()),
                 ("limit", &self.server_cfg.max_post_limit.to_string()),
                 ("tags", &self.tag_string),
             ])
-            .send()
-            .await?
-            .text()
-            .await?;
+        };
+
+        wait_for_slot(&self.server_cfg.name).await;
+
+        let items = request.send().await?.text().await?;
 
         let start = Instant::now();
 
@@ -270,16 +309,28 @@ impl Extractor for MoebooruExtractor {
                 tags.push(Tag::new(i, TagType::Any));
             });
 
-            let unit = Post {
+            let mut unit = Post {
                 id: c.id.unwrap(),
                 website: ImageBoards::Moebooru,
                 url,
                 md5: c.md5.clone().unwrap(),
                 extension: Extension::guess_format(&ext),
                 tags,
-                rating: Rating::from_rating_str(&c.rating),
+                rating: Rating::from_board_str(&c.rating, ImageBoards::Moebooru),
+                frame_delays: None,
+                variant: FileVariant::Original,
+                original_extension: None,
+                pool_post_id: None,
+                server: None,
             };
 
+            // Moebooru's `sample_url` is a resized version, while `preview_url` is the thumbnail
+            unit.set_variant(
+                self.variant,
+                self.variant
+                    .pick(c.sample_url.clone(), c.preview_url.clone()),
+            );
+
             post_mtx.push(unit);
         });
 
@@ -303,3 +354,50 @@ impl Extractor for MoebooruExtractor {
         self
     }
 }
+
+impl PopularFetch for MoebooruExtractor {
+    fn setup_popular(&mut self, query: PopularQuery) -> &mut Self {
+        self.popular = Some(query);
+        self
+    }
+}
+
+impl MoebooruExtractor {
+    /// Splits the tags between the search and the local filter, so the search fits in the tag
+    /// limit of the server.
+    async fn plan_query(&mut self) -> Result<QueryPlan, ExtractorError> {
+        let counts = if QueryPlan::fits(&self.tags, self.server_cfg.max_tags) {
+            HashMap::new()
+        } else {
+            self.post_counts().await
+        };
+
+        let plan = QueryPlan::new(
+            &self.tags,
+            &self.excluded_tags,
+            self.server_cfg.max_tags,
+            &counts,
+        )?;
+        self.tag_string = plan.tag_string();
+
+        Ok(plan)
+    }
+
+    /// Moebooru can only look up a single tag by it's exact name, so each tag takes a request.
+    async fn post_counts(&self) -> HashMap<String, u64> {
+        let mut counts = HashMap::new();
+
+        for tag in QueryPlan::countable(&self.tags) {
+            wait_for_slot(&self.server_cfg.name).await;
+
+            let request = self
+                .client
+                .get(format!("{}/tag.json", self.server_cfg.base_url))
+                .query(&[("name", tag.as_str())]);
+
+            counts.extend(fetch_post_counts(request).await);
+        }
+
+        counts
+    }
+}
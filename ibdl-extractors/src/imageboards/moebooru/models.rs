@@ -6,6 +6,9 @@ pub struct KonachanPost {
     pub id: Option<u64>,
     pub md5: Option<String>,
     pub file_url: Option<String>,
+    pub jpeg_url: Option<String>,
+    pub sample_url: Option<String>,
+    pub preview_url: Option<String>,
     pub rating: String,
     pub tags: String,
 }
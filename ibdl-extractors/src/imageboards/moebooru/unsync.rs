@@ -11,6 +11,7 @@ use ibdl_common::{
 use crate::{
     blacklist::BlacklistFilter,
     error::ExtractorError,
+    imageboards::abort_requested,
     imageboards::{AsyncFetch, Extractor},
 };
 
@@ -52,6 +53,8 @@ impl AsyncFetch for ExtractorUnit {
         )
         .await?;
 
+        let plan = self.plan_query().await?;
+
         let mut has_posts: bool = false;
         let mut total_posts_sent: u16 = 0;
 
@@ -62,7 +65,7 @@ impl AsyncFetch for ExtractorUnit {
         loop {
             let position = start_page.map_or(page, |n| page + n);
 
-            let posts = self.get_post_list(position).await?;
+            let mut posts = self.get_post_list(position).await?;
             let size = posts.len();
 
             if size == 0 {
@@ -73,6 +76,16 @@ impl AsyncFetch for ExtractorUnit {
                 break;
             }
 
+            // Posts come newest first, so the ones after a known post were all seen before
+            let reached_known = self.newer_than.is_some_and(|last_id| {
+                posts.retain(|post| post.id > last_id);
+                posts.len() < size
+            });
+
+            if plan.filters_locally() {
+                posts.retain(|post| plan.matches(post));
+            }
+
             let list = if !self.disable_blacklist || !self.download_ratings.is_empty() {
                 let (removed, posts) = blacklist.filter(posts);
                 self.total_removed += removed;
@@ -86,6 +99,10 @@ impl AsyncFetch for ExtractorUnit {
             }
 
             for i in list {
+                if abort_requested(self.abort_signal.as_ref()) {
+                    break;
+                }
+
                 if let Some(num) = limit {
                     if total_posts_sent >= num {
                         break;
@@ -106,6 +123,16 @@ impl AsyncFetch for ExtractorUnit {
                 }
             }
 
+            if abort_requested(self.abort_signal.as_ref()) {
+                debug!("Stopped by the download queue.");
+                break;
+            }
+
+            if reached_known {
+                debug!("Reached posts that were already fetched.");
+                break;
+            }
+
             if page == 100 {
                 break;
             }
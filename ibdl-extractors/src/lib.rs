@@ -14,3 +14,5 @@ pub mod error;
 pub mod extractor_config;
 pub mod imageboards;
 pub mod prelude;
+pub mod query;
+pub mod rate_limit;
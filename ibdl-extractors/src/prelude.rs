@@ -1,7 +1,9 @@
+pub use crate::imageboards::AbortSignal;
 pub use crate::imageboards::AsyncFetch;
 pub use crate::imageboards::Auth;
 pub use crate::imageboards::Extractor;
 pub use crate::imageboards::ExtractorThreadHandle;
+pub use crate::imageboards::HiddenPostRecovery;
 pub use crate::imageboards::PoolExtract;
 pub use crate::imageboards::PostFetchAsync;
 pub use crate::imageboards::SinglePostFetch;
@@ -0,0 +1,327 @@
+//! Fits a tag search within the number of tags an imageboard accepts in a single search.
+//!
+//! Tags that don't fit are checked after fetching each page, so the search still finds the same
+//! posts, only going through more pages. The tags with the least posts narrow the search the most,
+//! so they are the ones sent to the imageboard.
+//!
+//! Metatags like `order:score` or `rating:general` and wildcards can't be checked against the tags
+//! of a post, so they are always sent. Since many tags have a colon in their name, any tag with a
+//! colon is treated as a metatag.
+use std::{
+    collections::{HashMap, HashSet},
+    hash::BuildHasher,
+};
+
+use ibdl_common::{
+    join_tags,
+    log::debug,
+    post::Post,
+    reqwest::RequestBuilder,
+    serde::{self, Deserialize},
+};
+
+use crate::error::ExtractorError;
+
+/// Tags of a search split between the ones sent to the imageboard and the ones checked locally.
+#[derive(Debug, Clone, Default)]
+pub struct QueryPlan {
+    /// Tags sent to the imageboard.
+    pub server_tags: Vec<String>,
+    /// Tags every post must have.
+    pub required: Vec<String>,
+    /// Tags no post can have, without the leading `-`.
+    pub forbidden: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "self::serde")]
+struct TagCount {
+    name: String,
+    #[serde(alias = "count")]
+    post_count: u64,
+}
+
+impl QueryPlan {
+    /// Splits `tags` for an imageboard that accepts up to `max_tags` tags per search.
+    ///
+    /// `post_counts` has the number of posts of each tag. Tags without a count are sent last,
+    /// in the order they were given. If there's room left, `excluded` tags are also sent negated,
+    /// so less posts are fetched only to be removed by the blacklist.
+    pub fn new<S: BuildHasher>(
+        tags: &[String],
+        excluded: &[String],
+        max_tags: Option<usize>,
+        post_counts: &HashMap<String, u64, S>,
+    ) -> Result<Self, ExtractorError> {
+        let max = max_tags.unwrap_or(usize::MAX);
+
+        let (server_tags, local): (Vec<_>, Vec<_>) =
+            tags.iter().cloned().partition(|tag| !is_local(tag));
+
+        if server_tags.len() > max {
+            return Err(ExtractorError::TooManyTags {
+                current: server_tags.len(),
+                max: max as u64,
+            });
+        }
+
+        let (mut positive, negated): (Vec<_>, Vec<_>) =
+            local.into_iter().partition(|tag| !tag.starts_with('-'));
+
+        positive.sort_by_key(|tag| {
+            post_counts
+                .get(&tag.to_lowercase())
+                .copied()
+                .unwrap_or(u64::MAX)
+        });
+
+        let mut plan = Self {
+            server_tags,
+            ..Self::default()
+        };
+
+        for tag in positive {
+            if plan.server_tags.len() < max {
+                plan.server_tags.push(tag);
+            } else {
+                plan.required.push(tag);
+            }
+        }
+
+        for tag in negated {
+            if plan.server_tags.len() < max {
+                plan.server_tags.push(tag);
+            } else {
+                plan.forbidden.push(tag[1..].to_string());
+            }
+        }
+
+        // Blacklist entries can have many tags in a single line, those are left to the blacklist
+        let extra = excluded
+            .iter()
+            .filter(|tag| is_local(tag) && !tag.starts_with('-') && !tag.contains(' '))
+            .map(|tag| format!("-{tag}"));
+
+        for tag in extra {
+            if plan.server_tags.len() >= max {
+                break;
+            }
+
+            if !plan.server_tags.contains(&tag) {
+                plan.server_tags.push(tag);
+            }
+        }
+
+        if plan.filters_locally() {
+            debug!(
+                "Searching {:?}, checking {:?} and {:?} locally",
+                plan.server_tags, plan.required, plan.forbidden
+            );
+        }
+
+        Ok(plan)
+    }
+
+    /// Whether all `tags` can be sent to an imageboard that accepts up to `max_tags` tags.
+    pub fn fits(tags: &[String], max_tags: Option<usize>) -> bool {
+        max_tags.map_or(true, |max| tags.len() <= max)
+    }
+
+    /// Tags that need their post count to be planned.
+    pub fn countable(tags: &[String]) -> impl Iterator<Item = &String> {
+        tags.iter()
+            .filter(|tag| is_local(tag) && !tag.starts_with('-'))
+    }
+
+    /// Tags sent to the imageboard, in the URL format.
+    pub fn tag_string(&self) -> String {
+        join_tags!(self.server_tags)
+    }
+
+    pub fn filters_locally(&self) -> bool {
+        !self.required.is_empty() || !self.forbidden.is_empty()
+    }
+
+    /// Whether the post matches the tags that were left out of the search.
+    ///
+    /// Imageboards ignore the case of tags, so they are compared in lowercase.
+    pub fn matches(&self, post: &Post) -> bool {
+        let tags = post
+            .tags
+            .iter()
+            .map(|tag| tag.tag().to_lowercase())
+            .collect::<HashSet<_>>();
+
+        let has = |name: &String| tags.contains(&name.to_lowercase());
+
+        self.required.iter().all(has) && !self.forbidden.iter().any(has)
+    }
+}
+
+/// Whether the tag can be checked against the tags of a post.
+fn is_local(tag: &str) -> bool {
+    let name = tag.strip_prefix('-').unwrap_or(tag);
+
+    !name.is_empty() && !name.starts_with('~') && !name.contains([':', '*'])
+}
+
+/// Reads the post count of each tag from a tag list endpoint.
+///
+/// Counts only change which tags are sent first, so any error returns an empty map.
+pub(crate) async fn fetch_post_counts(request: RequestBuilder) -> HashMap<String, u64> {
+    let counts = match request.send().await {
+        Ok(response) => response.json::<Vec<TagCount>>().await,
+        Err(error) => Err(error),
+    };
+
+    match counts {
+        Ok(tags) => tags
+            .into_iter()
+            .map(|tag| (tag.name, tag.post_count))
+            .collect(),
+        Err(error) => {
+            debug!("Failed to fetch tag counts: {error}");
+            HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ibdl_common::{
+        post::{
+            extension::Extension,
+            rating::Rating,
+            tags::{Tag, TagType},
+            variant::FileVariant,
+        },
+        ImageBoards,
+    };
+
+    use super::*;
+
+    fn strings(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(ToString::to_string).collect()
+    }
+
+    fn counts(tags: &[(&str, u64)]) -> HashMap<String, u64> {
+        tags.iter()
+            .map(|(name, count)| ((*name).to_string(), *count))
+            .collect()
+    }
+
+    fn post(tags: &[&str]) -> Post {
+        Post {
+            id: 1,
+            website: ImageBoards::Danbooru,
+            url: String::new(),
+            md5: String::new(),
+            extension: Extension::JPG,
+            rating: Rating::Safe,
+            tags: tags.iter().map(|tag| Tag::new(tag, TagType::Any)).collect(),
+            frame_delays: None,
+            variant: FileVariant::Original,
+            original_extension: None,
+            pool_post_id: None,
+            server: None,
+        }
+    }
+
+    #[test]
+    fn metatags_are_not_local() {
+        assert!(is_local("blue_eyes"));
+        assert!(is_local("-blue_eyes"));
+        assert!(!is_local("order:score"));
+        assert!(!is_local("-rating:explicit"));
+        assert!(!is_local("blue_*"));
+        assert!(!is_local("~blue_eyes"));
+        assert!(!is_local("-"));
+    }
+
+    #[test]
+    fn metatags_are_always_sent() {
+        let tags = strings(&["order:score", "blue_eyes", "rating:general", "long_hair"]);
+        let plan = QueryPlan::new(&tags, &[], Some(2), &HashMap::new()).unwrap();
+
+        assert_eq!(
+            plan.server_tags,
+            strings(&["order:score", "rating:general"])
+        );
+        assert_eq!(plan.required, strings(&["blue_eyes", "long_hair"]));
+        assert!(plan.forbidden.is_empty());
+    }
+
+    #[test]
+    fn tags_with_less_posts_are_sent_first() {
+        let tags = strings(&["long_hair", "Skyfire_(arknights)", "unknown", "blue_eyes"]);
+        let post_counts = counts(&[
+            ("long_hair", 5_000_000),
+            ("skyfire_(arknights)", 900),
+            ("blue_eyes", 1_000_000),
+        ]);
+
+        let plan = QueryPlan::new(&tags, &[], Some(2), &post_counts).unwrap();
+
+        assert_eq!(
+            plan.server_tags,
+            strings(&["Skyfire_(arknights)", "blue_eyes"])
+        );
+        assert_eq!(plan.required, strings(&["long_hair", "unknown"]));
+    }
+
+    #[test]
+    fn negated_tags_go_after_positive_ones() {
+        let tags = strings(&["-comic", "blue_eyes", "-monochrome"]);
+        let plan = QueryPlan::new(&tags, &[], Some(2), &HashMap::new()).unwrap();
+
+        assert_eq!(plan.server_tags, strings(&["blue_eyes", "-comic"]));
+        assert!(plan.required.is_empty());
+        assert_eq!(plan.forbidden, strings(&["monochrome"]));
+    }
+
+    #[test]
+    fn too_many_metatags_fail() {
+        let tags = strings(&["order:score", "rating:general", "blue_eyes"]);
+        let result = QueryPlan::new(&tags, &[], Some(1), &HashMap::new());
+
+        assert!(matches!(
+            result,
+            Err(ExtractorError::TooManyTags { current: 2, max: 1 })
+        ));
+    }
+
+    #[test]
+    fn excludes_fill_the_room_left() {
+        let tags = strings(&["blue_eyes", "-comic"]);
+        let excluded = strings(&["comic", "gore", "rating:explicit", "-solo", "male red_eyes"]);
+
+        let plan = QueryPlan::new(&tags, &excluded, Some(3), &HashMap::new()).unwrap();
+        assert_eq!(plan.server_tags, strings(&["blue_eyes", "-comic", "-gore"]));
+
+        let plan = QueryPlan::new(&tags, &excluded, None, &HashMap::new()).unwrap();
+        assert_eq!(plan.server_tags, strings(&["blue_eyes", "-comic", "-gore"]));
+    }
+
+    #[test]
+    fn unlimited_search_sends_everything() {
+        let tags = strings(&["blue_eyes", "long_hair", "-comic"]);
+        let plan = QueryPlan::new(&tags, &[], None, &HashMap::new()).unwrap();
+
+        assert_eq!(plan.server_tags, tags);
+        assert!(!plan.filters_locally());
+    }
+
+    #[test]
+    fn matches_ignores_case() {
+        let plan = QueryPlan {
+            server_tags: Vec::new(),
+            required: strings(&["Blue_Eyes"]),
+            forbidden: strings(&["COMIC"]),
+        };
+
+        assert!(plan.matches(&post(&["blue_eyes", "long_hair"])));
+        assert!(plan.matches(&post(&["BLUE_EYES"])));
+        assert!(!plan.matches(&post(&["long_hair"])));
+        assert!(!plan.matches(&post(&["blue_eyes", "Comic"])));
+    }
+}
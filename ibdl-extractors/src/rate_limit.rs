@@ -0,0 +1,61 @@
+//! Minimum interval between API requests to the same server, shared by every extractor running in
+//! the process.
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use ibdl_common::{
+    log::debug,
+    tokio::time::{sleep_until, Instant},
+};
+use once_cell::sync::Lazy;
+
+static RATE_LIMITS: Lazy<Mutex<HashMap<String, ServerLimit>>> = Lazy::new(Default::default);
+
+#[derive(Debug, Clone, Copy)]
+struct ServerLimit {
+    interval: Duration,
+    next_slot: Instant,
+}
+
+/// Replaces the rate limits of all servers. Each key is the name of a server, as listed by
+/// `--servers`, and each value is the minimum time between two requests to it.
+///
+/// Servers without a limit are never delayed.
+pub fn set_rate_limits(limits: impl IntoIterator<Item = (String, Duration)>) {
+    let mut current = RATE_LIMITS.lock().unwrap();
+
+    let updated = limits
+        .into_iter()
+        .map(|(server, interval)| {
+            // Keep the next slot of servers that were already limited
+            let next_slot = current
+                .get(&server)
+                .map_or_else(Instant::now, |limit| limit.next_slot);
+
+            (
+                server,
+                ServerLimit {
+                    interval,
+                    next_slot,
+                },
+            )
+        })
+        .collect();
+
+    *current = updated;
+}
+
+/// Waits until `server` can receive another request.
+pub(crate) async fn wait_for_slot(server: &str) {
+    let Some(slot) = RATE_LIMITS.lock().unwrap().get_mut(server).map(|limit| {
+        let slot = limit.next_slot.max(Instant::now());
+        limit.next_slot = slot + limit.interval;
+        slot
+    }) else {
+        return;
+    };
+
+    if slot > Instant::now() {
+        debug!("Waiting for the rate limit of {server}");
+        sleep_until(slot).await;
+    }
+}
@@ -1,24 +1,22 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
 use std::process::exit;
-use std::sync::atomic::AtomicU64;
-use std::sync::Arc;
 
-use color_eyre::eyre::{bail, Result};
+use color_eyre::eyre::Result;
 use color_eyre::owo_colors::OwoColorize;
 use dialoguer::theme::ColorfulTheme;
 use dialoguer::Confirm;
-use ibdl_common::tokio::sync::mpsc::{channel, unbounded_channel};
-use ibdl_common::tokio::{self, join};
-use ibdl_core::async_queue::Queue;
+use ibdl_common::tokio;
 use ibdl_core::clap::Parser;
+use ibdl_core::cli::commands::run::RunReport;
+use ibdl_core::cli::commands::sync::SyncReport;
+use ibdl_core::cli::download::DownloadResult;
 use ibdl_core::cli::{Cli, Commands, AVAILABLE_SERVERS};
 use ibdl_extractors::imageboards::ExtractorFeatures;
-use once_cell::sync::Lazy;
-
-static POST_COUNTER: Lazy<Arc<AtomicU64>> = Lazy::new(|| Arc::new(AtomicU64::new(0)));
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args: Cli = Cli::parse();
+    let mut args: Cli = Cli::parse();
 
     if args.servers {
         print_servers()
@@ -27,77 +25,255 @@ async fn main() -> Result<()> {
     env_logger::builder().format_timestamp(None).init();
     color_eyre::install()?;
 
-    let dirname = args.generate_save_path()?;
+    if let Commands::Sync(com) = &args.mode {
+        let reports = com.run(&args).await?;
+        print_sync_reports(&mut io::stdout(), &reports)?;
+        return Ok(());
+    }
 
-    if (dirname.exists() && (dirname.is_file() || dirname.read_dir()?.next().is_some()))
-        && !args.overwrite
-    {
-        let conf_exists = Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(format!(
-                "The path {} is not empty or already exists. Do you want to continue?",
-                dirname.display().bold().blue().italic()
-            ))
-            .wait_for_newline(true)
-            .interact()
-            .unwrap();
-        if !conf_exists {
-            println!("{}", "Download cancelled".bold().blue());
-            exit(0);
+    if let Commands::Run(com) = &args.mode {
+        let reports = com.run().await?;
+
+        if reports.iter().any(|report| report.to_stdout) {
+            print_run_reports(&mut io::stderr(), &reports)?;
+        } else {
+            print_run_reports(&mut io::stdout(), &reports)?;
         }
+        return Ok(());
+    }
+
+    if let Commands::Daemon(com) = &args.mode {
+        com.run().await?;
+        return Ok(());
     }
 
-    let (channel_tx, channel_rx) = unbounded_channel();
+    if let Commands::Serve(com) = &args.mode {
+        com.run().await?;
+        return Ok(());
+    }
 
-    let (length_sender, length_channel) = channel(args.simultaneous_downloads as usize);
-    let mut is_pool = false;
+    let confirm_overwrite = |existing: &[&PathBuf]| {
+        let prompt = if let [path] = existing {
+            format!(
+                "The path {} is not empty or already exists. Do you want to continue?",
+                path.display().bold().blue().italic()
+            )
+        } else {
+            format!(
+                "{} of the output paths are not empty or already exist. Do you want to continue?",
+                existing.len().bold().blue()
+            )
+        };
 
-    let (ext, client) = match &args.mode {
-        Commands::Search(com) => com.init_extractor(&args, channel_tx, length_sender).await?,
-        Commands::Pool(com) => {
-            is_pool = true;
-            com.init_extractor(&args, channel_tx, length_sender).await?
-        }
-        Commands::Post(com) => com.init_extractor(&args, channel_tx, length_sender).await?,
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .wait_for_newline(true)
+            .interact()
+            .unwrap()
     };
 
-    let qw = Queue::new(
-        args.imageboard.clone(),
-        args.simultaneous_downloads,
-        Some(client),
-        args.cbz,
-        is_pool,
-        args.name_type(),
-        args.annotate,
-    );
-
-    let asd = qw.setup_async_downloader(dirname, POST_COUNTER.clone(), channel_rx, length_channel);
+    let Some(result) = args.download(confirm_overwrite).await? else {
+        println!("{}", "Download cancelled".bold().blue());
+        exit(0);
+    };
 
-    let (Ok(removed), Ok(results)) = join!(ext, asd) else {
-        bail!("Failed starting threads!")
+    // Keep the standard output clean when the archive is being written to it
+    let mut report: Box<dyn Write> = if args.output_to_stdout() {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
     };
 
-    print_results(results?, removed?);
+    print_results(&mut report, result.downloaded, result.blacklisted)?;
+
+    print_hidden_report(&mut report, &result, args.recover_hidden)?;
 
     Ok(())
 }
 
-fn print_results(total_down: u64, total_black: u64) {
-    println!(
+fn print_results(out: &mut impl Write, total_down: u64, total_black: u64) -> io::Result<()> {
+    writeln!(
+        out,
         "{} {} {}",
         total_down.to_string().bold().blue(),
         "files".bold().blue(),
         "downloaded".bold()
-    );
+    )?;
 
     if total_black > 0 && total_down != 0 {
-        println!(
+        writeln!(
+            out,
             "{} {}",
             total_black.to_string().bold().red(),
             "found posts with blacklisted tags were not downloaded."
                 .bold()
                 .red()
-        );
+        )?;
+    }
+
+    Ok(())
+}
+
+fn print_sync_reports(out: &mut impl Write, reports: &[SyncReport]) -> io::Result<()> {
+    for report in reports {
+        let name = format!("{} ({}):", report.name, report.server);
+
+        match &report.result {
+            Ok(result) if result.downloaded == 0 => {
+                writeln!(out, "{} {}", name.bold(), "no new posts".bold())?;
+            }
+            Ok(result) => {
+                write!(
+                    out,
+                    "{} {} {}",
+                    name.bold(),
+                    result.downloaded.to_string().bold().blue(),
+                    "new files downloaded".bold()
+                )?;
+
+                if result.blacklisted > 0 {
+                    write!(
+                        out,
+                        ", {} {}",
+                        result.blacklisted.to_string().bold().red(),
+                        "blacklisted".bold().red()
+                    )?;
+                }
+
+                writeln!(out)?;
+            }
+            Err(error) => {
+                writeln!(
+                    out,
+                    "{} {} {}",
+                    name.bold(),
+                    "failed:".bold().red(),
+                    error.to_string().red()
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_run_reports(out: &mut impl Write, reports: &[RunReport]) -> io::Result<()> {
+    let width = reports
+        .iter()
+        .map(|report| report.name.len())
+        .chain([5])
+        .max()
+        .unwrap_or_default();
+
+    writeln!(
+        out,
+        "\n{}",
+        format!(
+            "{:<width$}  {:<6}  {:>10}  {:>7}  {:>11}  {:>6}  {:>8}",
+            "Job", "Mode", "Downloaded", "Skipped", "Blacklisted", "Failed", "Time"
+        )
+        .bold()
+        .underline()
+    )?;
+
+    let mut total = (0, 0, 0, 0);
+    let mut failed_jobs = 0;
+
+    for report in reports {
+        let name = format!("{:<width$}  {:<6}", report.name, report.mode);
+        let elapsed = format!("{:.1?}", report.elapsed);
+
+        match &report.result {
+            Ok(result) => {
+                writeln!(
+                    out,
+                    "{}  {:>10}  {:>7}  {:>11}  {:>6}  {:>8}",
+                    name.bold(),
+                    result.downloaded.bold().blue(),
+                    result.skipped.bold().green(),
+                    result.blacklisted.bold().red(),
+                    result.failed.bold().red(),
+                    elapsed
+                )?;
+
+                total.0 += result.downloaded;
+                total.1 += result.skipped;
+                total.2 += result.blacklisted;
+                total.3 += result.failed;
+            }
+            Err(error) => {
+                failed_jobs += 1;
+
+                writeln!(
+                    out,
+                    "{}  {} {}",
+                    name.bold(),
+                    "failed:".bold().red(),
+                    error.to_string().red()
+                )?;
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "{}  {:>10}  {:>7}  {:>11}  {:>6}",
+        format!("{:<width$}  {:<6}", "Total", "").bold(),
+        total.0.bold().blue(),
+        total.1.bold().green(),
+        total.2.bold().red(),
+        total.3.bold().red()
+    )?;
+
+    if failed_jobs > 0 {
+        writeln!(
+            out,
+            "{} {}",
+            failed_jobs.to_string().bold().red(),
+            "jobs failed.".bold().red()
+        )?;
     }
+
+    Ok(())
+}
+
+fn print_hidden_report(
+    out: &mut impl Write,
+    result: &DownloadResult,
+    recover_hidden: bool,
+) -> io::Result<()> {
+    let hidden = result.hidden;
+
+    if hidden == 0 {
+        return Ok(());
+    }
+
+    writeln!(
+        out,
+        "{} {}",
+        hidden.to_string().bold().yellow(),
+        "posts had their file url hidden by the imageboard.".bold()
+    )?;
+
+    if recover_hidden {
+        writeln!(
+            out,
+            "{} {}",
+            result.recovered.to_string().bold().blue(),
+            "of them were recovered from their MD5 hash.".bold()
+        )?;
+    } else {
+        writeln!(
+            out,
+            "{} {}",
+            "Use".bold(),
+            "--recover-hidden to try downloading them anyway."
+                .bold()
+                .blue()
+        )?;
+    }
+
+    Ok(())
 }
 
 fn print_servers() {
@@ -131,6 +307,10 @@ fn print_servers() {
             features.push("Pool Download");
         }
 
+        if ext_feat.contains(ExtractorFeatures::Popular) {
+            features.push("Popular Posts");
+        }
+
         println!(
             "{:<16} - {}:\n - {} {}\n - {} {}\n - {} {}\n - {} {:?}\n",
             format!("[{}]", srv),